@@ -0,0 +1,145 @@
+//! Compares the collision and line clear checks of `BitBoard` with the ones
+//! on `Vec<Vec<u8>>` maps, on maps from games played by the bot.
+
+#[macro_use]
+extern crate bencher;
+extern crate tetris;
+
+use bencher::{black_box, Bencher};
+
+use tetris::bitboard::{BitBoard, PieceMasks};
+use tetris::bot::{self, Bot, Weights};
+use tetris::game::{Tetrimino, Tetris};
+
+/// Maps after every 10 pieces of a few games, with the piece to place.
+fn positions() -> Vec<(Vec<Vec<u8>>, Tetrimino)> {
+    let bot = Bot::new(Weights::default());
+    let mut positions = Vec::new();
+    for seed in 0..4 {
+        let mut tetris = Tetris::with_seed(seed);
+        for _ in 0..20 {
+            if bot.play(&mut tetris, 10) < 10 || !tetris.spawn() {
+                break;
+            }
+            if let Some(ref piece) = tetris.current_piece {
+                positions.push((tetris.game_map.clone(), piece.clone()));
+            }
+            tetris.current_piece = None;
+        }
+    }
+    positions
+}
+
+/// Every position of every state in the map, and a bit around it.
+fn each_position(game_map: &[Vec<u8>], piece: &Tetrimino, mut f: impl FnMut(usize, isize, usize)) {
+    for state in 0..piece.states.len() {
+        for x in -2..game_map[0].len() as isize {
+            for y in 0..game_map.len() {
+                f(state, x, y);
+            }
+        }
+    }
+}
+
+fn test_position(b: &mut Bencher) {
+    let positions = positions();
+    b.iter(|| {
+        let mut nb = 0;
+        for (game_map, piece) in &positions {
+            each_position(game_map, piece, |state, x, y| {
+                nb += piece.test_position(game_map, state, x, y) as u32;
+            });
+        }
+        black_box(nb)
+    });
+}
+
+fn bitboard_fits(b: &mut Bencher) {
+    let positions = positions()
+        .into_iter()
+        .map(|(game_map, piece)| {
+            let board = BitBoard::from_map(&game_map);
+            (game_map, piece.clone(), board, PieceMasks::new(&piece))
+        })
+        .collect::<Vec<_>>();
+    b.iter(|| {
+        let mut nb = 0;
+        for (game_map, piece, board, masks) in &positions {
+            each_position(game_map, piece, |state, x, y| {
+                nb += board.fits(masks, (state as u8, x, y)) as u32;
+            });
+        }
+        black_box(nb)
+    });
+}
+
+/// Every placement of the piece, locked with the lines cleared.
+fn lock_piece(b: &mut Bencher) {
+    let positions = positions();
+    b.iter(|| {
+        let mut cleared = 0;
+        for (game_map, piece) in &positions {
+            let board = BitBoard::from_map(game_map);
+            let masks = PieceMasks::new(piece);
+            each_position(game_map, piece, |state, x, y| {
+                let position = (state as u8, x, y);
+                if board.fits(&masks, position) {
+                    let mut piece = piece.clone();
+                    piece.current_state = state as u8;
+                    piece.x = x;
+                    piece.y = y;
+                    cleared += bot::lock_piece(game_map, &piece).1;
+                }
+            });
+        }
+        black_box(cleared)
+    });
+}
+
+fn bitboard_lock(b: &mut Bencher) {
+    let positions = positions();
+    b.iter(|| {
+        let mut cleared = 0;
+        for (game_map, piece) in &positions {
+            let board = BitBoard::from_map(game_map);
+            let masks = PieceMasks::new(piece);
+            each_position(game_map, piece, |state, x, y| {
+                let position = (state as u8, x, y);
+                if board.fits(&masks, position) {
+                    cleared += board.clone().lock(&masks, position);
+                }
+            });
+        }
+        black_box(cleared)
+    });
+}
+
+fn placements(b: &mut Bencher) {
+    let positions = positions();
+    let games = positions
+        .into_iter()
+        .map(|(game_map, piece)| {
+            let mut tetris = Tetris::with_seed(0);
+            tetris.game_map = game_map;
+            tetris.current_piece = Some(piece);
+            tetris
+        })
+        .collect::<Vec<_>>();
+    b.iter(|| {
+        let mut nb = 0;
+        for tetris in &games {
+            nb += bot::placements(tetris).len();
+        }
+        black_box(nb)
+    });
+}
+
+benchmark_group!(
+    benches,
+    test_position,
+    bitboard_fits,
+    lock_piece,
+    bitboard_lock,
+    placements
+);
+benchmark_main!(benches);
@@ -0,0 +1,378 @@
+//! Sound effects and background music, played through SDL2_mixer when the
+//! `audio` feature is enabled.
+//!
+//! The sounds are read from `assets/sounds/<name>.wav` and the music from
+//! `assets/music.ogg` when those files exist, otherwise they are synthesized
+//! at startup. Without the feature, or without a usable audio device (e.g.
+//! with `SDL_AUDIODRIVER=dummy`), everything silently does nothing.
+
+use tetris::game::Action;
+use tetris::session::TickOutcome;
+
+#[cfg_attr(not(feature = "audio"), allow(dead_code))]
+pub const SOUNDS_DIRECTORY: &str = "assets/sounds";
+#[cfg_attr(not(feature = "audio"), allow(dead_code))]
+pub const MUSIC_FILE: &str = "assets/music.ogg";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sound {
+    Move,
+    Rotate,
+    Lock,
+    /// Gets longer with the number of cleared lines.
+    LineClear(u32),
+    LevelUp,
+    Hold,
+    GameOver,
+}
+
+impl Sound {
+    /// Name of the file overriding the sound, without its extension.
+    #[cfg_attr(not(feature = "audio"), allow(dead_code))]
+    pub fn name(self) -> String {
+        match self {
+            Sound::Move => "move".to_owned(),
+            Sound::Rotate => "rotate".to_owned(),
+            Sound::Lock => "lock".to_owned(),
+            Sound::LineClear(lines) => format!("clear{}", lines.clamp(1, 4)),
+            Sound::LevelUp => "level_up".to_owned(),
+            Sound::Hold => "hold".to_owned(),
+            Sound::GameOver => "game_over".to_owned(),
+        }
+    }
+
+    #[cfg_attr(not(feature = "audio"), allow(dead_code))]
+    const ALL: [Sound; 10] = [
+        Sound::Move,
+        Sound::Rotate,
+        Sound::Lock,
+        Sound::LineClear(1),
+        Sound::LineClear(2),
+        Sound::LineClear(3),
+        Sound::LineClear(4),
+        Sound::LevelUp,
+        Sound::Hold,
+        Sound::GameOver,
+    ];
+
+    /// The sounds of a tick: the moves, rotations and holds, then the
+    /// locks and line clears, then the level up.
+    pub fn for_tick(outcome: &TickOutcome) -> Vec<Sound> {
+        let mut sounds = outcome
+            .moves
+            .iter()
+            .filter_map(|(_, action)| match action {
+                Action::Left | Action::Right => Some(Sound::Move),
+                Action::Rotate => Some(Sound::Rotate),
+                Action::Hold => Some(Sound::Hold),
+                Action::SoftDrop | Action::HardDrop => None,
+            })
+            .collect::<Vec<_>>();
+        for (_, cleared) in &outcome.locks {
+            sounds.push(match *cleared {
+                0 => Sound::Lock,
+                lines => Sound::LineClear(lines),
+            });
+        }
+        if outcome.level_up {
+            sounds.push(Sound::LevelUp);
+        }
+        sounds
+    }
+}
+
+/// Volumes, from 0 to 100.
+#[derive(Clone, Copy, Debug)]
+pub struct Volumes {
+    pub sfx: u8,
+    pub music: u8,
+}
+
+impl Default for Volumes {
+    fn default() -> Volumes {
+        Volumes { sfx: 80, music: 50 }
+    }
+}
+
+/// Square waves with a short decay, as 16-bit mono samples.
+#[cfg_attr(not(feature = "audio"), allow(dead_code))]
+mod synth {
+    pub const FREQUENCY: i32 = 22_050;
+
+    pub const A4: f32 = 440.;
+    pub const B4: f32 = 493.88;
+    pub const C5: f32 = 523.25;
+    pub const D5: f32 = 587.33;
+    pub const E5: f32 = 659.25;
+    pub const F5: f32 = 698.46;
+    pub const G5: f32 = 783.99;
+    pub const A5: f32 = 880.;
+    pub const C6: f32 = 1046.5;
+    pub const REST: f32 = 0.;
+
+    /// Appends a note of `seconds` to `samples`. A frequency of 0 is a rest.
+    pub fn note(samples: &mut Vec<i16>, frequency: f32, seconds: f32, amplitude: f32) {
+        let length = (seconds * FREQUENCY as f32) as usize;
+        for i in 0..length {
+            let value = if frequency <= 0. {
+                0.
+            } else {
+                let phase = i as f32 * frequency / FREQUENCY as f32;
+                let square = if phase.fract() < 0.5 { 1. } else { -1. };
+                let decay = 1. - i as f32 / length as f32;
+                square * decay * amplitude
+            };
+            samples.push((value * i16::MAX as f32) as i16);
+        }
+    }
+
+    pub fn notes(notes: &[(f32, f32)], amplitude: f32) -> Vec<i16> {
+        let mut samples = Vec::new();
+        for &(frequency, seconds) in notes {
+            note(&mut samples, frequency, seconds, amplitude);
+        }
+        samples
+    }
+
+    /// A WAV file holding `samples`, so that SDL2_mixer can load it.
+    pub fn wav(samples: &[i16]) -> Vec<u8> {
+        let data_size = samples.len() as u32 * 2;
+        let mut bytes = Vec::with_capacity(44 + data_size as usize);
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data_size).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        // PCM, one channel.
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&(FREQUENCY as u32).to_le_bytes());
+        bytes.extend_from_slice(&(FREQUENCY as u32 * 2).to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_size.to_le_bytes());
+        for sample in samples {
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn sound(sound: super::Sound) -> Vec<i16> {
+        use super::Sound;
+
+        const CLEAR: [f32; 4] = [C5, E5, G5, C6];
+        match sound {
+            Sound::Move => notes(&[(A5, 0.03)], 0.15),
+            Sound::Rotate => notes(&[(C6, 0.04)], 0.15),
+            Sound::Lock => notes(&[(A4 / 2., 0.08)], 0.3),
+            Sound::LineClear(lines) => {
+                let lines = lines.clamp(1, 4) as usize;
+                let melody = CLEAR[..lines]
+                    .iter()
+                    .map(|frequency| (*frequency, 0.07))
+                    .collect::<Vec<_>>();
+                notes(&melody, 0.3)
+            }
+            Sound::LevelUp => notes(
+                &[
+                    (C5, 0.06),
+                    (E5, 0.06),
+                    (G5, 0.06),
+                    (C6, 0.06),
+                    (G5, 0.06),
+                    (C6, 0.15),
+                ],
+                0.3,
+            ),
+            Sound::Hold => notes(&[(E5, 0.04), (A5, 0.04)], 0.2),
+            Sound::GameOver => notes(&[(E5, 0.2), (C5, 0.2), (A4, 0.2), (A4 / 2., 0.5)], 0.3),
+        }
+    }
+
+    /// The first part of Korobeiniki, in beats.
+    const MELODY: [(f32, f32); 40] = [
+        (E5, 1.),
+        (B4, 0.5),
+        (C5, 0.5),
+        (D5, 1.),
+        (C5, 0.5),
+        (B4, 0.5),
+        (A4, 1.),
+        (A4, 0.5),
+        (C5, 0.5),
+        (E5, 1.),
+        (D5, 0.5),
+        (C5, 0.5),
+        (B4, 1.5),
+        (C5, 0.5),
+        (D5, 1.),
+        (E5, 1.),
+        (C5, 1.),
+        (A4, 1.),
+        (A4, 1.),
+        (REST, 1.),
+        (REST, 0.5),
+        (D5, 1.),
+        (F5, 0.5),
+        (A5, 1.),
+        (G5, 0.5),
+        (F5, 0.5),
+        (E5, 1.5),
+        (C5, 0.5),
+        (E5, 1.),
+        (D5, 0.5),
+        (C5, 0.5),
+        (B4, 1.),
+        (B4, 0.5),
+        (C5, 0.5),
+        (D5, 1.),
+        (E5, 1.),
+        (C5, 1.),
+        (A4, 1.),
+        (A4, 1.),
+        (REST, 1.5),
+    ];
+
+    pub fn music() -> Vec<i16> {
+        const BEAT: f32 = 60. / 144.;
+        let melody = MELODY
+            .iter()
+            .map(|&(frequency, beats)| (frequency, beats * BEAT))
+            .collect::<Vec<_>>();
+        notes(&melody, 0.2)
+    }
+}
+
+#[cfg(feature = "audio")]
+mod backend {
+    use super::{synth, Sound, Volumes, MUSIC_FILE, SOUNDS_DIRECTORY};
+
+    use sdl2::mixer::{
+        self, Channel, Chunk, LoaderRWops, Music, Sdl2MixerContext, AUDIO_S16LSB, MAX_VOLUME,
+    };
+    use sdl2::rwops::RWops;
+
+    use std::path::Path;
+
+    /// Channel kept for the synthesized music, so that sound effects never
+    /// cut it.
+    const MUSIC_CHANNEL: i32 = 0;
+
+    enum Background {
+        File(Music<'static>),
+        Synthesized(Chunk),
+    }
+
+    pub struct Audio {
+        _context: Option<Sdl2MixerContext>,
+        sounds: Vec<(Sound, Chunk)>,
+        background: Option<Background>,
+    }
+
+    fn load_chunk(sound: Sound) -> Option<Chunk> {
+        let path = Path::new(SOUNDS_DIRECTORY).join(format!("{}.wav", sound.name()));
+        if path.exists() {
+            if let Ok(chunk) = Chunk::from_file(&path) {
+                return Some(chunk);
+            }
+        }
+        synthesized(&synth::sound(sound))
+    }
+
+    fn synthesized(samples: &[i16]) -> Option<Chunk> {
+        let wav = synth::wav(samples);
+        let chunk = RWops::from_bytes(&wav).ok()?.load_wav().ok();
+        chunk
+    }
+
+    fn volume(volume: u8) -> i32 {
+        i32::from(volume.min(100)) * MAX_VOLUME / 100
+    }
+
+    impl Audio {
+        pub fn new(sdl_context: &sdl2::Sdl, volumes: Volumes) -> Option<Audio> {
+            sdl_context.audio().ok()?;
+            mixer::open_audio(synth::FREQUENCY, AUDIO_S16LSB, 2, 1024).ok()?;
+            // Ogg support is optional, the synthesized music is used without it.
+            let context = mixer::init(mixer::INIT_OGG).ok();
+            mixer::allocate_channels(16);
+            mixer::reserve_channels(1);
+
+            let mut sounds = Vec::new();
+            for sound in Sound::ALL {
+                if let Some(mut chunk) = load_chunk(sound) {
+                    chunk.set_volume(volume(volumes.sfx));
+                    sounds.push((sound, chunk));
+                }
+            }
+
+            let background = if Path::new(MUSIC_FILE).exists() {
+                Music::from_file(MUSIC_FILE).ok().map(Background::File)
+            } else {
+                None
+            };
+            let background = background.or_else(|| {
+                synthesized(&synth::music()).map(|mut chunk| {
+                    chunk.set_volume(volume(volumes.music));
+                    Background::Synthesized(chunk)
+                })
+            });
+            match background {
+                Some(Background::File(ref music)) => {
+                    Music::set_volume(volume(volumes.music));
+                    let _ = music.play(-1);
+                }
+                Some(Background::Synthesized(ref chunk)) => {
+                    let _ = mixer::channel(MUSIC_CHANNEL).play(chunk, -1);
+                }
+                None => {}
+            }
+
+            Some(Audio {
+                _context: context,
+                sounds,
+                background,
+            })
+        }
+
+        pub fn play(&self, sound: Sound) {
+            if let Some((_, chunk)) = self.sounds.iter().find(|(known, _)| *known == sound) {
+                let _ = Channel::all().play(chunk, 0);
+            }
+        }
+
+        pub fn stop_music(&self) {
+            match self.background {
+                Some(Background::File(_)) => Music::halt(),
+                Some(Background::Synthesized(_)) => mixer::channel(MUSIC_CHANNEL).halt(),
+                None => {}
+            }
+        }
+    }
+
+    impl Drop for Audio {
+        fn drop(&mut self) {
+            self.stop_music();
+            Channel::all().halt();
+        }
+    }
+}
+
+#[cfg(not(feature = "audio"))]
+mod backend {
+    use super::{Sound, Volumes};
+
+    pub struct Audio;
+
+    impl Audio {
+        pub fn new(_sdl_context: &sdl2::Sdl, _volumes: Volumes) -> Option<Audio> {
+            None
+        }
+
+        pub fn play(&self, _sound: Sound) {}
+
+        pub fn stop_music(&self) {}
+    }
+}
+
+pub use self::backend::Audio;
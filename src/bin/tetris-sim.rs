@@ -0,0 +1,220 @@
+//! Plays many bot games without any display and prints aggregate statistics.
+//!
+//! Game `i` is seeded with `seed + i`, so the results only depend on the
+//! options, not on the number of threads.
+
+extern crate serde;
+extern crate serde_json;
+extern crate tetris;
+
+use serde::Serialize;
+use tetris::bot::{Bot, Weights, WEIGHTS_FILE};
+use tetris::game::Tetris;
+
+use std::env;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+const USAGE: &str = "Usage: tetris-sim [--games N] [--threads N] [--seed N] \
+                     [--max-pieces N] [--weights FILE] [--format json|csv]";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Json,
+    Csv,
+}
+
+struct Options {
+    games: usize,
+    threads: usize,
+    seed: u64,
+    max_pieces: u32,
+    weights: Weights,
+    format: Format,
+}
+
+impl Options {
+    fn from_args() -> Result<Options, String> {
+        let mut options = Options {
+            games: 100,
+            threads: thread::available_parallelism()
+                .map(|nb| nb.get())
+                .unwrap_or(1),
+            seed: 0,
+            max_pieces: 10_000,
+            weights: Weights::load(WEIGHTS_FILE).unwrap_or_default(),
+            format: Format::Json,
+        };
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for {}", arg))?;
+            let bad_value = || format!("Invalid value for {}: {}", arg, value);
+            match arg.as_str() {
+                "--games" => options.games = value.parse().map_err(|_| bad_value())?,
+                "--threads" => options.threads = value.parse().map_err(|_| bad_value())?,
+                "--seed" => options.seed = value.parse().map_err(|_| bad_value())?,
+                "--max-pieces" => options.max_pieces = value.parse().map_err(|_| bad_value())?,
+                "--weights" => options.weights = Weights::load(&value).ok_or_else(bad_value)?,
+                "--format" => {
+                    options.format = match value.as_str() {
+                        "json" => Format::Json,
+                        "csv" => Format::Csv,
+                        _ => return Err(bad_value()),
+                    }
+                }
+                _ => return Err(format!("Unknown option {}", arg)),
+            }
+        }
+        if options.threads == 0 {
+            return Err("--threads must be at least 1".to_owned());
+        }
+        Ok(options)
+    }
+}
+
+struct GameResult {
+    lines: u32,
+    score: u32,
+    pieces: u32,
+    topped_out: bool,
+}
+
+fn play_game(bot: &Bot, seed: u64, max_pieces: u32) -> GameResult {
+    let mut tetris = Tetris::with_seed(seed);
+    let pieces = bot.play(&mut tetris, max_pieces);
+    GameResult {
+        lines: tetris.nb_lines,
+        score: tetris.score,
+        pieces,
+        topped_out: pieces < max_pieces,
+    }
+}
+
+fn play_games(options: &Options) -> Vec<GameResult> {
+    let next_game = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(options.games));
+
+    thread::scope(|scope| {
+        for _ in 0..options.threads.min(options.games) {
+            scope.spawn(|| {
+                let bot = Bot::new(options.weights.clone());
+                loop {
+                    let game = next_game.fetch_add(1, Ordering::SeqCst);
+                    if game >= options.games {
+                        break;
+                    }
+                    let seed = options.seed.wrapping_add(game as u64);
+                    let result = play_game(&bot, seed, options.max_pieces);
+                    results
+                        .lock()
+                        .expect("A simulation thread panicked")
+                        .push((game, result));
+                }
+            });
+        }
+    });
+
+    let mut results = results.into_inner().expect("A simulation thread panicked");
+    results.sort_by_key(|(game, _)| *game);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+#[derive(Serialize)]
+struct Summary {
+    mean: f64,
+    median: f64,
+}
+
+/// What `--format json` prints.
+#[derive(Serialize)]
+struct Report {
+    games: usize,
+    seed: u64,
+    max_pieces: u32,
+    lines: Summary,
+    score: Summary,
+    pieces: Summary,
+    top_out_rate: f64,
+}
+
+impl Summary {
+    fn new(mut values: Vec<u32>) -> Summary {
+        if values.is_empty() {
+            return Summary {
+                mean: 0.,
+                median: 0.,
+            };
+        }
+        values.sort_unstable();
+        let middle = values.len() / 2;
+        Summary {
+            mean: values.iter().map(|value| *value as f64).sum::<f64>() / values.len() as f64,
+            median: if values.len().is_multiple_of(2) {
+                (values[middle - 1] as f64 + values[middle] as f64) / 2.
+            } else {
+                values[middle] as f64
+            },
+        }
+    }
+}
+
+fn main() {
+    let options = match Options::from_args() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let results = play_games(&options);
+    let lines = Summary::new(results.iter().map(|result| result.lines).collect());
+    let score = Summary::new(results.iter().map(|result| result.score).collect());
+    let pieces = Summary::new(results.iter().map(|result| result.pieces).collect());
+    let top_out_rate = if results.is_empty() {
+        0.
+    } else {
+        results.iter().filter(|result| result.topped_out).count() as f64 / results.len() as f64
+    };
+
+    match options.format {
+        Format::Json => {
+            let report = Report {
+                games: results.len(),
+                seed: options.seed,
+                max_pieces: options.max_pieces,
+                lines,
+                score,
+                pieces,
+                top_out_rate,
+            };
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&report).expect("Reports are always serializable")
+            );
+        }
+        Format::Csv => {
+            println!(
+                "games,seed,max_pieces,mean_lines,median_lines,mean_score,median_score,\
+                 mean_pieces,median_pieces,top_out_rate"
+            );
+            println!(
+                "{},{},{},{:.3},{:.1},{:.3},{:.1},{:.3},{:.1},{:.4}",
+                results.len(),
+                options.seed,
+                options.max_pieces,
+                lines.mean,
+                lines.median,
+                score.mean,
+                score.median,
+                pieces.mean,
+                pieces.median,
+                top_out_rate
+            );
+        }
+    }
+}
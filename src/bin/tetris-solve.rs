@@ -0,0 +1,180 @@
+//! Prints every way a queue of pieces can make a perfect clear, or build an
+//! opener, from a board.
+//!
+//! The search is split by first piece between the threads, and the
+//! solutions are printed in the same order whatever their number.
+
+extern crate tetris;
+
+use tetris::fumen;
+use tetris::game::MAP_HEIGHT;
+use tetris::pieces::PieceSet;
+use tetris::sandbox::Setup;
+use tetris::solver::{self, Goal, Opener, Problem, Step};
+
+use std::env;
+use std::process;
+use std::thread;
+
+const USAGE: &str = "Usage: tetris-solve [--board FILE | --fumen DATA] [--queue PIECES] \
+                     [--hold PIECE] [--pc LINES | --opener NAME] [--pieces NAME|FILE] \
+                     [--threads N] [--format placements|fumen]";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Placements,
+    Fumen,
+}
+
+struct Options {
+    problem: Problem,
+    pieces: PieceSet,
+    threads: usize,
+    format: Format,
+}
+
+/// Piece names separated by spaces, or in a single word if they're all one
+/// letter long, like `TIOLJSZ`.
+fn parse_queue(queue: &str, pieces: &PieceSet) -> Result<Vec<u8>, String> {
+    let names = if queue.trim().contains(char::is_whitespace) {
+        queue
+            .split_whitespace()
+            .map(str::to_owned)
+            .collect::<Vec<_>>()
+    } else {
+        queue.trim().chars().map(|name| name.to_string()).collect()
+    };
+    names
+        .iter()
+        .map(|name| {
+            pieces
+                .kind(name)
+                .ok_or_else(|| format!("Unknown piece {:?}", name))
+        })
+        .collect()
+}
+
+impl Options {
+    fn from_args() -> Result<Options, String> {
+        let mut args = env::args().skip(1).collect::<Vec<_>>();
+        if args.len() % 2 != 0 {
+            return Err(format!("Missing value for {}", args[args.len() - 1]));
+        }
+        // The pieces are needed to read the rest.
+        let pieces = match args.iter().position(|arg| arg == "--pieces") {
+            Some(nb) => {
+                let name = args.remove(nb + 1);
+                args.remove(nb);
+                PieceSet::named_or_load(&name)
+                    .map_err(|e| format!("Couldn't load the pieces {:?}: {}", name, e))?
+            }
+            None => PieceSet::load_or_builtin()?,
+        };
+
+        let mut setup = Setup::new();
+        let mut queue = None;
+        let mut hold = None;
+        let mut goal = Goal::PerfectClear(4);
+        let mut threads = thread::available_parallelism()
+            .map(|nb| nb.get())
+            .unwrap_or(1);
+        let mut format = Format::Placements;
+        for pair in args.chunks(2) {
+            let (arg, value) = (&pair[0], &pair[1]);
+            let bad_value = || format!("Invalid value for {}: {}", arg, value);
+            match arg.as_str() {
+                "--board" => setup = Setup::load(value, &pieces)?,
+                "--fumen" => {
+                    let pages = fumen::decode(value, &pieces)?;
+                    setup = Setup::from_page(&pages, 0);
+                }
+                "--queue" => queue = Some(parse_queue(value, &pieces)?),
+                "--hold" => hold = Some(pieces.kind(value).ok_or_else(bad_value)?),
+                "--pc" => {
+                    let lines = value.parse().map_err(|_| bad_value())?;
+                    if lines == 0 || lines > MAP_HEIGHT {
+                        return Err(bad_value());
+                    }
+                    goal = Goal::PerfectClear(lines);
+                }
+                "--opener" => goal = Goal::Opener(Opener::named(value)?),
+                "--threads" => threads = value.parse().map_err(|_| bad_value())?,
+                "--format" => {
+                    format = match value.as_str() {
+                        "placements" => Format::Placements,
+                        "fumen" => Format::Fumen,
+                        _ => return Err(bad_value()),
+                    }
+                }
+                _ => return Err(format!("Unknown option {}", arg)),
+            }
+        }
+        if threads == 0 {
+            return Err("--threads must be at least 1".to_owned());
+        }
+        // Without a queue, the one of the board.
+        let queue =
+            queue.unwrap_or_else(|| setup.current.iter().chain(&setup.next).cloned().collect());
+        if queue.is_empty() {
+            return Err("No pieces to place, see --queue".to_owned());
+        }
+        Ok(Options {
+            problem: Problem {
+                game_map: setup.game_map,
+                queue,
+                hold: hold.or(setup.hold),
+                goal,
+            },
+            pieces,
+            threads,
+            format,
+        })
+    }
+}
+
+/// A solution as the piece, rotation state and top-left corner of each
+/// placement, `hold` before the ones played by holding.
+fn placements(solution: &[Step], pieces: &PieceSet) -> String {
+    solution
+        .iter()
+        .map(|step| {
+            let (state, x, y) = step.position;
+            format!(
+                "{}{} {} {} {}",
+                if step.hold { "hold " } else { "" },
+                pieces.pieces[step.kind as usize - 1].name,
+                state,
+                x,
+                y
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn main() {
+    let options = match Options::from_args() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(1);
+        }
+    };
+    let solutions = solver::solve(&options.problem, &options.pieces, options.threads);
+    for solution in &solutions {
+        match options.format {
+            Format::Placements => println!("{}", placements(solution, &options.pieces)),
+            Format::Fumen => {
+                let pages = solver::pages(&options.problem, solution, &options.pieces);
+                match fumen::encode(&pages, &options.pieces) {
+                    Ok(fumen) => println!("{}", fumen),
+                    Err(e) => {
+                        eprintln!("Couldn't write a fumen: {}", e);
+                        process::exit(1);
+                    }
+                }
+            }
+        }
+    }
+    eprintln!("{} solutions", solutions.len());
+}
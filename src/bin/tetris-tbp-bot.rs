@@ -0,0 +1,117 @@
+//! The built-in bot behind the external bot protocol, as an example of a bot
+//! program and to test the protocol: `tetris --tbp-bot tetris-tbp-bot`.
+
+extern crate serde_json;
+extern crate tetris;
+
+use tetris::bot::{self, Bot, Weights, WEIGHTS_FILE};
+use tetris::game::{Tetris, GARBAGE};
+use tetris::pieces::PieceSet;
+use tetris::tbp::{piece_kind, placement_location, BotMessage, FrontendMessage, Move};
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+
+/// What the bot knows about the game, rebuilt from the messages.
+#[derive(Default)]
+struct State {
+    game_map: Vec<Vec<u8>>,
+    hold: Option<u8>,
+    queue: VecDeque<u8>,
+}
+
+impl State {
+    fn tetris(&self) -> Option<Tetris> {
+        let mut tetris = Tetris::new();
+        tetris.game_map = self.game_map.clone();
+        tetris.current_piece = Some(tetris.pieces.piece(*self.queue.front()?));
+        tetris.hold_piece = self.hold;
+        Some(tetris)
+    }
+
+    fn play(&mut self, mv: &Move) {
+        let kind = piece_kind(mv.location.piece).unwrap_or(0);
+        let current = self.queue.pop_front();
+        if current != Some(kind) {
+            self.hold = current;
+        }
+        // The protocol only knows the built-in pieces, the game refuses to
+        // let a bot play others.
+        let mut piece = PieceSet::builtin().piece(kind);
+        piece.current_state = mv.location.orientation;
+        piece.x = mv.location.x;
+        piece.y = mv.location.y;
+        self.game_map = bot::lock_piece(&self.game_map, &piece).0;
+    }
+}
+
+fn send(message: &BotMessage) {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let _ = writeln!(
+        stdout,
+        "{}",
+        serde_json::to_string(message).expect("Messages are always serializable")
+    );
+    let _ = stdout.flush();
+}
+
+fn main() {
+    let bot = Bot::new(Weights::load(WEIGHTS_FILE).unwrap_or_default());
+    let mut state = State::default();
+
+    send(&BotMessage::Info {
+        name: "tetris-tbp-bot".to_owned(),
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+        author: "Tetris_RESF".to_owned(),
+        features: Vec::new(),
+    });
+
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let message = match serde_json::from_str::<FrontendMessage>(&line) {
+            Ok(message) => message,
+            // Unknown messages must be ignored.
+            Err(_) => continue,
+        };
+        match message {
+            FrontendMessage::Rules { .. } => send(&BotMessage::Ready),
+            FrontendMessage::Start { hold, queue, board } => {
+                state.hold = hold.and_then(piece_kind);
+                state.queue = queue.into_iter().filter_map(piece_kind).collect();
+                state.game_map = board
+                    .iter()
+                    .map(|line| {
+                        line.iter()
+                            .map(|case| match *case {
+                                Some(name) => piece_kind(name).unwrap_or(GARBAGE),
+                                None => 0,
+                            })
+                            .collect()
+                    })
+                    .collect();
+            }
+            FrontendMessage::NewPiece { piece } => state.queue.extend(piece_kind(piece)),
+            FrontendMessage::Suggest => {
+                let tetris = state.tetris();
+                let moves = tetris
+                    .as_ref()
+                    .and_then(|tetris| {
+                        let placement = bot.best_placement(tetris)?;
+                        placement_location(tetris, &placement)
+                    })
+                    .map(|location| Move { location })
+                    .into_iter()
+                    .collect();
+                send(&BotMessage::Suggestion { moves });
+            }
+            FrontendMessage::Play { mv } => state.play(&mv),
+            FrontendMessage::Stop => state = State::default(),
+            FrontendMessage::Quit => break,
+        }
+    }
+}
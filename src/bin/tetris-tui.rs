@@ -0,0 +1,821 @@
+//! Plays in the terminal instead of an SDL window, for example over SSH.
+//!
+//! It drives the same sessions as the SDL frontend, set up from the same
+//! options: solo, local versus, the built-in and external bots, network
+//! versus, the sandbox and fumen practice, recording, and suspended games.
+//! Cases are drawn two characters wide with ANSI true colours, and the keys
+//! and the mouse are read from the terminal in raw mode.
+
+extern crate libc;
+extern crate tetris;
+
+use tetris::editor::Edit;
+use tetris::effects::Effects;
+use tetris::game::{Tetrimino, Tetris, TICKS_PER_SECOND};
+use tetris::net::NetGame;
+use tetris::options::{self, LocalGame};
+use tetris::save::{self, SAVE_FILE};
+use tetris::session::Keymap;
+use tetris::stats::{self, GameRecord, RECORD_FILE};
+use tetris::theme::{Rgb, Theme};
+use tetris::versus::Versus;
+
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::process;
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// Width of one player's board with its garbage meter and side panel, in
+/// cases.
+const PLAYER_WIDTH: usize = 20;
+const SCREEN_HEIGHT: usize = 22;
+/// The lines telling the sandbox queue and what the last edit did.
+const STATUS_Y: usize = SCREEN_HEIGHT - 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Key {
+    Char(u8),
+    Up,
+    Down,
+    Left,
+    Right,
+    Escape,
+    Delete,
+    PageUp,
+    PageDown,
+    F5,
+    /// The left button, or the right one, pressed or dragged over the
+    /// character `(x, y)` of the terminal, from the top-left corner.
+    Mouse(bool, (usize, usize)),
+}
+
+const fn ctrl(letter: u8) -> Key {
+    Key::Char(letter - b'a' + 1)
+}
+
+const CTRL_C: Key = ctrl(b'c');
+const ENTER: Key = Key::Char(b'\r');
+const BACKSPACE: Key = Key::Char(0x7f);
+
+/// The key of a control sequence, `\x1b[` followed by `parameters` and
+/// `last`.
+fn control_key(parameters: &[u8], last: u8) -> Option<Key> {
+    match (parameters, last) {
+        (b"", b'A') => Some(Key::Up),
+        (b"", b'B') => Some(Key::Down),
+        (b"", b'C') => Some(Key::Right),
+        (b"", b'D') => Some(Key::Left),
+        (b"3", b'~') => Some(Key::Delete),
+        (b"5", b'~') => Some(Key::PageUp),
+        (b"6", b'~') => Some(Key::PageDown),
+        (b"15", b'~') => Some(Key::F5),
+        // The SGR mouse reports, `<button;x;y`, from 1.
+        ([b'<', mouse @ ..], b'M') => {
+            let mouse = std::str::from_utf8(mouse).ok()?;
+            let mut numbers = mouse.split(';').map(|number| number.parse::<usize>());
+            let (button, x, y) = (
+                numbers.next()?.ok()?,
+                numbers.next()?.ok()?,
+                numbers.next()?.ok()?,
+            );
+            // 32 is added while dragging, the wheel and the middle button are
+            // left out.
+            match button & !32 {
+                0 => Some(Key::Mouse(true, (x.checked_sub(1)?, y.checked_sub(1)?))),
+                2 => Some(Key::Mouse(false, (x.checked_sub(1)?, y.checked_sub(1)?))),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Splits what was read from the terminal into keys. Unknown escape
+/// sequences are dropped.
+fn parse_keys(bytes: &[u8]) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut bytes = bytes.iter().copied().peekable();
+    while let Some(byte) = bytes.next() {
+        if byte != 0x1b {
+            keys.push(Key::Char(byte.to_ascii_lowercase()));
+            continue;
+        }
+        match bytes.peek() {
+            Some(b'[') => {
+                bytes.next();
+                let mut parameters = Vec::new();
+                for byte in bytes.by_ref() {
+                    if (0x40..=0x7e).contains(&byte) {
+                        keys.extend(control_key(&parameters, byte));
+                        break;
+                    }
+                    parameters.push(byte);
+                }
+            }
+            Some(b'O') => {
+                bytes.next();
+                keys.extend(bytes.next().and_then(|last| control_key(b"", last)));
+            }
+            _ => keys.push(Key::Escape),
+        }
+    }
+    keys
+}
+
+const SOLO_KEYMAP: Keymap<Key> = Keymap {
+    left: Key::Left,
+    right: Key::Right,
+    soft_drop: Key::Down,
+    rotate: Key::Up,
+    hard_drop: Key::Char(b' '),
+    hold: Key::Char(b'c'),
+};
+
+const VERSUS_KEYMAPS: [Keymap<Key>; 2] = [
+    Keymap {
+        left: Key::Char(b'a'),
+        right: Key::Char(b'd'),
+        soft_drop: Key::Char(b's'),
+        rotate: Key::Char(b'w'),
+        hard_drop: Key::Char(b' '),
+        hold: Key::Char(b'q'),
+    },
+    Keymap {
+        left: Key::Left,
+        right: Key::Right,
+        soft_drop: Key::Down,
+        rotate: Key::Up,
+        hard_drop: ENTER,
+        hold: Key::Char(b'/'),
+    },
+];
+
+/// Raw mode and the alternate screen, until dropped.
+struct Terminal {
+    original: libc::termios,
+}
+
+impl Terminal {
+    /// With `mouse`, the terminal reports the presses and drags of the mouse
+    /// buttons instead of selecting text.
+    fn new(mouse: bool) -> io::Result<Terminal> {
+        // The reads return at once, with whatever keys were pressed.
+        let original = unsafe {
+            let mut original = std::mem::zeroed::<libc::termios>();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut original) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut raw = original;
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+            raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+            raw.c_cc[libc::VMIN] = 0;
+            raw.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            original
+        };
+        let mut stdout = io::stdout();
+        stdout.write_all(b"\x1b[?1049h\x1b[?25l\x1b[2J")?;
+        if mouse {
+            stdout.write_all(b"\x1b[?1002h\x1b[?1006h")?;
+        }
+        stdout.flush()?;
+        Ok(Terminal { original })
+    }
+
+    fn read_keys(&self) -> Vec<Key> {
+        // Room for the reports of a mouse dragged during a whole tick.
+        let mut buffer = [0; 1024];
+        match io::stdin().read(&mut buffer) {
+            Ok(nb_bytes) => parse_keys(&buffer[..nb_bytes]),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(b"\x1b[?1006l\x1b[?1002l\x1b[0m\x1b[?25h\x1b[?1049l");
+        let _ = stdout.flush();
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+/// One case of the screen, drawn as two characters.
+#[derive(Clone, Copy, PartialEq)]
+struct Case {
+    background: Rgb,
+    text: [char; 2],
+}
+
+struct Screen {
+    width: usize,
+    cases: Vec<Case>,
+    foreground: Rgb,
+}
+
+impl Screen {
+    fn new(width: usize, theme: &Theme) -> Screen {
+        Screen {
+            width,
+            cases: vec![
+                Case {
+                    background: theme.background,
+                    text: [' ', ' '],
+                };
+                width * SCREEN_HEIGHT
+            ],
+            foreground: theme.text,
+        }
+    }
+
+    fn fill(&mut self, x: usize, y: usize, background: Rgb) {
+        if x < self.width && y < SCREEN_HEIGHT {
+            self.cases[y * self.width + x].background = background;
+        }
+    }
+
+    fn background(&self, x: usize, y: usize) -> Rgb {
+        match self.cases.get(y * self.width + x) {
+            Some(case) if x < self.width => case.background,
+            _ => self.cases[0].background,
+        }
+    }
+
+    fn text(&mut self, x: usize, y: usize, text: &str) {
+        let chars = text.chars().collect::<Vec<_>>();
+        for (shift_x, pair) in chars.chunks(2).enumerate() {
+            if x + shift_x < self.width && y < SCREEN_HEIGHT {
+                let case = &mut self.cases[y * self.width + x + shift_x];
+                case.text = [pair[0], pair.get(1).copied().unwrap_or(' ')];
+            }
+        }
+    }
+
+    /// The escape sequences drawing the screen from the top-left corner of
+    /// the terminal.
+    fn to_ansi(&self) -> String {
+        let (r, g, b) = self.foreground;
+        let mut output = format!("\x1b[H\x1b[38;2;{};{};{}m", r, g, b);
+        for line in self.cases.chunks(self.width) {
+            let mut background = None;
+            for case in line {
+                if background != Some(case.background) {
+                    let (r, g, b) = case.background;
+                    let _ = write!(output, "\x1b[48;2;{};{};{}m", r, g, b);
+                    background = Some(case.background);
+                }
+                output.extend(case.text);
+            }
+            output.push_str("\x1b[0m\r\n");
+            let _ = write!(output, "\x1b[38;2;{};{};{}m", r, g, b);
+        }
+        output
+    }
+}
+
+/// The cases of a piece in its spawn orientation, without the empty rows
+/// and columns around it.
+fn preview_cases(piece: &Tetrimino) -> Vec<(usize, usize)> {
+    let mut cases = Vec::new();
+    for (y, line) in piece.states[0].iter().enumerate() {
+        for (x, case) in line.iter().enumerate() {
+            if *case != 0 {
+                cases.push((x, y));
+            }
+        }
+    }
+    let min_x = cases.iter().map(|(x, _)| *x).min().unwrap_or(0);
+    let min_y = cases.iter().map(|(_, y)| *y).min().unwrap_or(0);
+    cases
+        .into_iter()
+        .map(|(x, y)| (x - min_x, y - min_y))
+        .collect()
+}
+
+fn draw_preview(screen: &mut Screen, theme: &Theme, piece: &Tetrimino, (x, y): (usize, usize)) {
+    if let Some(color) = theme.block(piece.kind()) {
+        for (shift_x, shift_y) in preview_cases(piece) {
+            screen.fill(x + shift_x, y + shift_y, color);
+        }
+    }
+}
+
+fn draw_piece(screen: &mut Screen, color: Rgb, piece: &Tetrimino, (x, y): (usize, usize)) {
+    for (shift_y, line) in piece.states[piece.current_state as usize]
+        .iter()
+        .enumerate()
+    {
+        for (shift_x, case) in line.iter().enumerate() {
+            let column = piece.x + shift_x as isize;
+            if *case != 0 && column >= 0 {
+                screen.fill(x + column as usize, y + piece.y + shift_y, color);
+            }
+        }
+    }
+}
+
+/// `over` drawn on `under` with the opacity `alpha`.
+fn blend(under: Rgb, over: Rgb, alpha: u8) -> Rgb {
+    let mix = |under: u8, over: u8| {
+        ((under as u32 * (255 - alpha as u32) + over as u32 * alpha as u32) / 255) as u8
+    };
+    (
+        mix(under.0, over.0),
+        mix(under.1, over.1),
+        mix(under.2, over.2),
+    )
+}
+
+/// The line clears, hard drop trails and lock flashes, as in the window.
+/// The particles and the shaking don't fit in cases, they're left out.
+fn draw_effects(screen: &mut Screen, theme: &Theme, effects: &Effects, grid: (usize, usize)) {
+    const WHITE: Rgb = (255, 255, 255);
+    let mut fill = |(x, y): (isize, usize), color: Rgb, alpha: u8| {
+        if x >= 0 {
+            let (x, y) = (grid.0 + x as usize, grid.1 + y);
+            let under = screen.background(x, y);
+            screen.fill(x, y, blend(under, color, alpha));
+        }
+    };
+
+    if let Some(ref line_clear) = effects.line_clear {
+        // The cleared lines flash, then dissolve from the middle outwards.
+        let progress = line_clear.progress();
+        let alpha = (220. * (1. - progress)) as u8;
+        for &row in &line_clear.rows {
+            let middle = line_clear.width as f32 / 2.;
+            for x in 0..line_clear.width {
+                let distance = (x as f32 + 0.5 - middle).abs();
+                if distance < progress * (middle + 0.5) {
+                    fill((x as isize, row), theme.grid, 255);
+                } else {
+                    fill((x as isize, row), WHITE, alpha);
+                }
+            }
+        }
+    }
+    for trail in &effects.trails {
+        let rgb = theme.block(trail.kind).unwrap_or(WHITE);
+        let alpha = Effects::trail_alpha(trail);
+        for &(x, y) in &trail.cases {
+            for step in 1..=trail.length.min(y) {
+                // Fades out with the distance to the piece.
+                let alpha = (alpha as usize * (trail.length + 1 - step) / (trail.length + 1)) as u8;
+                fill((x, y - step), rgb, alpha);
+            }
+        }
+    }
+    for flash in &effects.flashes {
+        let alpha = Effects::flash_alpha(flash);
+        for &case in &flash.cases {
+            fill(case, WHITE, alpha);
+        }
+    }
+}
+
+/// Draws one player's board, with the hold piece, the next pieces and the
+/// HUD, `x` cases from the left of the screen.
+fn draw_player(
+    screen: &mut Screen,
+    theme: &Theme,
+    (tetris, effects): (&Tetris, Option<&Effects>),
+    pending_garbage: u32,
+    x: usize,
+) {
+    let (width, height) = (tetris.game_map[0].len(), tetris.game_map.len());
+    let grid = (x + 2, 1);
+    for y in 0..height + 2 {
+        screen.fill(grid.0 - 1, y, theme.border);
+        screen.fill(grid.0 + width, y, theme.border);
+    }
+    for shift_x in 0..width {
+        screen.fill(grid.0 + shift_x, 0, theme.border);
+        screen.fill(grid.0 + shift_x, height + 1, theme.border);
+    }
+    for (y, line) in tetris.game_map.iter().enumerate() {
+        for (shift_x, case) in line.iter().enumerate() {
+            let color = theme.block(*case).unwrap_or(theme.grid);
+            screen.fill(grid.0 + shift_x, grid.1 + y, color);
+        }
+    }
+
+    // The garbage meter grows from the bottom of the grid, on its left.
+    for y in 0..(pending_garbage as usize).min(height) {
+        screen.fill(x, grid.1 + height - 1 - y, theme.border);
+    }
+
+    if let Some(ref piece) = tetris.current_piece {
+        // The ghost shows where the piece would land.
+        let mut ghost = piece.clone();
+        while ghost.change_position(&tetris.game_map, ghost.x, ghost.y + 1) {}
+        draw_piece(screen, theme.ghost, &ghost, grid);
+        if let Some(color) = theme.block(piece.kind()) {
+            draw_piece(screen, color, piece, grid);
+        }
+    }
+    if let Some(effects) = effects {
+        draw_effects(screen, theme, effects, grid);
+    }
+
+    let panel = grid.0 + width + 2;
+    screen.text(panel, 1, "HOLD");
+    if let Some(kind) = tetris.hold_piece {
+        draw_preview(screen, theme, &tetris.pieces.piece(kind), (panel, 2));
+    }
+    screen.text(panel, 5, "NEXT");
+    // As many as fit, a line apart.
+    let mut y = 6;
+    for kind in &tetris.next_pieces {
+        let piece = tetris.pieces.piece(*kind);
+        draw_preview(screen, theme, &piece, (panel, y));
+        y += preview_cases(&piece)
+            .iter()
+            .map(|(_, shift_y)| shift_y + 2)
+            .max()
+            .unwrap_or(0);
+    }
+
+    let hud = height + 2;
+    screen.text(x + 1, hud, &format!("Score {}", tetris.score));
+    screen.text(x + 1, hud + 1, &format!("Lines {}", tetris.nb_lines));
+    screen.text(x + 7, hud + 1, &format!("Level {}", tetris.current_level));
+}
+
+/// Where the boards are drawn. The board of the first player is the one
+/// the mouse edits.
+const GRID: (usize, usize) = (2, 1);
+
+fn draw(
+    theme: &Theme,
+    games: &[&Tetris],
+    versus: Option<&Versus>,
+    effects: &[Effects],
+    status: &[&str],
+) -> io::Result<()> {
+    let mut screen = Screen::new(PLAYER_WIDTH * games.len(), theme);
+    for (nb, tetris) in games.iter().enumerate() {
+        let pending_garbage = match versus {
+            Some(versus) => versus.meters[nb].total(),
+            None => 0,
+        };
+        draw_player(
+            &mut screen,
+            theme,
+            (tetris, effects.get(nb)),
+            pending_garbage,
+            nb * PLAYER_WIDTH,
+        );
+    }
+    for (y, line) in status.iter().enumerate() {
+        screen.text(0, STATUS_Y + y, line);
+    }
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(screen.to_ansi().as_bytes())?;
+    stdout.flush()
+}
+
+/// The case of the first player's map under the character `(x, y)` of the
+/// terminal.
+fn case_at(tetris: &Tetris, (x, y): (usize, usize)) -> Option<(usize, usize)> {
+    let x = (x / 2).checked_sub(GRID.0)?;
+    let y = y.checked_sub(GRID.1)?;
+    if x < tetris.game_map[0].len() && y < tetris.game_map.len() {
+        Some((x, y))
+    } else {
+        None
+    }
+}
+
+/// The edit of the sandbox `key` asks for: the same keys as in the window,
+/// with the shifted digits of a US keyboard adding the next pieces.
+fn edit_from_key(key: Key, tetris: &Tetris) -> Option<Edit> {
+    const SHIFTED_DIGITS: &[u8] = b"!@#$%^&*(";
+    match key {
+        Key::Mouse(true, position) => Some(Edit::Fill(case_at(tetris, position)?)),
+        Key::Mouse(false, position) => Some(Edit::Empty(case_at(tetris, position)?)),
+        Key::Delete => Some(Edit::Clear),
+        Key::PageDown => Some(Edit::NextPage),
+        Key::PageUp => Some(Edit::PreviousPage),
+        BACKSPACE => Some(Edit::Unpick),
+        key if key == ctrl(b'z') => Some(Edit::Undo),
+        key if key == ctrl(b'y') => Some(Edit::Redo),
+        key if key == ctrl(b's') => Some(Edit::Save),
+        key if key == ctrl(b'r') => Some(Edit::Reload),
+        key if key == ctrl(b'e') => Some(Edit::Export),
+        Key::Char(digit @ b'1'..=b'9') => Some(Edit::Current(digit - b'0')),
+        Key::Char(shifted) => {
+            let kind = SHIFTED_DIGITS.iter().position(|digit| *digit == shifted)?;
+            Some(Edit::Next(kind as u8 + 1))
+        }
+        _ => None,
+    }
+}
+
+/// The heading of each player's game over summary.
+fn game_over_headings(nb_players: usize, loser: Option<usize>) -> Vec<String> {
+    if nb_players == 1 {
+        return vec!["GAME OVER".to_owned()];
+    }
+    (0..nb_players)
+        .map(|nb| match loser {
+            Some(loser) if loser != nb => format!("PLAYER {} WINS", nb + 1),
+            _ => format!("PLAYER {}", nb + 1),
+        })
+        .collect()
+}
+
+/// Shows the summaries of the games ended until a key is pressed. The keys
+/// pressed in the first half second are left out, as they were meant for
+/// the game.
+fn show_game_over(terminal: &Terminal, theme: &Theme, games: &[&Tetris], loser: Option<usize>) {
+    let mut screen = Screen::new(PLAYER_WIDTH * games.len(), theme);
+    let headings = game_over_headings(games.len(), loser);
+    for (nb, (tetris, heading)) in games.iter().zip(&headings).enumerate() {
+        let lines = std::iter::once(heading.clone())
+            .chain(std::iter::once(String::new()))
+            .chain(stats::game_over_lines(tetris));
+        for (y, line) in lines.enumerate() {
+            screen.text(nb * PLAYER_WIDTH + 1, y, &line);
+        }
+    }
+    let mut stdout = io::stdout().lock();
+    if stdout
+        .write_all(screen.to_ansi().as_bytes())
+        .and_then(|_| stdout.flush())
+        .is_err()
+    {
+        return;
+    }
+    let ignored = Instant::now() + Duration::from_millis(500);
+    loop {
+        let pressed = !terminal.read_keys().is_empty();
+        if pressed && Instant::now() >= ignored {
+            return;
+        }
+        sleep(frame_duration());
+    }
+}
+
+fn print_result(games: &[&Tetris], loser: Option<usize>) {
+    println!("Game over...");
+    for (nb, tetris) in games.iter().enumerate() {
+        println!(
+            "Player {}: {} points, {} lines, level {}{}",
+            nb + 1,
+            tetris.score,
+            tetris.nb_lines,
+            tetris.current_level,
+            match loser {
+                Some(loser) if games.len() > 1 && loser != nb => " [WINNER]",
+                _ => "",
+            }
+        );
+        for line in tetris.statistics.summary(&tetris.pieces) {
+            println!("    {}", line);
+        }
+    }
+    if let [tetris] = games {
+        if let Err(e) = GameRecord::new(tetris).save(RECORD_FILE) {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Commands {
+    quit: bool,
+    suspend: bool,
+}
+
+/// Escape and Ctrl-C quit, F5 suspends, `t` switches to the next theme.
+fn handle_commands(keys: &[Key], themes: &[Theme], theme: &mut usize) -> Commands {
+    let mut commands = Commands::default();
+    for key in keys {
+        match *key {
+            Key::Escape | CTRL_C => commands.quit = true,
+            Key::F5 => commands.suspend = true,
+            Key::Char(b't') => *theme = (*theme + 1) % themes.len(),
+            _ => {}
+        }
+    }
+    commands
+}
+
+fn frame_duration() -> Duration {
+    Duration::new(0, 1_000_000_000 / TICKS_PER_SECOND)
+}
+
+fn play_online(
+    net_game: &mut NetGame,
+    terminal: &Terminal,
+    (themes, theme): (&[Theme], &mut usize),
+) -> Option<usize> {
+    loop {
+        let keys = terminal.read_keys();
+        let quit = handle_commands(&keys, themes, theme).quit;
+        let actions = keys
+            .into_iter()
+            .filter_map(|key| SOLO_KEYMAP.action(key))
+            .collect();
+        let result = if quit {
+            net_game.forfeit()
+        } else {
+            net_game.send_inputs(actions).and_then(|_| net_game.step())
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            return None;
+        }
+        if net_game.loser.is_some() {
+            return net_game.loser;
+        }
+        let games = net_game.players.iter().collect::<Vec<_>>();
+        if let Err(e) = draw(&themes[*theme], &games, Some(&net_game.versus), &[], &[]) {
+            eprintln!("Couldn't draw the game: {}", e);
+            return None;
+        }
+        sleep(frame_duration());
+    }
+}
+
+/// How a local game ended.
+enum Ending {
+    Quit,
+    Lost(usize),
+    Suspended,
+}
+
+/// Runs the local game until someone loses, the players quit or suspend
+/// the game. What the players should be told once the terminal is back to
+/// normal goes to `messages`, the errors as `Err`.
+fn play_local(
+    game: &mut LocalGame,
+    terminal: &Terminal,
+    (themes, theme): (&[Theme], &mut usize),
+    messages: &mut Vec<Result<String, String>>,
+) -> Ending {
+    let keymaps: &[Keymap<Key>] = if game.session.players.len() > 1 {
+        &VERSUS_KEYMAPS
+    } else {
+        std::slice::from_ref(&SOLO_KEYMAP)
+    };
+    let mut message = String::new();
+    loop {
+        let keys = terminal.read_keys();
+        let commands = handle_commands(&keys, themes, theme);
+        let mut actions = Vec::new();
+        for key in keys {
+            if let Some(ref mut editor) = game.editor {
+                let tetris = &mut game.session.players[0].tetris;
+                if let Some(edit) = edit_from_key(key, tetris) {
+                    let result = editor.apply(edit, tetris);
+                    if let Ok(Some(ref told)) | Err(ref told) = result {
+                        message = told.clone();
+                    }
+                    messages.extend(result.transpose());
+                    continue;
+                }
+            }
+            for (nb, keymap) in keymaps.iter().enumerate() {
+                if let Some(action) = keymap.action(key) {
+                    actions.push((nb, action));
+                }
+            }
+        }
+        if let Some(ref mut bot_player) = game.bot_player {
+            if let Err(e) = bot_player.play(&game.session, &mut actions) {
+                messages.push(Err(e.to_string()));
+                return Ending::Lost(bot_player.player);
+            }
+        }
+        game.record(&actions);
+        let outcome = game.session.step(&actions);
+        if let Some(ref mut bot_player) = game.bot_player {
+            bot_player.on_tick(&outcome);
+        }
+        let mut title = String::new();
+        if let Some(ref mut editor) = game.editor {
+            let tetris = &game.session.players[0].tetris;
+            editor.sandbox.after_tick(tetris);
+            title = editor.title(tetris);
+        }
+        if let Some(loser) = outcome.loser {
+            return Ending::Lost(loser);
+        }
+
+        // Only solo games can be suspended.
+        if commands.suspend && game.session.players.len() == 1 && game.editor.is_none() {
+            return Ending::Suspended;
+        }
+        if commands.quit || commands.suspend {
+            return Ending::Quit;
+        }
+
+        if let Err(e) = draw(
+            &themes[*theme],
+            &game.session.games(),
+            game.session.versus.as_ref(),
+            &game.session.effects,
+            &[&title, &message],
+        ) {
+            messages.push(Err(format!("Couldn't draw the game: {}", e)));
+            return Ending::Quit;
+        }
+        sleep(frame_duration());
+    }
+}
+
+fn main() {
+    let pieces = Arc::new(options::load_pieces());
+    let brain = options::load_brain(&pieces);
+    let delays = options::load_delays();
+    let mut net_game = options::connect(&pieces, delays);
+    let pieces = match net_game {
+        Some(ref net_game) => net_game.players[0].pieces.clone(),
+        None => pieces,
+    };
+    let versus_mode = net_game.is_some() || options::has_flag("--versus");
+    let sandbox = options::sandbox_options(versus_mode);
+    // Only solo games can be suspended, the question is asked before the
+    // terminal is taken over.
+    let resumed = if versus_mode || sandbox.is_some() {
+        None
+    } else {
+        options::offer_resume()
+    };
+    let pieces = match resumed {
+        Some(ref tetris) => tetris.pieces.clone(),
+        None => pieces,
+    };
+    let themes = options::load_themes(&pieces);
+
+    let terminal = match Terminal::new(sandbox.is_some()) {
+        Ok(terminal) => terminal,
+        Err(e) => {
+            eprintln!("Couldn't set up the terminal: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let mut theme = 0;
+    if let Some(ref mut net_game) = net_game {
+        let loser = play_online(net_game, &terminal, (&themes, &mut theme));
+        let games = net_game.players.iter().collect::<Vec<_>>();
+        if loser.is_some() {
+            show_game_over(&terminal, &themes[theme], &games, loser);
+        }
+        drop(terminal);
+        print_result(&net_game.players.iter().collect::<Vec<_>>(), loser);
+        return;
+    }
+
+    let nb_players = if versus_mode { 2 } else { 1 };
+    let mut game = LocalGame::new(
+        nb_players,
+        (pieces, delays),
+        resumed,
+        sandbox,
+        brain,
+        options::load_effect_settings(),
+    );
+    let mut messages = Vec::new();
+    let ending = play_local(&mut game, &terminal, (&themes, &mut theme), &mut messages);
+    let games = game.session.games();
+    match ending {
+        Ending::Lost(loser) if game.editor.is_none() => {
+            show_game_over(&terminal, &themes[theme], &games, Some(loser))
+        }
+        Ending::Quit if game.editor.is_none() => {
+            show_game_over(&terminal, &themes[theme], &games, None)
+        }
+        _ => {}
+    }
+    drop(terminal);
+
+    match ending {
+        Ending::Suspended => match save::save(games[0], SAVE_FILE) {
+            Ok(()) => println!("Game suspended, it can be resumed at the next launch"),
+            Err(e) => {
+                eprintln!("Couldn't suspend the game: {}", e);
+                print_result(&games, None);
+            }
+        },
+        Ending::Lost(loser) => print_result(&games, Some(loser)),
+        Ending::Quit if game.editor.is_none() => print_result(&games, None),
+        Ending::Quit => {}
+    }
+    messages.extend(game.save_recording());
+    for message in messages {
+        match message {
+            Ok(message) => println!("{}", message),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
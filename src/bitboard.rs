@@ -0,0 +1,375 @@
+//! A playfield with one bitmask per row, for code testing many positions
+//! like the bot: a collision is a shift, an `and` and a comparison per row of
+//! the piece, and a complete line is a row equal to the full mask. The
+//! values of the cases are kept next to it for the line clears to move them.
+
+use crate::game::{Kicks, Tetrimino};
+
+/// `(state, x, y)` of a piece, as in `Tetrimino`.
+pub type Position = (u8, isize, usize);
+
+/// The rows of every state of a piece, bit `x` for the column `x` of its
+/// box, which can't be wider than 64 cases.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PieceMasks {
+    states: Vec<Vec<u64>>,
+    kicks: Kicks,
+    kind: u8,
+}
+
+impl PieceMasks {
+    pub fn new(piece: &Tetrimino) -> PieceMasks {
+        let states = piece
+            .states
+            .iter()
+            .map(|state| {
+                state
+                    .iter()
+                    .map(|line| {
+                        line.iter()
+                            .enumerate()
+                            .filter(|(_, case)| **case != 0)
+                            .fold(0, |mask, (x, _)| mask | 1 << x)
+                    })
+                    .collect()
+            })
+            .collect();
+        PieceMasks {
+            states,
+            kicks: piece.kicks.clone(),
+            kind: piece.kind(),
+        }
+    }
+
+    pub fn nb_states(&self) -> usize {
+        self.states.len()
+    }
+
+    /// The row of the highest block of the piece at `position`.
+    pub fn top(&self, (state, _, y): Position) -> usize {
+        y + self.states[state as usize]
+            .iter()
+            .position(|mask| *mask != 0)
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BitBoard {
+    /// Bit `x` of a row is set when its case is filled.
+    rows: Vec<u64>,
+    /// Values of the cases, like `Tetris::game_map`, row after row.
+    cases: Vec<u8>,
+    width: usize,
+    full: u64,
+}
+
+impl BitBoard {
+    /// A board like `game_map`, which must be 1 to 64 cases wide.
+    pub fn from_map(game_map: &[Vec<u8>]) -> BitBoard {
+        let width = game_map.first().map_or(0, |line| line.len());
+        assert!(
+            (1..=64).contains(&width),
+            "A bit board is 1 to 64 cases wide, not {}",
+            width
+        );
+        BitBoard {
+            rows: game_map
+                .iter()
+                .map(|line| {
+                    line.iter()
+                        .enumerate()
+                        .filter(|(_, case)| **case != 0)
+                        .fold(0, |row, (x, _)| row | 1 << x)
+                })
+                .collect(),
+            cases: game_map.concat(),
+            width,
+            full: u64::MAX >> (64 - width),
+        }
+    }
+
+    pub fn to_map(&self) -> Vec<Vec<u8>> {
+        self.cases
+            .chunks(self.width)
+            .map(|line| line.to_vec())
+            .collect()
+    }
+
+    pub fn rows(&self) -> &[u64] {
+        &self.rows
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// The same as `Tetrimino::test_position`.
+    pub fn fits(&self, piece: &PieceMasks, (state, x, y): Position) -> bool {
+        for (shift_y, mask) in piece.states[state as usize].iter().enumerate() {
+            if *mask == 0 {
+                continue;
+            }
+            let row = match self.rows.get(y + shift_y) {
+                Some(row) => *row,
+                None => return false,
+            };
+            // Blocks shifted out of the word are out of the map.
+            let shifted = if x >= 0 {
+                if x as u32 > mask.leading_zeros() {
+                    return false;
+                }
+                mask << x
+            } else {
+                if (-x) as u32 > mask.trailing_zeros() {
+                    return false;
+                }
+                mask >> -x
+            };
+            if shifted & !self.full != 0 || shifted & row != 0 {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Where `Tetrimino::rotate` would put the piece.
+    pub fn rotate(&self, piece: &PieceMasks, (state, x, y): Position) -> Position {
+        let next_state = ((state as usize + 1) % piece.states.len()) as u8;
+        let kicks = match piece.kicks.len() {
+            0 => return (state, x, y),
+            nb_lists => &piece.kicks[state as usize % nb_lists],
+        };
+        for &(kick_x, kick_y) in kicks {
+            let kicked_y = y as isize + kick_y;
+            if kicked_y >= 0 && self.fits(piece, (next_state, x + kick_x, kicked_y as usize)) {
+                return (next_state, x + kick_x, kicked_y as usize);
+            }
+        }
+        (state, x, y)
+    }
+
+    /// The position after a hard drop.
+    pub fn drop(&self, piece: &PieceMasks, (state, x, mut y): Position) -> Position {
+        while self.fits(piece, (state, x, y + 1)) {
+            y += 1;
+        }
+        (state, x, y)
+    }
+
+    /// Every position where the piece locks with the moves a player has
+    /// from `start`, searched a row at a time: bit `x + offset` of a word
+    /// stands for the column `x`, so each shift moves the whole row.
+    ///
+    /// `offset` is the furthest a block is from the left of the piece's box,
+    /// and the board can't be wider than `64 - offset` cases, so that the
+    /// box of a piece against the right wall still has its bit.
+    pub fn lock_positions(&self, piece: &PieceMasks, start: Position) -> Vec<Position> {
+        // For boxes sticking out on the left to have their bit.
+        let offset = piece
+            .states
+            .iter()
+            .flatten()
+            .filter(|mask| **mask != 0)
+            .map(|mask| 63 - mask.leading_zeros())
+            .max()
+            .unwrap_or(0);
+        assert!(
+            self.width + offset as usize <= 64,
+            "Lock positions are searched on boards up to {} cases wide for this piece, not {}",
+            64 - offset,
+            self.width
+        );
+        let height = self.rows.len();
+        // The positions each state fits at, row by row, at `state * height
+        // + y` like the ones reached.
+        let mut fits = Vec::with_capacity(piece.states.len() * height);
+        for state in &piece.states {
+            for y in 0..height {
+                let mut fit = u64::MAX;
+                for (shift_y, mask) in state.iter().enumerate() {
+                    if *mask == 0 {
+                        continue;
+                    }
+                    let empty = match self.rows.get(y + shift_y) {
+                        Some(row) => !row & self.full,
+                        None => {
+                            fit = 0;
+                            break;
+                        }
+                    };
+                    let mut blocks = *mask;
+                    while blocks != 0 {
+                        fit &= shift(empty, offset as isize - blocks.trailing_zeros() as isize);
+                        blocks &= blocks - 1;
+                    }
+                }
+                fits.push(fit);
+            }
+        }
+
+        let nb_states = piece.states.len();
+        let mut reached = vec![0u64; nb_states * height];
+        let (state, x, y) = start;
+        let start = state as usize * height + y;
+        match u32::try_from(x + offset as isize) {
+            Ok(bit) if bit < 64 && y < height && fits[start] >> bit & 1 != 0 => {
+                reached[start] = 1 << bit;
+            }
+            _ => return Vec::new(),
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for state in 0..nb_states {
+                for y in 0..height {
+                    let index = state * height + y;
+                    let fit = fits[index];
+                    let mut row = reached[index];
+                    if y > 0 {
+                        row |= reached[index - 1] & fit;
+                    }
+                    loop {
+                        let moved = row | (row << 1 | row >> 1) & fit;
+                        if moved == row {
+                            break;
+                        }
+                        row = moved;
+                    }
+                    if row != reached[index] {
+                        reached[index] = row;
+                        changed = true;
+                    }
+
+                    // Rotating moves those the first kick fits for.
+                    if nb_states < 2 || piece.kicks.is_empty() {
+                        continue;
+                    }
+                    let next_state = (state + 1) % nb_states;
+                    let mut left = row;
+                    for &(kick_x, kick_y) in &piece.kicks[state % piece.kicks.len()] {
+                        let kicked_y = y as isize + kick_y;
+                        if kicked_y < 0 || kicked_y as usize >= height {
+                            continue;
+                        }
+                        let kicked_index = next_state * height + kicked_y as usize;
+                        let kicked = shift(left, kick_x) & fits[kicked_index];
+                        left &= !shift(kicked, -kick_x);
+                        if kicked & !reached[kicked_index] != 0 {
+                            reached[kicked_index] |= kicked;
+                            changed = true;
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut locks = Vec::new();
+        for state in 0..nb_states {
+            for y in 0..height {
+                let index = state * height + y;
+                let below = if y + 1 < height { fits[index + 1] } else { 0 };
+                let mut resting = reached[index] & !below;
+                while resting != 0 {
+                    let x = resting.trailing_zeros() as isize - offset as isize;
+                    locks.push((state as u8, x, y));
+                    resting &= resting - 1;
+                }
+            }
+        }
+        locks
+    }
+
+    /// Writes the piece into the board and clears the complete lines, like
+    /// `Tetris::make_permanent`. Returns the number of cleared lines.
+    pub fn lock(&mut self, piece: &PieceMasks, (state, x, y): Position) -> u32 {
+        for (shift_y, mask) in piece.states[state as usize].iter().enumerate() {
+            let mut blocks = *mask;
+            while blocks != 0 {
+                let case_x = (x + blocks.trailing_zeros() as isize) as usize;
+                self.rows[y + shift_y] |= 1 << case_x;
+                self.cases[(y + shift_y) * self.width + case_x] = piece.kind;
+                blocks &= blocks - 1;
+            }
+        }
+        // Moves the rows that stay down, from the bottom.
+        let mut kept = self.rows.len();
+        for y in (0..self.rows.len()).rev() {
+            if self.rows[y] != self.full {
+                kept -= 1;
+                self.rows[kept] = self.rows[y];
+                self.cases
+                    .copy_within(y * self.width..(y + 1) * self.width, kept * self.width);
+            }
+        }
+        let cleared = kept;
+        self.rows[..cleared].fill(0);
+        self.cases[..cleared * self.width].fill(0);
+        cleared as u32
+    }
+}
+
+/// `bits` shifted left by `by`, or right when it's negative.
+fn shift(bits: u64, by: isize) -> u64 {
+    if by >= 0 {
+        bits.checked_shl(by as u32).unwrap_or(0)
+    } else {
+        bits.checked_shr(-by as u32).unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boards_are_up_to_64_cases_wide() {
+        let mut game_map = vec![vec![0; 64]; 2];
+        game_map[1][63] = 1;
+        let board = BitBoard::from_map(&game_map);
+        assert_eq!(board.rows(), [0, 1 << 63]);
+        assert_eq!(board.to_map(), game_map);
+    }
+
+    /// A piece two cases wide.
+    fn domino() -> PieceMasks {
+        PieceMasks::new(&Tetrimino {
+            states: vec![vec![vec![1, 1]]],
+            x: 0,
+            y: 0,
+            current_state: 0,
+            kicks: vec![vec![(0, 0)]],
+        })
+    }
+
+    #[test]
+    fn lock_positions_reach_the_right_wall() {
+        let board = BitBoard::from_map(&vec![vec![0; 63]; 2]);
+        let locks = board.lock_positions(&domino(), (0, 0, 0));
+        assert_eq!(locks.len(), 62);
+        assert!(locks.contains(&(0, 61, 1)));
+    }
+
+    #[test]
+    #[should_panic(expected = "up to 63 cases wide for this piece, not 64")]
+    fn lock_positions_need_a_bit_for_every_box() {
+        let board = BitBoard::from_map(&vec![vec![0; 64]; 2]);
+        board.lock_positions(&domino(), (0, 0, 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "not 65")]
+    fn wider_boards_are_refused() {
+        BitBoard::from_map(&[vec![0; 65]]);
+    }
+
+    #[test]
+    #[should_panic(expected = "not 0")]
+    fn empty_boards_are_refused() {
+        BitBoard::from_map(&[]);
+    }
+}
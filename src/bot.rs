@@ -0,0 +1,348 @@
+use crate::bitboard::{BitBoard, PieceMasks, Position};
+use crate::game::{Action, Tetrimino, Tetris};
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::Read;
+
+pub const WEIGHTS_FILE: &str = "bot_weights.txt";
+
+/// Coefficients of the heuristic used to rate a board. Negative weights
+/// penalise a feature, positive ones reward it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Weights {
+    pub aggregate_height: f64,
+    pub holes: f64,
+    pub bumpiness: f64,
+    pub lines: f64,
+    pub wells: f64,
+}
+
+impl Default for Weights {
+    fn default() -> Weights {
+        Weights {
+            aggregate_height: -0.510066,
+            holes: -0.35663,
+            bumpiness: -0.184483,
+            lines: 0.760666,
+            wells: -0.1,
+        }
+    }
+}
+
+impl Weights {
+    /// Parses one `name value` pair per line, e.g. `holes -0.35`. Missing
+    /// names keep their default value.
+    pub fn parse(content: &str) -> Option<Weights> {
+        let mut weights = Weights::default();
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let mut words = line.split_whitespace();
+            let (name, value) = (words.next()?, words.next()?.parse::<f64>().ok()?);
+            let weight = match name {
+                "aggregate_height" => &mut weights.aggregate_height,
+                "holes" => &mut weights.holes,
+                "bumpiness" => &mut weights.bumpiness,
+                "lines" => &mut weights.lines,
+                "wells" => &mut weights.wells,
+                _ => return None,
+            };
+            *weight = value;
+        }
+        Some(weights)
+    }
+
+    pub fn load(file_name: &str) -> Option<Weights> {
+        let mut f = File::open(file_name).ok()?;
+        let mut content = String::new();
+        f.read_to_string(&mut content).ok()?;
+        Weights::parse(&content)
+    }
+
+    pub fn evaluate(&self, game_map: &[Vec<u8>], cleared: u32) -> f64 {
+        let features = Features::new(game_map);
+        self.aggregate_height * features.aggregate_height as f64
+            + self.holes * features.holes as f64
+            + self.bumpiness * features.bumpiness as f64
+            + self.lines * cleared as f64
+            + self.wells * features.wells as f64
+    }
+}
+
+/// What the heuristic looks at on a board.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Features {
+    pub aggregate_height: u32,
+    pub holes: u32,
+    pub bumpiness: u32,
+    pub wells: u32,
+}
+
+impl Features {
+    pub fn new(game_map: &[Vec<u8>]) -> Features {
+        let width = game_map.first().map(|line| line.len()).unwrap_or(0);
+        let mut features = Features::default();
+        let mut heights = vec![0; width];
+
+        for (x, height) in heights.iter_mut().enumerate() {
+            let top = game_map.iter().position(|line| line[x] != 0);
+            if let Some(top) = top {
+                *height = (game_map.len() - top) as u32;
+                features.holes += game_map[top..].iter().filter(|line| line[x] == 0).count() as u32;
+            }
+        }
+        features.aggregate_height = heights.iter().sum();
+        for pair in heights.windows(2) {
+            features.bumpiness += (pair[0] as i32 - pair[1] as i32).unsigned_abs();
+        }
+        // Walls count as full columns.
+        let wall = game_map.len() as u32;
+        for x in 0..width {
+            let left = if x == 0 { wall } else { heights[x - 1] };
+            let right = if x + 1 == width { wall } else { heights[x + 1] };
+            features.wells += left.min(right).saturating_sub(heights[x]);
+        }
+        features
+    }
+}
+
+/// A position where the current (or held) piece can be locked, with the
+/// actions leading there from where the piece is now.
+#[derive(Clone, Debug)]
+pub struct Placement {
+    pub hold: bool,
+    pub current_state: u8,
+    pub x: isize,
+    pub y: usize,
+    pub actions: Vec<Action>,
+}
+
+/// Writes `piece` into a copy of `game_map` and clears the complete lines,
+/// like `Tetris::make_permanent` would. Returns the new map and the number of
+/// cleared lines.
+pub fn lock_piece(game_map: &[Vec<u8>], piece: &Tetrimino) -> (Vec<Vec<u8>>, u32) {
+    let mut game_map = game_map.to_vec();
+    for (shift_y, line) in piece.states[piece.current_state as usize]
+        .iter()
+        .enumerate()
+    {
+        for (shift_x, case) in line.iter().enumerate() {
+            if *case != 0 {
+                game_map[piece.y + shift_y][(piece.x + shift_x as isize) as usize] = *case;
+            }
+        }
+    }
+    let height = game_map.len();
+    game_map.retain(|line| line.contains(&0));
+    let cleared = (height - game_map.len()) as u32;
+    let width = game_map.first().map(|line| line.len()).unwrap_or(10);
+    while game_map.len() < height {
+        game_map.insert(0, vec![0; width]);
+    }
+    (game_map, cleared)
+}
+
+/// The positions a piece can reach from `start` with the moves a player
+/// has, searched breadth first, and the lock positions they drop to.
+///
+/// `BitBoard::lock_positions` finds the same lock positions faster, as the
+/// `lock_positions_are_reached_move_by_move` property test checks, but it
+/// moves whole rows of positions at once until nothing changes, so it can't
+/// tell how a position was reached. The bot plays, and matches the moves of
+/// external bots with, the shortest actions to each lock position, which
+/// take the parent of every position this search keeps.
+struct Reachable {
+    /// Each lock position, with the first position found dropping there.
+    locks: Vec<(Position, Position)>,
+    /// The position each one was first reached from, and with which move,
+    /// at its `Cases` index.
+    parents: Vec<Option<(Position, Action)>>,
+    cases: Cases,
+}
+
+/// Indexes of the positions of a piece on a board, for flat tables instead
+/// of hash maps. A piece that fits can't have its box further left than 64
+/// columns, its width.
+#[derive(Clone, Copy)]
+struct Cases {
+    columns: usize,
+    height: usize,
+    len: usize,
+}
+
+impl Cases {
+    fn new(board: &BitBoard, masks: &PieceMasks) -> Cases {
+        let (columns, height) = (board.width() + 64, board.height());
+        Cases {
+            columns,
+            height,
+            len: masks.nb_states() * columns * height,
+        }
+    }
+
+    /// Only for positions where the piece fits.
+    fn index(&self, (state, x, y): Position) -> usize {
+        (state as usize * self.columns + (x + 64) as usize) * self.height + y
+    }
+}
+
+impl Reachable {
+    fn new(board: &BitBoard, masks: &PieceMasks, start: Position) -> Reachable {
+        let cases = Cases::new(board, masks);
+        let mut locks = Vec::new();
+        let mut locked = vec![false; cases.len];
+        let mut parents = vec![None; cases.len];
+        let mut seen = vec![false; cases.len];
+        let mut queue = VecDeque::new();
+
+        seen[cases.index(start)] = true;
+        queue.push_back(start);
+        while let Some(position) = queue.pop_front() {
+            let lock = board.drop(masks, position);
+            if !locked[cases.index(lock)] {
+                locked[cases.index(lock)] = true;
+                locks.push((lock, position));
+            }
+
+            let (state, x, y) = position;
+            for action in [
+                Action::Left,
+                Action::Right,
+                Action::Rotate,
+                Action::SoftDrop,
+            ] {
+                let next = match action {
+                    Action::Left => (state, x - 1, y),
+                    Action::Right => (state, x + 1, y),
+                    Action::SoftDrop => (state, x, y + 1),
+                    _ => board.rotate(masks, position),
+                };
+                let moved = match action {
+                    Action::Rotate => next.0 != state,
+                    _ => board.fits(masks, next),
+                };
+                if moved && !seen[cases.index(next)] {
+                    seen[cases.index(next)] = true;
+                    parents[cases.index(next)] = Some((position, action));
+                    queue.push_back(next);
+                }
+            }
+        }
+        Reachable {
+            locks,
+            parents,
+            cases,
+        }
+    }
+
+    /// The shortest actions moving the piece to `position`.
+    fn actions(&self, mut position: Position) -> Vec<Action> {
+        let mut actions = Vec::new();
+        while let Some((parent, action)) = self.parents[self.cases.index(position)] {
+            actions.push(action);
+            position = parent;
+        }
+        actions.reverse();
+        actions
+    }
+}
+
+/// Every lock position `piece` can reach, on a `BitBoard` of `game_map`,
+/// with the shortest action sequence reaching it.
+fn reachable_placements(game_map: &[Vec<u8>], piece: &Tetrimino, hold: bool) -> Vec<Placement> {
+    let board = BitBoard::from_map(game_map);
+    let masks = PieceMasks::new(piece);
+    let reachable = Reachable::new(&board, &masks, (piece.current_state, piece.x, piece.y));
+    reachable
+        .locks
+        .iter()
+        .map(|&(lock, from)| {
+            let mut actions = if hold { vec![Action::Hold] } else { Vec::new() };
+            actions.extend(reachable.actions(from));
+            actions.push(Action::HardDrop);
+            Placement {
+                hold,
+                current_state: lock.0,
+                x: lock.1,
+                y: lock.2,
+                actions,
+            }
+        })
+        .collect()
+}
+
+/// Every placement of the current piece, and of the held one if holding is
+/// possible right now.
+pub fn placements(tetris: &Tetris) -> Vec<Placement> {
+    let piece = match tetris.current_piece {
+        Some(ref piece) => piece,
+        None => return Vec::new(),
+    };
+    let mut placements = reachable_placements(&tetris.game_map, piece, false);
+    if let (true, Some(held)) = (tetris.can_hold, tetris.hold_piece) {
+        let held = tetris.pieces.piece(held);
+        if held.test_current_position(&tetris.game_map) {
+            placements.extend(reachable_placements(&tetris.game_map, &held, true));
+        }
+    }
+    placements
+}
+
+pub struct Bot {
+    pub weights: Weights,
+}
+
+impl Bot {
+    pub fn new(weights: Weights) -> Bot {
+        Bot { weights }
+    }
+
+    fn rate(&self, tetris: &Tetris, placement: &Placement) -> f64 {
+        let mut piece = if placement.hold {
+            tetris.pieces.piece(tetris.hold_piece.unwrap_or(0))
+        } else {
+            match tetris.current_piece {
+                Some(ref piece) => piece.clone(),
+                None => return f64::MIN,
+            }
+        };
+        piece.current_state = placement.current_state;
+        piece.x = placement.x;
+        piece.y = placement.y;
+        let (game_map, cleared) = lock_piece(&tetris.game_map, &piece);
+        self.weights.evaluate(&game_map, cleared)
+    }
+
+    pub fn best_placement(&self, tetris: &Tetris) -> Option<Placement> {
+        placements(tetris)
+            .into_iter()
+            .map(|placement| (self.rate(tetris, &placement), placement))
+            .fold(
+                None,
+                |best: Option<(f64, Placement)>, (score, placement)| match best {
+                    Some((best_score, _)) if best_score >= score => best,
+                    _ => Some((score, placement)),
+                },
+            )
+            .map(|(_, placement)| placement)
+    }
+
+    /// Plays a whole game without any display, gravity nor delays, placing
+    /// pieces until the game is over or `max_pieces` were placed. Returns the
+    /// number of placed pieces.
+    pub fn play(&self, tetris: &mut Tetris, max_pieces: u32) -> u32 {
+        let mut nb_pieces = 0;
+        tetris.skip_delays();
+        while nb_pieces < max_pieces && tetris.spawn() {
+            let placement = match self.best_placement(tetris) {
+                Some(placement) => placement,
+                None => break,
+            };
+            for action in placement.actions {
+                tetris.apply_action(action);
+            }
+            tetris.skip_delays();
+            nb_pieces += 1;
+        }
+        nb_pieces
+    }
+}
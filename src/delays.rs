@@ -0,0 +1,87 @@
+//! How long the game waits between two pieces, in ticks. Rule sets differ
+//! on it: the default plays the next piece on the tick the last one locked,
+//! as the game always did.
+//!
+//! A delays file has one `name value` pair per line, e.g.:
+//!
+//! ```text
+//! entry 10
+//! line_clear 20
+//! ```
+
+use serde::{Deserialize, Serialize};
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+pub const DELAYS_FILE: &str = "delays.txt";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Delays {
+    /// The entry delay, or ARE: ticks between a lock, or the end of its line
+    /// clear, and the next piece.
+    pub entry: u32,
+    /// Ticks the complete lines stay on the map before they're removed.
+    pub line_clear: u32,
+}
+
+impl Delays {
+    pub fn parse(content: &str) -> Result<Delays, String> {
+        let mut delays = Delays::default();
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let mut words = line.split_whitespace();
+            let (name, value) = match (words.next(), words.next(), words.next()) {
+                (Some(name), Some(value), None) => (name, value),
+                _ => return Err(format!("{:?} isn't a name and a value", line)),
+            };
+            let delay = match name {
+                "entry" => &mut delays.entry,
+                "line_clear" => &mut delays.line_clear,
+                _ => return Err(format!("unknown delay {:?}", name)),
+            };
+            *delay = value
+                .parse()
+                .map_err(|_| format!("{:?} isn't a number of ticks", value))?;
+        }
+        Ok(delays)
+    }
+
+    /// Delays by name: `none`, the default, `classic`, close to the older
+    /// console games, or `modern`, with a short entry delay.
+    pub fn named(name: &str) -> Option<Delays> {
+        let (entry, line_clear) = match name {
+            "none" => (0, 0),
+            "classic" => (10, 20),
+            "modern" => (6, 12),
+            _ => return None,
+        };
+        Some(Delays { entry, line_clear })
+    }
+
+    /// The delays named `name`, see `Delays::named`, or those of the file
+    /// `name`.
+    pub fn named_or_load(name: &str) -> Result<Delays, String> {
+        match Delays::named(name) {
+            Some(delays) => Ok(delays),
+            None => Delays::load(name),
+        }
+    }
+
+    pub fn load(file_name: &str) -> Result<Delays, String> {
+        let mut f = File::open(file_name).map_err(|e| format!("{}: {}", file_name, e))?;
+        let mut content = String::new();
+        f.read_to_string(&mut content)
+            .map_err(|e| format!("{}: {}", file_name, e))?;
+        Delays::parse(&content).map_err(|e| format!("{}: {}", file_name, e))
+    }
+
+    /// `DELAYS_FILE` if there is one, no delays otherwise.
+    pub fn load_or_default() -> Result<Delays, String> {
+        if Path::new(DELAYS_FILE).exists() {
+            Delays::load(DELAYS_FILE)
+        } else {
+            Ok(Delays::default())
+        }
+    }
+}
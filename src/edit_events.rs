@@ -0,0 +1,81 @@
+//! The controls of the sandbox in the window: the mouse fills and empties
+//! cases, digits pick the pieces, and Ctrl with a letter undoes, redoes,
+//! saves, reloads or exports to a fumen. Page Up and Page Down go through
+//! the pages of a fumen.
+
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, LCTRLMOD, LSHIFTMOD, RCTRLMOD, RSHIFTMOD};
+use sdl2::mouse::MouseButton;
+
+use tetris::editor::Edit;
+
+/// Gives the case of the map under a point of the window, if there's one.
+pub type CaseAt<'a> = dyn Fn((i32, i32)) -> Option<(usize, usize)> + 'a;
+
+/// The edit `event` asks for. `case_at` gives the case of the map under a
+/// point of the window, if there's one.
+pub fn edit_from_event(
+    event: &Event,
+    case_at: impl Fn((i32, i32)) -> Option<(usize, usize)>,
+) -> Option<Edit> {
+    match *event {
+        Event::MouseButtonDown {
+            mouse_btn, x, y, ..
+        } => match mouse_btn {
+            MouseButton::Left => Some(Edit::Fill(case_at((x, y))?)),
+            MouseButton::Right => Some(Edit::Empty(case_at((x, y))?)),
+            _ => None,
+        },
+        Event::MouseMotion {
+            mousestate, x, y, ..
+        } => {
+            if mousestate.left() {
+                Some(Edit::Fill(case_at((x, y))?))
+            } else if mousestate.right() {
+                Some(Edit::Empty(case_at((x, y))?))
+            } else {
+                None
+            }
+        }
+        Event::KeyDown {
+            keycode: Some(keycode),
+            keymod,
+            ..
+        } => {
+            let ctrl = keymod.intersects(LCTRLMOD | RCTRLMOD);
+            let shift = keymod.intersects(LSHIFTMOD | RSHIFTMOD);
+            let digits = [
+                Keycode::Num1,
+                Keycode::Num2,
+                Keycode::Num3,
+                Keycode::Num4,
+                Keycode::Num5,
+                Keycode::Num6,
+                Keycode::Num7,
+                Keycode::Num8,
+                Keycode::Num9,
+            ];
+            match keycode {
+                Keycode::Z if ctrl && shift => Some(Edit::Redo),
+                Keycode::Z if ctrl => Some(Edit::Undo),
+                Keycode::Y if ctrl => Some(Edit::Redo),
+                Keycode::S if ctrl => Some(Edit::Save),
+                Keycode::R if ctrl => Some(Edit::Reload),
+                Keycode::E if ctrl => Some(Edit::Export),
+                Keycode::PageDown => Some(Edit::NextPage),
+                Keycode::PageUp => Some(Edit::PreviousPage),
+                Keycode::Backspace => Some(Edit::Unpick),
+                Keycode::Delete => Some(Edit::Clear),
+                keycode => {
+                    let kind = digits.iter().position(|digit| *digit == keycode)? as u8 + 1;
+                    Some(if shift {
+                        Edit::Next(kind)
+                    } else {
+                        Edit::Current(kind)
+                    })
+                }
+            }
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,144 @@
+//! The edits of a sandbox game: cases filled and emptied, pieces picked,
+//! placements undone and redone, the setup saved, reloaded or exported to a
+//! fumen, and the pages of a fumen gone through. The frontends turn their
+//! own controls into edits.
+
+use crate::fumen;
+use crate::game::{Tetris, GARBAGE};
+use crate::sandbox::{Sandbox, Setup};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edit {
+    /// Fills the case `(x, y)` of the map.
+    Fill((usize, usize)),
+    Empty((usize, usize)),
+    /// Replaces the current piece with one of a kind.
+    Current(u8),
+    /// Adds a kind after the picked next pieces.
+    Next(u8),
+    Unpick,
+    Clear,
+    Undo,
+    Redo,
+    Save,
+    Reload,
+    /// Gives the fumen of the board and current piece.
+    Export,
+    /// Starts again from a page of the practiced fumen, the next one or the
+    /// previous one.
+    NextPage,
+    PreviousPage,
+}
+
+/// A sandbox game and the file its setup goes to.
+pub struct Editor {
+    pub sandbox: Sandbox,
+    pub file_name: String,
+}
+
+impl Editor {
+    /// Applies `edit` to `tetris`. Returns what the player should be told,
+    /// if anything, or why it couldn't be done.
+    pub fn apply(&mut self, edit: Edit, tetris: &mut Tetris) -> Result<Option<String>, String> {
+        let is_kind = |kind: u8| kind as usize <= tetris.pieces.len();
+        match edit {
+            Edit::Fill(case) => {
+                self.sandbox.paint(tetris, case, GARBAGE);
+            }
+            Edit::Empty(case) => {
+                self.sandbox.paint(tetris, case, 0);
+            }
+            Edit::Current(kind) if is_kind(kind) => {
+                self.sandbox.set_current(tetris, kind);
+            }
+            Edit::Next(kind) if is_kind(kind) => self.sandbox.pick_next(tetris, kind),
+            Edit::Unpick => self.sandbox.unpick(tetris),
+            Edit::Clear => self.sandbox.clear(tetris),
+            Edit::Undo => {
+                tetris.undo();
+            }
+            Edit::Redo => {
+                tetris.redo();
+            }
+            Edit::Save => {
+                let setup = self.sandbox.setup(tetris);
+                setup
+                    .save(&self.file_name, &tetris.pieces)
+                    .map_err(|e| format!("Couldn't save the setup: {}", e))?;
+                return Ok(Some(format!("Setup saved to {}", self.file_name)));
+            }
+            Edit::Reload => {
+                let (sandbox, game) = Setup::load(&self.file_name, &tetris.pieces)
+                    .and_then(|setup| {
+                        let mut game = setup.game(rand::random(), tetris.pieces.clone())?;
+                        Ok((Sandbox::new(&mut game, &setup), game))
+                    })
+                    .map_err(|e| format!("Couldn't load the setup: {}", e))?;
+                self.sandbox = sandbox;
+                *tetris = game;
+            }
+            Edit::Export => {
+                return fumen::from_state(&tetris.state())
+                    .map(Some)
+                    .map_err(|e| format!("Couldn't export the board: {}", e))
+            }
+            Edit::NextPage | Edit::PreviousPage => {
+                let practice = match self.sandbox.practice {
+                    Some(ref practice) => practice,
+                    None => return Ok(None),
+                };
+                let page = match edit {
+                    Edit::NextPage => practice.page() + 1,
+                    _ => practice.page().saturating_sub(1),
+                };
+                let page = page.min(practice.pages.len() - 1);
+                let pages = practice.pages.clone();
+                let (sandbox, game) =
+                    Sandbox::practice(pages, page, rand::random(), tetris.pieces.clone())
+                        .map_err(|e| format!("Couldn't go to page {}: {}", page + 1, e))?;
+                self.sandbox = sandbox;
+                *tetris = game;
+            }
+            Edit::Current(_) | Edit::Next(_) => {}
+        }
+        Ok(None)
+    }
+
+    /// A line telling the queue, with the picked pieces in brackets, and
+    /// where the practice of a fumen is.
+    pub fn title(&self, tetris: &Tetris) -> String {
+        let picked = self.sandbox.picked(tetris);
+        let names = tetris
+            .next_pieces
+            .iter()
+            .chain(&tetris.queued)
+            .enumerate()
+            .map(|(nb, kind)| {
+                let name = &tetris.pieces.pieces[*kind as usize - 1].name;
+                // The picked pieces are told apart from the random ones.
+                if nb < picked {
+                    format!("[{}]", name)
+                } else {
+                    name.clone()
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut title = format!("Tetris sandbox - next {}", names.join(" "));
+        if let Some(ref practice) = self.sandbox.practice {
+            let page = practice.page();
+            match practice.pages.get(page) {
+                Some(page_shown) => {
+                    title += &format!(" - page {}/{}", page + 1, practice.pages.len());
+                    if !page_shown.comment.is_empty() {
+                        title += &format!(" {:?}", page_shown.comment);
+                    }
+                }
+                None => title += " - all pages played",
+            }
+            if practice.last_result() == Some(false) {
+                title += " - missed, Ctrl-Z to retry";
+            }
+        }
+        title
+    }
+}
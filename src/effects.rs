@@ -0,0 +1,326 @@
+//! Animations drawn on top of the game: line clears, hard-drop trails, lock
+//! flashes, particles and screen shake. They only change what's shown: the
+//! line clear animation plays while the game's `Phase::LineClear` lasts.
+
+use rand::{self, Rng};
+
+use crate::game::{Phase, Tetrimino, Tetris};
+
+use std::fs::File;
+use std::io::Read;
+
+pub const EFFECTS_FILE: &str = "effects.txt";
+
+const TRAIL_FRAMES: u32 = 12;
+const FLASH_FRAMES: u32 = 8;
+const SHAKE_FRAMES: u32 = 15;
+const PARTICLE_FRAMES: u32 = 45;
+const PARTICLES_PER_LINE: usize = 25;
+const GRAVITY: f32 = 0.02;
+
+/// Which effects are shown.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EffectSettings {
+    pub line_clear: bool,
+    pub hard_drop_trail: bool,
+    pub lock_flash: bool,
+    pub particles: bool,
+    pub screen_shake: bool,
+}
+
+impl Default for EffectSettings {
+    fn default() -> EffectSettings {
+        EffectSettings {
+            line_clear: true,
+            hard_drop_trail: true,
+            lock_flash: true,
+            particles: true,
+            screen_shake: true,
+        }
+    }
+}
+
+impl EffectSettings {
+    pub fn none() -> EffectSettings {
+        EffectSettings {
+            line_clear: false,
+            hard_drop_trail: false,
+            lock_flash: false,
+            particles: false,
+            screen_shake: false,
+        }
+    }
+
+    /// Parses one `name value` pair per line, `on` or `off`, e.g.
+    /// `screen_shake off`.
+    pub fn parse(content: &str) -> Option<EffectSettings> {
+        let mut settings = EffectSettings::default();
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let mut words = line.split_whitespace();
+            let (name, value) = (words.next()?, words.next()?);
+            let effect = match name {
+                "line_clear" => &mut settings.line_clear,
+                "hard_drop_trail" => &mut settings.hard_drop_trail,
+                "lock_flash" => &mut settings.lock_flash,
+                "particles" => &mut settings.particles,
+                "screen_shake" => &mut settings.screen_shake,
+                _ => return None,
+            };
+            *effect = match value {
+                "on" => true,
+                "off" => false,
+                _ => return None,
+            };
+        }
+        Some(settings)
+    }
+
+    pub fn load(file_name: &str) -> Option<EffectSettings> {
+        let mut f = File::open(file_name).ok()?;
+        let mut content = String::new();
+        f.read_to_string(&mut content).ok()?;
+        EffectSettings::parse(&content)
+    }
+}
+
+/// What happened when a piece locked.
+pub struct LockEvent {
+    /// The piece where it locked.
+    pub piece: Tetrimino,
+    /// Where a hard drop started from.
+    pub dropped_from: Option<usize>,
+    pub cleared_rows: Vec<usize>,
+    pub t_spin: bool,
+}
+
+impl LockEvent {
+    /// `piece` is the piece before the action that locked it, and `rotated`
+    /// whether its last move was a rotation.
+    pub fn new(
+        game_map: &[Vec<u8>],
+        piece: &Tetrimino,
+        hard_drop: bool,
+        rotated: bool,
+    ) -> LockEvent {
+        let mut locked = piece.clone();
+        while locked.change_position(game_map, locked.x, locked.y + 1) {}
+        let mut board = game_map.to_vec();
+        for (shift_y, line) in locked.states[locked.current_state as usize]
+            .iter()
+            .enumerate()
+        {
+            for (shift_x, case) in line.iter().enumerate() {
+                if *case != 0 {
+                    board[locked.y + shift_y][(locked.x + shift_x as isize) as usize] = *case;
+                }
+            }
+        }
+        let cleared_rows = board
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| !line.contains(&0))
+            .map(|(y, _)| y)
+            .collect();
+        LockEvent {
+            t_spin: rotated && locked.y == piece.y && locked.is_t_spin_position(game_map),
+            dropped_from: if hard_drop && locked.y != piece.y {
+                Some(piece.y)
+            } else {
+                None
+            },
+            piece: locked,
+            cleared_rows,
+        }
+    }
+}
+
+/// A case of the grid, in cells from its top-left corner.
+pub type Cell = (isize, usize);
+
+/// The complete lines of a map in `Phase::LineClear`.
+pub struct LineClear {
+    pub rows: Vec<usize>,
+    pub width: usize,
+    /// Ticks before the lines are removed, out of `ticks`.
+    pub ticks_left: u32,
+    pub ticks: u32,
+}
+
+impl LineClear {
+    /// From 0 when the lines were cleared to 1 when they're removed.
+    pub fn progress(&self) -> f32 {
+        1. - self.ticks_left as f32 / self.ticks.max(1) as f32
+    }
+}
+
+pub struct Trail {
+    /// The top case of each column of the piece where it landed.
+    pub cases: Vec<Cell>,
+    pub length: usize,
+    pub kind: u8,
+    pub frame: u32,
+}
+
+pub struct Flash {
+    pub cases: Vec<Cell>,
+    pub frame: u32,
+}
+
+pub struct Particle {
+    /// Position and speed, in cells and cells per frame.
+    pub x: f32,
+    pub y: f32,
+    pub speed_x: f32,
+    pub speed_y: f32,
+    pub kind: u8,
+    pub frame: u32,
+}
+
+/// The running effects of one player.
+pub struct Effects {
+    pub settings: EffectSettings,
+    pub line_clear: Option<LineClear>,
+    pub trails: Vec<Trail>,
+    pub flashes: Vec<Flash>,
+    pub particles: Vec<Particle>,
+    shake: u32,
+}
+
+fn cases(piece: &Tetrimino) -> Vec<Cell> {
+    let mut cases = Vec::new();
+    for (shift_y, line) in piece.states[piece.current_state as usize]
+        .iter()
+        .enumerate()
+    {
+        for (shift_x, case) in line.iter().enumerate() {
+            if *case != 0 {
+                cases.push((piece.x + shift_x as isize, piece.y + shift_y));
+            }
+        }
+    }
+    cases
+}
+
+impl Effects {
+    pub fn new(settings: EffectSettings) -> Effects {
+        Effects {
+            settings,
+            line_clear: None,
+            trails: Vec::new(),
+            flashes: Vec::new(),
+            particles: Vec::new(),
+            shake: 0,
+        }
+    }
+
+    pub fn on_lock(&mut self, event: &LockEvent) {
+        let kind = event.piece.kind();
+        let cases = cases(&event.piece);
+        if let (true, Some(from)) = (self.settings.hard_drop_trail, event.dropped_from) {
+            // Only the top of each column leaves a trail.
+            let mut top = Vec::<Cell>::new();
+            for &(x, y) in &cases {
+                match top.iter_mut().find(|(column, _)| *column == x) {
+                    Some(case) => case.1 = case.1.min(y),
+                    None => top.push((x, y)),
+                }
+            }
+            self.trails.push(Trail {
+                cases: top,
+                length: event.piece.y - from,
+                kind,
+                frame: 0,
+            });
+        }
+        if self.settings.lock_flash {
+            self.flashes.push(Flash { cases, frame: 0 });
+        }
+        if self.settings.particles && (event.cleared_rows.len() >= 4 || event.t_spin) {
+            let mut rng = rand::thread_rng();
+            let rows = if event.cleared_rows.is_empty() {
+                vec![event.piece.y + 1]
+            } else {
+                event.cleared_rows.clone()
+            };
+            for row in rows {
+                for _ in 0..PARTICLES_PER_LINE {
+                    self.particles.push(Particle {
+                        x: rng.gen_range(0., 10.),
+                        y: row as f32 + 0.5,
+                        speed_x: rng.gen_range(-0.15, 0.15),
+                        speed_y: rng.gen_range(-0.35, -0.05),
+                        kind: if event.t_spin {
+                            kind
+                        } else {
+                            rng.gen_range(1, 8)
+                        },
+                        frame: 0,
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn on_garbage(&mut self, lines: u32) {
+        if self.settings.screen_shake && lines > 0 {
+            self.shake = SHAKE_FRAMES;
+        }
+    }
+
+    /// Moves every effect one frame forward, and the line clear animation
+    /// to where the delay of `tetris` is.
+    pub fn tick(&mut self, tetris: &Tetris) {
+        self.line_clear = match tetris.phase() {
+            Phase::LineClear(ticks_left) if self.settings.line_clear => Some(LineClear {
+                rows: (0..tetris.game_map.len())
+                    .filter(|&y| !tetris.game_map[y].contains(&0))
+                    .collect(),
+                width: tetris.game_map[0].len(),
+                ticks_left,
+                ticks: tetris.delays.line_clear,
+            }),
+            _ => None,
+        };
+        self.trails.retain_mut(|trail| {
+            trail.frame += 1;
+            trail.frame < TRAIL_FRAMES
+        });
+        self.flashes.retain_mut(|flash| {
+            flash.frame += 1;
+            flash.frame < FLASH_FRAMES
+        });
+        self.particles.retain_mut(|particle| {
+            particle.x += particle.speed_x;
+            particle.y += particle.speed_y;
+            particle.speed_y += GRAVITY;
+            particle.frame += 1;
+            particle.frame < PARTICLE_FRAMES
+        });
+        self.shake = self.shake.saturating_sub(1);
+    }
+
+    /// How far the board is shaken, in fractions of a cell.
+    pub fn shake_offset(&self) -> (f32, f32) {
+        if self.shake == 0 {
+            return (0., 0.);
+        }
+        let strength = self.shake as f32 / SHAKE_FRAMES as f32 * 0.25;
+        let mut rng = rand::thread_rng();
+        (
+            rng.gen_range(-strength, strength),
+            rng.gen_range(0., strength),
+        )
+    }
+
+    pub fn trail_alpha(trail: &Trail) -> u8 {
+        (120 * (TRAIL_FRAMES - trail.frame) / TRAIL_FRAMES) as u8
+    }
+
+    pub fn flash_alpha(flash: &Flash) -> u8 {
+        (200 * (FLASH_FRAMES - flash.frame) / FLASH_FRAMES) as u8
+    }
+
+    pub fn particle_alpha(particle: &Particle) -> u8 {
+        (255 * (PARTICLE_FRAMES - particle.frame) / PARTICLE_FRAMES) as u8
+    }
+}
@@ -0,0 +1,111 @@
+//! Replays played back offscreen and saved as an animated GIF or as numbered
+//! PNG images, drawn by the same renderer as the window.
+
+use sdl2::pixels::PixelFormatEnum;
+
+use tetris::game::TICKS_PER_SECOND;
+use tetris::replay::Replay;
+use tetris::theme::Theme;
+
+use crate::gif::GifEncoder;
+use crate::render::Renderer;
+use crate::screenshot::{self, Offscreen};
+use tetris::effects::EffectSettings;
+use tetris::session::Session;
+
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_FPS: u32 = 30;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Gif,
+    Png,
+}
+
+pub struct ExportOptions {
+    pub format: Format,
+    /// The GIF file, or the directory the PNG images are saved in.
+    pub output: String,
+    pub size: (u32, u32),
+    /// Frames per second, up to `TICKS_PER_SECOND`.
+    pub fps: u32,
+}
+
+/// Plays `replay` back and saves its frames. Returns how many were saved.
+pub fn export(
+    replay: &Replay,
+    options: &ExportOptions,
+    (theme, skin): (&Theme, &mut Option<PathBuf>),
+    effect_settings: EffectSettings,
+) -> Result<u32, String> {
+    let (width, height) = options.size;
+    let mut session = Session::from_replay(replay, effect_settings);
+    let mut offscreen = Offscreen::new(replay.nb_players, options.size)?;
+    let textures = crate::load_textures(
+        &mut offscreen.canvas,
+        &offscreen.texture_creator,
+        theme,
+        skin,
+    );
+    let mut renderer = Renderer::new(&offscreen.texture_creator, textures);
+    let ttf_context = sdl2::ttf::init().ok();
+    let text = crate::load_text(ttf_context.as_ref(), &offscreen.layout);
+
+    let mut gif = match options.format {
+        Format::Gif => {
+            if width > u16::MAX as u32 || height > u16::MAX as u32 {
+                return Err(format!("{}x{} is too big for a GIF", width, height));
+            }
+            let file = File::create(&options.output).map_err(|e| e.to_string())?;
+            let gif = GifEncoder::new(BufWriter::new(file), width as u16, height as u16);
+            Some(gif.map_err(|e| e.to_string())?)
+        }
+        Format::Png => {
+            fs::create_dir_all(&options.output).map_err(|e| e.to_string())?;
+            None
+        }
+    };
+
+    let fps = options.fps.clamp(1, TICKS_PER_SECOND) as u64;
+    let mut nb_frames = 0;
+    let mut over = false;
+    while !over {
+        let actions = replay.inputs_at(session.tick).collect::<Vec<_>>();
+        over = session.step(&actions).loser.is_some() || session.tick > replay.end;
+        // Only the ticks starting a new frame are drawn.
+        if !over && session.tick * fps / TICKS_PER_SECOND as u64 <= nb_frames {
+            continue;
+        }
+        renderer.draw_frame(
+            &mut offscreen.canvas,
+            &offscreen.layout,
+            text.as_ref(),
+            &session.games(),
+            session.versus.as_ref(),
+            &session.effects,
+        )?;
+        match gif {
+            Some(ref mut gif) => {
+                // Rounded to hundredths of a second without drifting.
+                let delay =
+                    (100 * (nb_frames + 1) + fps / 2) / fps - (100 * nb_frames + fps / 2) / fps;
+                let rgb = offscreen.canvas.read_pixels(None, PixelFormatEnum::RGB24)?;
+                gif.add_frame(&rgb, delay as u16)
+                    .map_err(|e| e.to_string())?;
+            }
+            None => {
+                let file_name =
+                    Path::new(&options.output).join(format!("frame-{:05}.png", nb_frames));
+                screenshot::save_png(&offscreen.canvas, &file_name.to_string_lossy())?;
+            }
+        }
+        nb_frames += 1;
+    }
+    if let Some(gif) = gif {
+        gif.finish().map_err(|e| e.to_string())?;
+    }
+    Ok(nb_frames as u32)
+}
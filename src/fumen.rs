@@ -0,0 +1,679 @@
+//! Boards shared as fumen strings, the format of the community's editor, in
+//! its version 1.15: `v115@` followed by the pages of a sequence, alone or
+//! in an URL.
+//!
+//! Fumen's field is 23 rows of 10 cases above a garbage row, of which the
+//! game's 16 rows are the bottom ones. Each page writes what changed since
+//! the previous one, and its piece, by the position of its center. Pieces
+//! are told apart by their shapes rather than their names, since fumen's
+//! `J` and `L` are the mirrors of the built-in ones, and garbage is grey.
+
+use crate::game::{GameState, GARBAGE, MAP_HEIGHT, MAP_WIDTH};
+use crate::pieces::PieceSet;
+
+const TABLE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+/// Comments are written with the printable ASCII characters, from space.
+const COMMENT_CHARS: u32 = 96;
+const FIELD_TOP: usize = 23;
+/// The cases of the field and of its garbage row.
+const FIELD_BLOCKS: usize = (FIELD_TOP + 1) * MAP_WIDTH;
+/// Fumen's pieces, from 1, for messages. 8 is grey.
+const NAMES: [&str; 7] = ["I", "L", "O", "Z", "T", "J", "S"];
+const GREY: u8 = 8;
+/// The blocks of fumen's pieces around their center, `y` going up.
+const SHAPES: [[(isize, isize); 4]; 7] = [
+    [(0, 0), (-1, 0), (1, 0), (2, 0)],
+    [(0, 0), (-1, 0), (1, 0), (1, 1)],
+    [(0, 0), (1, 0), (0, 1), (1, 1)],
+    [(0, 0), (1, 0), (0, 1), (-1, 1)],
+    [(0, 0), (-1, 0), (1, 0), (0, 1)],
+    [(0, 0), (-1, 0), (1, 0), (-1, 1)],
+    [(0, 0), (-1, 0), (0, 1), (1, 1)],
+];
+/// Fumen's rotations, in the order it numbers them.
+const REVERSE: u32 = 0;
+const RIGHT: u32 = 1;
+const SPAWN: u32 = 2;
+const LEFT: u32 = 3;
+
+/// One step of a sequence.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Page {
+    pub game_map: Vec<Vec<u8>>,
+    /// `(kind, x, y, state)` of the piece to place, as in `GameState`.
+    pub piece: Option<(u8, isize, usize, u8)>,
+    pub comment: String,
+}
+
+impl Page {
+    /// The map once the piece is locked and the full lines are cleared.
+    pub fn locked(&self, pieces: &PieceSet) -> Vec<Vec<u8>> {
+        let mut game_map = self.game_map.clone();
+        if let Some((kind, x, y, state)) = self.piece {
+            for (block_x, block_y) in blocks(pieces, (kind, x, y, state)) {
+                if block_y < MAP_HEIGHT && block_x >= 0 && (block_x as usize) < MAP_WIDTH {
+                    game_map[block_y][block_x as usize] = kind;
+                }
+            }
+        }
+        game_map.retain(|line| line.contains(&0));
+        while game_map.len() < MAP_HEIGHT {
+            game_map.insert(0, vec![0; MAP_WIDTH]);
+        }
+        game_map
+    }
+}
+
+/// Where the blocks of a piece are on the map.
+fn blocks(pieces: &PieceSet, (kind, x, y, state): (u8, isize, usize, u8)) -> Vec<(isize, usize)> {
+    let piece = pieces.piece(kind);
+    let mut blocks = Vec::new();
+    for (shift_y, line) in piece.states[state as usize].iter().enumerate() {
+        for (shift_x, case) in line.iter().enumerate() {
+            if *case != 0 {
+                blocks.push((x + shift_x as isize, y + shift_y));
+            }
+        }
+    }
+    blocks
+}
+
+fn map_blocks(pieces: &PieceSet, position: (u8, isize, usize, u8)) -> Vec<(isize, isize)> {
+    blocks(pieces, position)
+        .into_iter()
+        .map(|(x, y)| (x, y as isize))
+        .collect()
+}
+
+/// `blocks` moved so that the leftmost and topmost ones are at 0, sorted.
+fn normalized(blocks: &[(isize, isize)]) -> (Vec<(isize, isize)>, (isize, isize)) {
+    let min_x = blocks.iter().map(|block| block.0).min().unwrap_or(0);
+    let min_y = blocks.iter().map(|block| block.1).min().unwrap_or(0);
+    let mut shape = blocks
+        .iter()
+        .map(|&(x, y)| (x - min_x, y - min_y))
+        .collect::<Vec<_>>();
+    shape.sort_unstable();
+    (shape, (min_x, min_y))
+}
+
+/// The blocks of a fumen piece, in map coordinates.
+fn fumen_blocks(kind: u8, rotation: u32, (x, y): (isize, isize)) -> Vec<(isize, isize)> {
+    SHAPES[kind as usize - 1]
+        .iter()
+        .map(|&(dx, dy)| {
+            let (dx, dy) = match rotation {
+                SPAWN => (dx, dy),
+                RIGHT => (dy, -dx),
+                REVERSE => (-dx, -dy),
+                _ => (-dy, dx),
+            };
+            (x + dx, MAP_HEIGHT as isize - 1 - (y + dy))
+        })
+        .collect()
+}
+
+/// How far fumen's own centers are from the ones of `fumen_blocks`.
+fn center_shift(kind: u8, rotation: u32) -> (isize, isize) {
+    match (NAMES[kind as usize - 1], rotation) {
+        ("O", LEFT) => (1, -1),
+        ("O", REVERSE) | ("I", REVERSE) => (1, 0),
+        ("O", SPAWN) | ("I", LEFT) | ("S", SPAWN) | ("Z", SPAWN) => (0, -1),
+        ("S", RIGHT) => (-1, 0),
+        ("Z", LEFT) => (1, 0),
+        _ => (0, 0),
+    }
+}
+
+/// The kind of `pieces` of each of fumen's pieces, the first of the same
+/// shape.
+fn kinds(pieces: &PieceSet) -> Vec<Option<u8>> {
+    (1..=NAMES.len() as u8)
+        .map(|fumen| {
+            let shapes = [SPAWN, RIGHT, REVERSE, LEFT]
+                .iter()
+                .map(|rotation| normalized(&fumen_blocks(fumen, *rotation, (0, 0))).0)
+                .collect::<Vec<_>>();
+            (1..=pieces.len() as u8).find(|kind| {
+                (0..pieces.piece(*kind).states.len() as u8).any(|state| {
+                    shapes.contains(&normalized(&map_blocks(pieces, (*kind, 0, 0, state))).0)
+                })
+            })
+        })
+        .collect()
+}
+
+/// Fumen's piece for a kind of `pieces`, or grey.
+fn fumen_kind(kinds: &[Option<u8>], kind: u8) -> u8 {
+    match kind {
+        0 => 0,
+        kind => kinds
+            .iter()
+            .position(|fumen| *fumen == Some(kind))
+            .map_or(GREY, |position| position as u8 + 1),
+    }
+}
+
+/// The kind of `pieces` for fumen's piece, grey being garbage.
+fn case_kind(kinds: &[Option<u8>], fumen: u8) -> u8 {
+    match fumen {
+        0 => 0,
+        GREY => GARBAGE,
+        fumen => kinds[fumen as usize - 1].unwrap_or(GARBAGE),
+    }
+}
+
+fn poll(data: &mut &[u8], nb_chars: usize) -> Result<u32, String> {
+    if data.len() < nb_chars {
+        return Err("the fumen ends too soon".to_owned());
+    }
+    let mut value = 0;
+    for (nb, c) in data[..nb_chars].iter().enumerate() {
+        let digit = TABLE
+            .iter()
+            .position(|table| table == c)
+            .ok_or_else(|| format!("unexpected {:?} in the fumen", *c as char))?;
+        value += digit as u32 * 64u32.pow(nb as u32);
+    }
+    *data = &data[nb_chars..];
+    Ok(value)
+}
+
+fn push(data: &mut Vec<u8>, mut value: u32, nb_chars: usize) {
+    for _ in 0..nb_chars {
+        data.push(TABLE[value as usize % 64]);
+        value /= 64;
+    }
+}
+
+/// The field of the next page: the piece locked, if it is, and the full
+/// lines cleared, then the garbage row risen and the field mirrored if the
+/// page says so.
+fn next_field(field: &[u8], piece: &[(isize, isize)], kind: u8, flags: Flags) -> Vec<u8> {
+    let mut rows = field
+        .chunks(MAP_WIDTH)
+        .map(|row| row.to_vec())
+        .collect::<Vec<_>>();
+    let garbage = rows.pop().unwrap_or_default();
+    if flags.lock {
+        for &(x, y) in piece {
+            let row = y + (FIELD_TOP - MAP_HEIGHT) as isize;
+            if (0..FIELD_TOP as isize).contains(&row) && (0..MAP_WIDTH as isize).contains(&x) {
+                rows[row as usize][x as usize] = kind;
+            }
+        }
+        rows.retain(|row| row.contains(&0));
+        while rows.len() < FIELD_TOP {
+            rows.insert(0, vec![0; MAP_WIDTH]);
+        }
+    }
+    let garbage = if flags.rise {
+        rows.remove(0);
+        rows.push(garbage);
+        vec![0; MAP_WIDTH]
+    } else {
+        garbage
+    };
+    if flags.mirror {
+        for row in &mut rows {
+            row.reverse();
+        }
+    }
+    rows.push(garbage);
+    rows.concat()
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Flags {
+    rise: bool,
+    mirror: bool,
+    colour: bool,
+    comment: bool,
+    lock: bool,
+}
+
+/// Comments are escaped like JavaScript's `escape`.
+fn escape(comment: &str) -> String {
+    let mut escaped = String::new();
+    for c in comment.chars() {
+        if c.is_ascii_alphanumeric() || "@*_+-./".contains(c) {
+            escaped.push(c);
+        } else if (c as u32) < 0x100 {
+            escaped += &format!("%{:02X}", c as u32);
+        } else {
+            for unit in c.encode_utf16(&mut [0; 2]) {
+                escaped += &format!("%u{:04X}", unit);
+            }
+        }
+    }
+    escaped
+}
+
+fn unescape(escaped: &str) -> String {
+    let mut units = Vec::new();
+    let mut rest = escaped;
+    while let Some(c) = rest.chars().next() {
+        let code = if c == '%' && rest.starts_with("%u") {
+            u16::from_str_radix(rest.get(2..6).unwrap_or(""), 16)
+                .ok()
+                .map(|unit| (unit, 6))
+        } else if c == '%' {
+            u16::from_str_radix(rest.get(1..3).unwrap_or(""), 16)
+                .ok()
+                .map(|unit| (unit, 3))
+        } else {
+            None
+        };
+        match code {
+            Some((unit, len)) => {
+                units.push(unit);
+                rest = &rest[len..];
+            }
+            None => {
+                units.extend(c.encode_utf16(&mut [0; 2]).iter());
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    String::from_utf16_lossy(&units)
+}
+
+/// The pages of `fumen`, a fumen string or an URL ending with one.
+pub fn decode(fumen: &str, pieces: &PieceSet) -> Result<Vec<Page>, String> {
+    let start = ["v115@", "m115@", "d115@"]
+        .iter()
+        .filter_map(|prefix| fumen.find(prefix))
+        .min()
+        .ok_or("not a fumen of version 1.15")?;
+    let data = fumen[start + 5..]
+        .trim()
+        .bytes()
+        .filter(|c| *c != b'?')
+        .collect::<Vec<_>>();
+    let mut data = &data[..];
+    let kinds = kinds(pieces);
+
+    let mut pages = Vec::new();
+    let mut field = vec![0; FIELD_BLOCKS];
+    let mut repeat = 0;
+    let mut comment = String::new();
+    while !data.is_empty() {
+        if repeat > 0 {
+            repeat -= 1;
+        } else {
+            let previous = field.clone();
+            let mut index = 0;
+            while index < FIELD_BLOCKS {
+                let value = poll(&mut data, 2)? as usize;
+                let (diff, nb_blocks) = (value / FIELD_BLOCKS, value % FIELD_BLOCKS + 1);
+                if diff == 8 && nb_blocks == FIELD_BLOCKS {
+                    repeat = poll(&mut data, 1)?;
+                }
+                if index + nb_blocks > FIELD_BLOCKS || diff > 16 {
+                    return Err("invalid field in the fumen".to_owned());
+                }
+                for case in index..index + nb_blocks {
+                    let kind = previous[case] as usize + diff;
+                    if !(8..=8 + GREY as usize).contains(&kind) {
+                        return Err("invalid field in the fumen".to_owned());
+                    }
+                    field[case] = (kind - 8) as u8;
+                }
+                index += nb_blocks;
+            }
+        }
+
+        let mut value = poll(&mut data, 3)?;
+        let kind = (value % 8) as u8;
+        value /= 8;
+        let rotation = value % 4;
+        value /= 4;
+        let coordinate = value as usize % FIELD_BLOCKS;
+        value /= FIELD_BLOCKS as u32;
+        let flags = Flags {
+            rise: value & 1 != 0,
+            mirror: value & 2 != 0,
+            colour: value & 4 != 0,
+            comment: value & 8 != 0,
+            lock: value & 16 == 0,
+        };
+        if flags.comment {
+            let len = poll(&mut data, 2)? as usize;
+            let mut escaped = String::new();
+            for _ in 0..len.div_ceil(4) {
+                let mut value = poll(&mut data, 5)?;
+                for _ in 0..4 {
+                    escaped.push((b' ' + (value % COMMENT_CHARS) as u8) as char);
+                    value /= COMMENT_CHARS;
+                }
+            }
+            escaped.truncate(len);
+            comment = unescape(&escaped);
+        }
+
+        let mut fumen_piece = Vec::new();
+        let piece = if kind == 0 || kind == GREY {
+            None
+        } else {
+            let (shift_x, shift_y) = center_shift(kind, rotation);
+            let center = (
+                (coordinate % MAP_WIDTH) as isize + shift_x,
+                (FIELD_TOP - coordinate / MAP_WIDTH) as isize - 1 + shift_y,
+            );
+            fumen_piece = fumen_blocks(kind, rotation, center);
+            Some(piece_position(pieces, &kinds, kind, &fumen_piece)?)
+        };
+        let rows = field.chunks(MAP_WIDTH).collect::<Vec<_>>();
+        if rows[..FIELD_TOP - MAP_HEIGHT]
+            .iter()
+            .any(|row| row.iter().any(|case| *case != 0))
+        {
+            return Err(format!("the board is higher than {} rows", MAP_HEIGHT));
+        }
+        pages.push(Page {
+            game_map: rows[FIELD_TOP - MAP_HEIGHT..FIELD_TOP]
+                .iter()
+                .map(|row| row.iter().map(|case| case_kind(&kinds, *case)).collect())
+                .collect(),
+            piece,
+            comment: comment.clone(),
+        });
+        field = next_field(&field, &fumen_piece, kind, flags);
+    }
+    if pages.is_empty() {
+        return Err("the fumen has no page".to_owned());
+    }
+    Ok(pages)
+}
+
+/// The position of the piece of `pieces` covering the blocks of a fumen
+/// piece.
+fn piece_position(
+    pieces: &PieceSet,
+    kinds: &[Option<u8>],
+    fumen: u8,
+    fumen_piece: &[(isize, isize)],
+) -> Result<(u8, isize, usize, u8), String> {
+    let name = NAMES[fumen as usize - 1];
+    let kind = kinds[fumen as usize - 1].ok_or_else(|| format!("the pieces have no {}", name))?;
+    let (shape, (x, y)) = normalized(fumen_piece);
+    if y < 0 {
+        return Err(format!("the {} is above the board", name));
+    }
+    let piece = pieces.piece(kind);
+    for state in 0..piece.states.len() {
+        let (state_shape, (shift_x, shift_y)) =
+            normalized(&map_blocks(pieces, (kind, 0, 0, state as u8)));
+        if state_shape == shape && y >= shift_y {
+            return Ok((kind, x - shift_x, (y - shift_y) as usize, state as u8));
+        }
+    }
+    Err(format!(
+        "no rotation of {} is where the fumen puts it",
+        name
+    ))
+}
+
+/// The fumen of `pages`, each piece locked before the next page.
+pub fn encode(pages: &[Page], pieces: &PieceSet) -> Result<String, String> {
+    let mut data = Vec::new();
+    let mut previous = vec![0; FIELD_BLOCKS];
+    // Where the number of pages repeating the previous field is written.
+    let mut repeat: Option<usize> = None;
+    let mut comment = String::new();
+    let kinds = kinds(pieces);
+    for (nb, page) in pages.iter().enumerate() {
+        let mut field = vec![0; (FIELD_TOP - MAP_HEIGHT) * MAP_WIDTH];
+        field.extend(
+            page.game_map
+                .iter()
+                .flatten()
+                .map(|case| fumen_kind(&kinds, *case)),
+        );
+        field.resize(FIELD_BLOCKS, 0);
+
+        let mut values = Vec::new();
+        let mut case = 0;
+        while case < FIELD_BLOCKS {
+            let diff = 8 + field[case] as usize - previous[case] as usize;
+            let mut nb_blocks = 1;
+            while case + nb_blocks < FIELD_BLOCKS
+                && 8 + field[case + nb_blocks] as usize - previous[case + nb_blocks] as usize
+                    == diff
+            {
+                nb_blocks += 1;
+            }
+            values.push(diff * FIELD_BLOCKS + nb_blocks - 1);
+            case += nb_blocks;
+        }
+        let unchanged = values == [8 * FIELD_BLOCKS + FIELD_BLOCKS - 1];
+        match repeat {
+            Some(index) if unchanged && data[index] != TABLE[63] => {
+                let count = TABLE.iter().position(|c| *c == data[index]).unwrap_or(0);
+                data[index] = TABLE[count + 1];
+            }
+            _ => {
+                for value in values {
+                    push(&mut data, value as u32, 2);
+                }
+                repeat = if unchanged {
+                    data.push(TABLE[0]);
+                    Some(data.len() - 1)
+                } else {
+                    None
+                };
+            }
+        }
+
+        let (kind, rotation, coordinate, fumen_piece) = match page.piece {
+            Some(position) => {
+                let (kind, rotation, center) = fumen_piece(pieces, &kinds, position)?;
+                let (shift_x, shift_y) = center_shift(kind, rotation);
+                let (x, y) = (center.0 - shift_x, center.1 - shift_y);
+                let coordinate = (FIELD_TOP as isize - y - 1) * MAP_WIDTH as isize + x;
+                (
+                    kind,
+                    rotation,
+                    coordinate as u32,
+                    fumen_blocks(kind, rotation, center),
+                )
+            }
+            None => (0, REVERSE, 0, Vec::new()),
+        };
+        let flags = Flags {
+            colour: nb == 0,
+            comment: page.comment != comment,
+            lock: true,
+            ..Flags::default()
+        };
+        let flag_bits = [
+            flags.rise,
+            flags.mirror,
+            flags.colour,
+            flags.comment,
+            !flags.lock,
+        ]
+        .iter()
+        .enumerate()
+        .map(|(bit, set)| (*set as u32) << bit)
+        .sum::<u32>();
+        let value =
+            ((flag_bits * FIELD_BLOCKS as u32 + coordinate) * 4 + rotation) * 8 + kind as u32;
+        push(&mut data, value, 3);
+        if flags.comment {
+            comment = page.comment.clone();
+            let escaped = escape(&comment).into_bytes();
+            push(&mut data, escaped.len() as u32, 2);
+            for chars in escaped.chunks(4) {
+                let value = chars
+                    .iter()
+                    .rev()
+                    .fold(0, |value, c| value * COMMENT_CHARS + (c - b' ') as u32);
+                push(&mut data, value, 5);
+            }
+        }
+        previous = next_field(&field, &fumen_piece, kind, flags);
+    }
+
+    // The editor cuts its strings in lines of 47 characters.
+    let mut fumen = "v115@".to_owned();
+    for (nb, chars) in data.chunks(47).enumerate() {
+        if nb > 0 {
+            fumen.push('?');
+        }
+        fumen += &String::from_utf8_lossy(chars);
+    }
+    Ok(fumen)
+}
+
+/// Fumen's piece, rotation and center covering the piece at `position`.
+fn fumen_piece(
+    pieces: &PieceSet,
+    kinds: &[Option<u8>],
+    position: (u8, isize, usize, u8),
+) -> Result<(u8, u32, (isize, isize)), String> {
+    let name = &pieces.pieces[position.0 as usize - 1].name;
+    let kind = fumen_kind(kinds, position.0);
+    let (shape, (x, y)) = normalized(&map_blocks(pieces, position));
+    if kind != GREY {
+        for rotation in [SPAWN, RIGHT, REVERSE, LEFT] {
+            let (fumen_shape, (shift_x, shift_y)) =
+                normalized(&fumen_blocks(kind, rotation, (0, 0)));
+            if fumen_shape == shape {
+                // Fumen's rows go up from the bottom of the map.
+                let center = (x - shift_x, -(y - shift_y));
+                return Ok((kind, rotation, center));
+            }
+        }
+    }
+    Err(format!("{} can't be written in a fumen", name))
+}
+
+/// The fumen of a game, with its current piece where it is.
+pub fn from_state(state: &GameState) -> Result<String, String> {
+    encode(
+        &[Page {
+            game_map: state.game_map.clone(),
+            piece: state.current_piece,
+            comment: String::new(),
+        }],
+        &state.pieces,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty_map() -> Vec<Vec<u8>> {
+        vec![vec![0; MAP_WIDTH]; MAP_HEIGHT]
+    }
+
+    #[test]
+    fn empty_fields_are_a_known_string() {
+        let pieces = PieceSet::builtin();
+        let page = Page {
+            game_map: empty_map(),
+            piece: None,
+            comment: String::new(),
+        };
+        assert_eq!(
+            encode(std::slice::from_ref(&page), &pieces).unwrap(),
+            "v115@vhAAgH"
+        );
+        assert_eq!(
+            decode("https://fumen.zui.jp/?v115@vhAAgH", &pieces).unwrap(),
+            [page]
+        );
+    }
+
+    #[test]
+    fn fields_are_read_from_the_editor() {
+        // Four rows of six grey cases, a well on the right.
+        let pages = decode("v115@9gF8DeF8DeF8DeF8NeAgH", &PieceSet::builtin()).unwrap();
+        let mut game_map = empty_map();
+        for line in &mut game_map[MAP_HEIGHT - 4..] {
+            line[..6].copy_from_slice(&[GARBAGE; 6]);
+        }
+        assert_eq!(pages[0].game_map, game_map);
+        assert_eq!(pages[0].piece, None);
+    }
+
+    #[test]
+    fn t_spin_doubles_are_read_from_the_editor() {
+        // A T pointing down in its slot: the field changes in runs of 210
+        // empty, 3 grey, 3 empty, 8 grey, 1 empty, 5 grey and 10 empty
+        // cases, then the T (5) reversed (0) around case 214, to lock.
+        let pieces = PieceSet::builtin();
+        let pages = decode("v115@RhC8CeH8AeE8JeFLJ", &pieces).unwrap();
+        let t = pieces.kind("T").unwrap();
+        let mut game_map = empty_map();
+        game_map[MAP_HEIGHT - 2] = vec![GARBAGE; MAP_WIDTH];
+        game_map[MAP_HEIGHT - 2][3..6].fill(0);
+        game_map[MAP_HEIGHT - 1] = vec![GARBAGE; MAP_WIDTH];
+        game_map[MAP_HEIGHT - 1][4] = 0;
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].game_map, game_map);
+        assert_eq!(pages[0].piece, Some((t, 3, MAP_HEIGHT - 2, 0)));
+        assert_eq!(pages[0].locked(&pieces), empty_map());
+        assert_eq!(encode(&pages, &pieces).unwrap(), "v115@RhC8CeH8AeE8JeFLJ");
+    }
+
+    #[test]
+    fn pieces_go_back_where_they_were() {
+        let pieces = PieceSet::builtin();
+        for kind in 1..=pieces.len() as u8 {
+            for state in 0..pieces.piece(kind).states.len() as u8 {
+                let mut game_map = empty_map();
+                game_map[MAP_HEIGHT - 1] = vec![GARBAGE; MAP_WIDTH];
+                game_map[MAP_HEIGHT - 1][0] = 0;
+                game_map[MAP_HEIGHT - 2][9] = kind;
+                let page = Page {
+                    game_map,
+                    piece: Some((kind, 3, 9, state)),
+                    comment: String::new(),
+                };
+                let fumen = encode(std::slice::from_ref(&page), &pieces).unwrap();
+                assert_eq!(decode(&fumen, &pieces).unwrap(), [page]);
+            }
+        }
+    }
+
+    #[test]
+    fn pages_follow_the_locked_pieces() {
+        let pieces = PieceSet::builtin();
+        let i = pieces.kind("I").unwrap();
+        let o = pieces.kind("O").unwrap();
+        let mut game_map = empty_map();
+        game_map[MAP_HEIGHT - 1][..6].copy_from_slice(&[GARBAGE; 6]);
+        let first = Page {
+            game_map,
+            piece: Some((i, 6, MAP_HEIGHT - 1, 0)),
+            comment: "Clear a line".to_owned(),
+        };
+        let second = Page {
+            game_map: first.locked(&pieces),
+            piece: Some((o, 0, MAP_HEIGHT - 2, 0)),
+            comment: "then 100%!".to_owned(),
+        };
+        assert_eq!(second.game_map, empty_map());
+        let third = Page {
+            game_map: second.locked(&pieces),
+            piece: None,
+            comment: "then 100%!".to_owned(),
+        };
+        let pages = vec![first, second, third];
+        let fumen = encode(&pages, &pieces).unwrap();
+        assert_eq!(decode(&fumen, &pieces).unwrap(), pages);
+    }
+
+    #[test]
+    fn broken_fumens_are_rejected() {
+        let pieces = PieceSet::builtin();
+        assert!(decode("vhAAgH", &pieces).is_err());
+        assert!(decode("v115@vhA", &pieces).is_err());
+        assert!(decode("v115@v!AAgH", &pieces).is_err());
+        // Blocks above the 16 rows of the map.
+        assert!(decode("v115@A8uhAgH", &pieces).is_err());
+    }
+}
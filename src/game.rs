@@ -0,0 +1,1418 @@
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use crate::delays::Delays;
+use crate::pieces::PieceSet;
+use crate::stats::{self, Statistics};
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::Arc;
+
+pub const TICKS_PER_SECOND: u32 = 60;
+pub const LEVEL_TIMES: [u32; 10] = [1000, 850, 700, 600, 500, 400, 300, 250, 221, 190];
+pub const LEVEL_LINES: [u32; 10] = [20, 40, 60, 80, 100, 120, 140, 160, 180, 200];
+/// Value of the cells added to the map by `Tetris::add_garbage`, after
+/// every piece kind.
+pub const GARBAGE: u8 = u8::MAX;
+pub const MAP_WIDTH: usize = 10;
+pub const MAP_HEIGHT: usize = 16;
+/// Number of upcoming pieces known in advance.
+pub const NB_NEXT_PIECES: usize = 5;
+
+pub type Piece = Vec<Vec<u8>>;
+pub type States = Vec<Piece>;
+/// `(x, y)` offsets tried in turn when rotating, by state rotated from. A
+/// single list is used for every state.
+pub type Kicks = Vec<Vec<(isize, isize)>>;
+
+#[derive(Clone, Debug)]
+pub struct Tetrimino {
+    pub states: States,
+    pub x: isize,
+    pub y: usize,
+    pub current_state: u8,
+    pub kicks: Kicks,
+}
+
+impl Tetrimino {
+    pub fn kind(&self) -> u8 {
+        self.states[0]
+            .iter()
+            .flatten()
+            .find(|case| **case != 0)
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    pub fn rotate(&mut self, game_map: &[Vec<u8>]) {
+        let mut tmp_state = self.current_state + 1;
+        if tmp_state as usize >= self.states.len() {
+            tmp_state = 0;
+        }
+        let kicks = match self.kicks.len() {
+            0 => return,
+            nb_lists => &self.kicks[self.current_state as usize % nb_lists],
+        };
+        for &(x, y) in kicks {
+            let y = self.y as isize + y;
+            if y >= 0 && self.test_position(game_map, tmp_state as usize, self.x + x, y as usize) {
+                self.current_state = tmp_state;
+                self.x += x;
+                self.y = y as usize;
+                break;
+            }
+        }
+    }
+
+    pub fn test_position(
+        &self,
+        game_map: &[Vec<u8>],
+        tmp_state: usize,
+        x: isize,
+        y: usize,
+    ) -> bool {
+        for (shift_y, line) in self.states[tmp_state].iter().enumerate() {
+            for (shift_x, case) in line.iter().enumerate() {
+                let x = x + shift_x as isize;
+                if *case != 0
+                    && (y + shift_y >= game_map.len()
+                        || x < 0
+                        || x as usize >= game_map[y + shift_y].len()
+                        || game_map[y + shift_y][x as usize] != 0)
+                {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    pub fn test_current_position(&self, game_map: &[Vec<u8>]) -> bool {
+        self.test_position(game_map, self.current_state as usize, self.x, self.y)
+    }
+
+    pub fn change_position(&mut self, game_map: &[Vec<u8>], new_x: isize, new_y: usize) -> bool {
+        if self.test_position(game_map, self.current_state as usize, new_x, new_y) {
+            self.x = new_x;
+            self.y = new_y;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether a T piece sits in a T-spin position: three of the four cases
+    /// diagonal to its center are filled, walls and floor included. Whether
+    /// it got there by rotating is up to the caller.
+    pub fn is_t_spin_position(&self, game_map: &[Vec<u8>]) -> bool {
+        // A T is four blocks, three of them around the center.
+        if self.states[0]
+            .iter()
+            .flatten()
+            .filter(|case| **case != 0)
+            .count()
+            != 4
+        {
+            return false;
+        }
+        let state = &self.states[self.current_state as usize];
+        let filled = |x: isize, y: isize| {
+            y >= 0
+                && x >= 0
+                && state
+                    .get(y as usize)
+                    .and_then(|line| line.get(x as usize))
+                    .is_some_and(|case| *case != 0)
+        };
+        let (width, height) = (state[0].len() as isize, state.len() as isize);
+        // The center is the only case with three neighbours.
+        let center = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .find(|&(x, y)| {
+                filled(x, y)
+                    && [(-1, 0), (1, 0), (0, -1), (0, 1)]
+                        .iter()
+                        .filter(|(dx, dy)| filled(x + dx, y + dy))
+                        .count()
+                        == 3
+            });
+        let (center_x, center_y) = match center {
+            Some(center) => center,
+            None => return false,
+        };
+        let corners = [(-1, -1), (1, -1), (-1, 1), (1, 1)]
+            .iter()
+            .filter(|(dx, dy)| {
+                let x = self.x + center_x + dx;
+                let y = self.y as isize + center_y + dy;
+                y < 0
+                    || y as usize >= game_map.len()
+                    || x < 0
+                    || x as usize >= game_map[y as usize].len()
+                    || game_map[y as usize][x as usize] != 0
+            })
+            .count();
+        corners >= 3
+    }
+}
+
+/// A single player input, as applied to the current piece.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Left,
+    Right,
+    SoftDrop,
+    Rotate,
+    HardDrop,
+    Hold,
+}
+
+impl Action {
+    pub fn to_char(self) -> char {
+        match self {
+            Action::Left => 'L',
+            Action::Right => 'R',
+            Action::SoftDrop => 'D',
+            Action::Rotate => 'U',
+            Action::HardDrop => 'H',
+            Action::Hold => 'C',
+        }
+    }
+
+    pub fn from_char(c: char) -> Option<Action> {
+        match c {
+            'L' => Some(Action::Left),
+            'R' => Some(Action::Right),
+            'D' => Some(Action::SoftDrop),
+            'U' => Some(Action::Rotate),
+            'H' => Some(Action::HardDrop),
+            'C' => Some(Action::Hold),
+            _ => None,
+        }
+    }
+}
+
+fn seeded_rng(seed: u64) -> XorShiftRng {
+    // A xorshift generator must not be seeded with zeros only.
+    XorShiftRng::from_seed([seed as u32, (seed >> 32) as u32, 0x9e37_79b9, 1])
+}
+
+/// What the game does between two pieces, see `Delays`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Phase {
+    /// A piece is falling, or spawns on the next `Tetris::spawn`.
+    #[default]
+    Falling,
+    /// The complete lines are removed after this many ticks.
+    LineClear(u32),
+    /// The next piece can spawn after this many ticks.
+    Entry(u32),
+}
+
+/// Everything a game is made of, to continue it later, see `Tetris::state`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GameState {
+    pub game_map: Vec<Vec<u8>>,
+    pub current_level: u32,
+    pub score: u32,
+    pub nb_lines: u32,
+    /// `(kind, x, y, state)` of the current piece.
+    pub current_piece: Option<(u8, isize, usize, u8)>,
+    pub hold_piece: Option<u8>,
+    pub can_hold: bool,
+    pub next_pieces: VecDeque<u8>,
+    #[serde(default)]
+    pub queued: VecDeque<u8>,
+    pub pieces: PieceSet,
+    pub statistics: Statistics,
+    /// What `Statistics` keeps about the pieces in progress, see
+    /// `Statistics::progress`.
+    pub statistics_progress: (u32, u32),
+    pub prev_piece: u8,
+    pub seed: u64,
+    pub draws: u64,
+    pub gravity_ticks: u32,
+    pub rotated: bool,
+    #[serde(default)]
+    pub delays: Delays,
+    #[serde(default)]
+    pub phase: Phase,
+    /// Rotations and hold asked for before the piece spawned.
+    #[serde(default)]
+    pub initial_actions: (u8, bool),
+}
+
+pub struct Tetris {
+    pub game_map: Vec<Vec<u8>>,
+    pub current_level: u32,
+    pub score: u32,
+    pub nb_lines: u32,
+    pub current_piece: Option<Tetrimino>,
+    /// Kind of the held piece, see `PieceSet::piece`.
+    pub hold_piece: Option<u8>,
+    /// A piece can only be held once until it's locked.
+    pub can_hold: bool,
+    /// Kinds of the pieces coming after the current one, in order.
+    pub next_pieces: VecDeque<u8>,
+    /// Kinds of the pieces coming after the next ones, before the random
+    /// ones, for practice.
+    pub queued: VecDeque<u8>,
+    pub pieces: Arc<PieceSet>,
+    pub statistics: Statistics,
+    pub delays: Delays,
+    phase: Phase,
+    /// Rotations and hold asked for during the delays, played when the
+    /// next piece spawns: the initial rotation and hold, IRS and IHS.
+    initial_actions: (u8, bool),
+    prev_piece: u8,
+    /// The generator is the one of `seed` after `draws` numbers, which is
+    /// how it's saved.
+    seed: u64,
+    draws: u64,
+    rng: XorShiftRng,
+    gravity_ticks: u32,
+    /// Whether the current piece last moved by rotating, for T-spins.
+    rotated: bool,
+    history: Option<History>,
+}
+
+/// The placements of a practice game, to take them back and play them again.
+struct History {
+    /// The game before each placement, with its piece back at the top.
+    undo: VecDeque<GameState>,
+    /// The games undone, the last one first.
+    redo: Vec<GameState>,
+    limit: usize,
+}
+
+impl Default for Tetris {
+    fn default() -> Tetris {
+        Tetris::new()
+    }
+}
+
+impl Tetris {
+    pub fn new() -> Tetris {
+        Tetris::with_seed(rand::random())
+    }
+
+    /// Two games created with the same seed and fed the same actions stay
+    /// identical, which is what lockstep multiplayer relies on.
+    pub fn with_seed(seed: u64) -> Tetris {
+        Tetris::with_pieces(seed, Arc::new(PieceSet::builtin()))
+    }
+
+    /// A game of the pieces of `pieces`, which both sides of a lockstep game
+    /// must share too.
+    pub fn with_pieces(seed: u64, pieces: Arc<PieceSet>) -> Tetris {
+        let mut game_map = Vec::new();
+        for _ in 0..MAP_HEIGHT {
+            game_map.push(vec![0; MAP_WIDTH]);
+        }
+        let mut tetris = Tetris {
+            game_map,
+            current_level: 1,
+            score: 0,
+            nb_lines: 0,
+            current_piece: None,
+            hold_piece: None,
+            can_hold: true,
+            next_pieces: VecDeque::with_capacity(NB_NEXT_PIECES + 1),
+            queued: VecDeque::new(),
+            statistics: Statistics::new(pieces.len()),
+            delays: Delays::default(),
+            phase: Phase::Falling,
+            initial_actions: (0, false),
+            prev_piece: pieces.len() as u8,
+            pieces,
+            seed,
+            draws: 0,
+            rng: seeded_rng(seed),
+            gravity_ticks: 0,
+            rotated: false,
+            history: None,
+        };
+        while tetris.next_pieces.len() < NB_NEXT_PIECES {
+            let kind = tetris.random_kind();
+            tetris.next_pieces.push_back(kind);
+        }
+        tetris
+    }
+
+    pub fn state(&self) -> GameState {
+        GameState {
+            game_map: self.game_map.clone(),
+            current_level: self.current_level,
+            score: self.score,
+            nb_lines: self.nb_lines,
+            current_piece: self
+                .current_piece
+                .as_ref()
+                .map(|piece| (piece.kind(), piece.x, piece.y, piece.current_state)),
+            hold_piece: self.hold_piece,
+            can_hold: self.can_hold,
+            next_pieces: self.next_pieces.clone(),
+            queued: self.queued.clone(),
+            pieces: (*self.pieces).clone(),
+            statistics: self.statistics.clone(),
+            statistics_progress: self.statistics.progress(),
+            prev_piece: self.prev_piece,
+            seed: self.seed,
+            draws: self.draws,
+            gravity_ticks: self.gravity_ticks,
+            rotated: self.rotated,
+            delays: self.delays,
+            phase: self.phase,
+            initial_actions: self.initial_actions,
+        }
+    }
+
+    /// The game `state` was taken from, which continues the same way. States
+    /// that no game could be in are rejected.
+    pub fn from_state(state: GameState) -> Result<Tetris, String> {
+        let pieces = PieceSet::new(state.pieces.name, state.pieces.kicks, state.pieces.pieces)?;
+        let nb_kinds = pieces.len() as u8;
+        let is_kind = |kind: u8| (1..=nb_kinds).contains(&kind);
+        if state.game_map.len() != MAP_HEIGHT
+            || state.game_map.iter().any(|line| {
+                line.len() != MAP_WIDTH
+                    || line
+                        .iter()
+                        .any(|case| *case != 0 && *case != GARBAGE && !is_kind(*case))
+            })
+        {
+            return Err("invalid map".to_owned());
+        }
+        if state.next_pieces.len() != NB_NEXT_PIECES
+            || !state.next_pieces.iter().all(|kind| is_kind(*kind))
+            || !state.queued.iter().all(|kind| is_kind(*kind))
+            || !state.hold_piece.is_none_or(is_kind)
+            || state.prev_piece > nb_kinds
+        {
+            return Err("invalid pieces".to_owned());
+        }
+        if state.current_level == 0 || state.current_level as usize > LEVEL_LINES.len() {
+            return Err(format!("invalid level {}", state.current_level));
+        }
+        if state.statistics.spawned.len() != pieces.len() {
+            return Err("invalid statistics".to_owned());
+        }
+        if state.phase != Phase::Falling && state.current_piece.is_some() {
+            return Err("a piece during the delays".to_owned());
+        }
+        let current_piece = match state.current_piece {
+            Some((kind, x, y, current_state)) => {
+                if !is_kind(kind) {
+                    return Err("invalid current piece".to_owned());
+                }
+                let mut piece = pieces.piece(kind);
+                if current_state as usize >= piece.states.len()
+                    || !piece.test_position(&state.game_map, current_state as usize, x, y)
+                {
+                    return Err("invalid current piece".to_owned());
+                }
+                piece.x = x;
+                piece.y = y;
+                piece.current_state = current_state;
+                Some(piece)
+            }
+            None => None,
+        };
+        let mut rng = seeded_rng(state.seed);
+        for _ in 0..state.draws {
+            rng.next_u32();
+        }
+        Ok(Tetris {
+            game_map: state.game_map,
+            current_level: state.current_level,
+            score: state.score,
+            nb_lines: state.nb_lines,
+            current_piece,
+            hold_piece: state.hold_piece,
+            can_hold: state.can_hold,
+            next_pieces: state.next_pieces,
+            queued: state.queued,
+            pieces: Arc::new(pieces),
+            statistics: state.statistics.with_progress(state.statistics_progress),
+            delays: state.delays,
+            phase: state.phase,
+            initial_actions: state.initial_actions,
+            prev_piece: state.prev_piece,
+            seed: state.seed,
+            draws: state.draws,
+            rng,
+            gravity_ticks: state.gravity_ticks,
+            rotated: state.rotated,
+            history: None,
+        })
+    }
+
+    /// Keeps the `limit` last placements to undo them. Only practice games
+    /// do, the others stay as they were played.
+    pub fn enable_history(&mut self, limit: usize) {
+        self.history = Some(History {
+            undo: VecDeque::with_capacity(limit),
+            redo: Vec::new(),
+            limit,
+        });
+    }
+
+    /// Numbers of placements that can be undone and redone.
+    pub fn history_len(&self) -> (usize, usize) {
+        self.history
+            .as_ref()
+            .map_or((0, 0), |history| (history.undo.len(), history.redo.len()))
+    }
+
+    /// Goes back to before the last placement, its piece at the top again.
+    /// Returns `false` if there's none, or without a history. A placement
+    /// that can't be undone stays in the history.
+    pub fn undo(&mut self) -> bool {
+        let state = match self
+            .history
+            .as_mut()
+            .and_then(|history| history.undo.pop_back())
+        {
+            Some(state) => state,
+            None => return false,
+        };
+        let now = self.state();
+        let restored = self.restore(&state);
+        if let Some(ref mut history) = self.history {
+            if restored {
+                history.redo.push(now);
+            } else {
+                history.undo.push_back(state);
+            }
+        }
+        restored
+    }
+
+    /// Plays again the last placement undone.
+    pub fn redo(&mut self) -> bool {
+        let state = match self.history.as_mut().and_then(|history| history.redo.pop()) {
+            Some(state) => state,
+            None => return false,
+        };
+        let now = self.state();
+        let restored = self.restore(&state);
+        if let Some(ref mut history) = self.history {
+            if restored {
+                history.undo.push_back(now);
+            } else {
+                history.redo.push(state);
+            }
+        }
+        restored
+    }
+
+    /// Continues from `state`, keeping the history.
+    fn restore(&mut self, state: &GameState) -> bool {
+        match Tetris::from_state(state.clone()) {
+            Ok(mut tetris) => {
+                tetris.history = self.history.take();
+                *self = tetris;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Saves the game before its current piece locks, with the piece where
+    /// it spawned if it still fits there.
+    fn push_history(&mut self) {
+        if self.history.is_none() {
+            return;
+        }
+        let mut state = self.state();
+        if let Some(ref piece) = self.current_piece {
+            let spawned = self.pieces.piece(piece.kind());
+            if spawned.test_current_position(&self.game_map) {
+                state.current_piece = Some((piece.kind(), spawned.x, spawned.y, 0));
+                // Its moves are judged again, though the inputs played
+                // still count.
+                state.statistics_progress.1 = 0;
+            }
+        }
+        state.gravity_ticks = 0;
+        state.rotated = false;
+        if let Some(ref mut history) = self.history {
+            if history.undo.len() == history.limit {
+                history.undo.pop_front();
+            }
+            if history.limit > 0 {
+                history.undo.push_back(state);
+            }
+            history.redo.clear();
+        }
+    }
+
+    fn update_score(&mut self, to_add: u32) {
+        self.score += to_add;
+    }
+
+    fn increase_level(&mut self) {
+        self.current_level += 1;
+    }
+
+    fn increase_line(&mut self) {
+        self.nb_lines += 1;
+        // The last level lasts forever.
+        if (self.current_level as usize) < LEVEL_LINES.len()
+            && self.nb_lines > LEVEL_LINES[self.current_level as usize - 1]
+        {
+            self.increase_level();
+        }
+    }
+
+    /// Scores the complete lines and returns how many there are. They're
+    /// removed by `remove_lines`.
+    fn check_lines(&mut self) -> u32 {
+        let cleared = self
+            .game_map
+            .iter()
+            .filter(|line| !line.contains(&0))
+            .count() as u32;
+        let mut score_add = self.current_level * cleared;
+        if cleared as usize == self.game_map.len() {
+            // A "tetris"!
+            score_add += 1000;
+        }
+        self.update_score(score_add);
+        for _ in 0..cleared {
+            self.increase_line();
+        }
+        cleared
+    }
+
+    /// Replaces the complete lines with empty ones at the top.
+    fn remove_lines(&mut self) {
+        self.game_map.retain(|line| line.contains(&0));
+        while self.game_map.len() < MAP_HEIGHT {
+            self.game_map.insert(0, vec![0; MAP_WIDTH]);
+        }
+    }
+
+    /// The entry delay if there's one, once the lines are removed.
+    fn entry_phase(&self) -> Phase {
+        match self.delays.entry {
+            0 => Phase::Falling,
+            ticks => Phase::Entry(ticks),
+        }
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    /// Ends the delays at once, for games played without time.
+    pub fn skip_delays(&mut self) {
+        if let Phase::LineClear(_) = self.phase {
+            self.remove_lines();
+        }
+        self.phase = Phase::Falling;
+    }
+
+    fn random_kind(&mut self) -> u8 {
+        let nb_kinds = self.pieces.len() as u8;
+        let mut rand_nb = self.next_random() as u8 % nb_kinds;
+        if self.prev_piece == rand_nb {
+            rand_nb = self.next_random() as u8 % nb_kinds;
+        }
+        self.prev_piece = rand_nb;
+        rand_nb + 1
+    }
+
+    /// Takes the first of the next pieces and adds the first queued one, or
+    /// a random one, at the end of the queue.
+    pub fn create_new_tetrimino(&mut self) -> Tetrimino {
+        let kind = match self.queued.pop_front() {
+            Some(kind) => kind,
+            None => self.random_kind(),
+        };
+        self.next_pieces.push_back(kind);
+        let kind = self.next_pieces.pop_front().unwrap_or(kind);
+        self.pieces.piece(kind)
+    }
+
+    /// Spawns a new piece if there is none and the delays are over. Returns
+    /// `false` when the new piece doesn't fit, meaning the game is over.
+    pub fn spawn(&mut self) -> bool {
+        if self.current_piece.is_some() || self.phase != Phase::Falling {
+            return true;
+        }
+        let current_piece = self.create_new_tetrimino();
+        if !current_piece.test_current_position(&self.game_map) {
+            return false;
+        }
+        self.statistics.on_spawn(current_piece.kind());
+        self.rotated = false;
+        self.current_piece = Some(current_piece);
+
+        let (rotations, hold) = mem::take(&mut self.initial_actions);
+        if hold {
+            self.hold();
+            // Without a held piece, the next one comes instead.
+            if !self.spawn() {
+                return false;
+            }
+        }
+        if let Some(ref mut piece) = self.current_piece {
+            for _ in 0..rotations as usize % piece.states.len() {
+                piece.rotate(&self.game_map);
+            }
+        }
+        true
+    }
+
+    /// Replaces the current piece with a new one of `kind` at its spawn
+    /// position, for practice. Returns `false`, leaving the game as it was,
+    /// if it doesn't fit there.
+    pub fn replace_current_piece(&mut self, kind: u8) -> bool {
+        let piece = self.pieces.piece(kind);
+        if self.phase != Phase::Falling || !piece.test_current_position(&self.game_map) {
+            return false;
+        }
+        self.current_piece = Some(piece);
+        self.gravity_ticks = 0;
+        self.rotated = false;
+        true
+    }
+
+    /// Writes the current piece into the map and returns the number of lines
+    /// it cleared.
+    pub fn make_permanent(&mut self) -> u32 {
+        let mut to_add = 0;
+        let (mut t_spin, mut optimal_inputs) = (false, None);
+        if self.current_piece.is_some() {
+            self.push_history();
+        }
+        if let Some(ref mut piece) = self.current_piece {
+            t_spin = self.rotated && piece.is_t_spin_position(&self.game_map);
+            let spawned = self.pieces.piece(piece.kind());
+            optimal_inputs = stats::optimal_inputs(&self.game_map, &spawned, piece);
+            let mut shift_y = 0;
+
+            while shift_y < piece.states[piece.current_state as usize].len()
+                && piece.y + shift_y < self.game_map.len()
+            {
+                let mut shift_x = 0;
+
+                while shift_x < piece.states[piece.current_state as usize][shift_y].len()
+                    && (piece.x + shift_x as isize)
+                        < self.game_map[piece.y + shift_y].len() as isize
+                {
+                    if piece.states[piece.current_state as usize][shift_y][shift_x] != 0 {
+                        let x = piece.x + shift_x as isize;
+                        self.game_map[piece.y + shift_y][x as usize] =
+                            piece.states[piece.current_state as usize][shift_y][shift_x];
+                    }
+                    shift_x += 1;
+                }
+                shift_y += 1;
+            }
+            to_add += self.current_level;
+        }
+        self.update_score(to_add);
+        let cleared = self.check_lines();
+        self.statistics.on_lock(cleared, t_spin, optimal_inputs);
+        self.current_piece = None;
+        self.can_hold = true;
+        self.phase = if cleared > 0 && self.delays.line_clear > 0 {
+            Phase::LineClear(self.delays.line_clear)
+        } else {
+            self.remove_lines();
+            self.entry_phase()
+        };
+        cleared
+    }
+
+    /// Swaps the current piece with the held one. Without a held piece, the
+    /// current one is simply put aside and the next `spawn` brings a new one.
+    fn hold(&mut self) {
+        if !self.can_hold {
+            return;
+        }
+        let kind = match self.current_piece {
+            Some(ref piece) => piece.kind(),
+            None => return,
+        };
+        let held = match self.hold_piece {
+            Some(held) => {
+                let piece = self.pieces.piece(held);
+                if !piece.test_current_position(&self.game_map) {
+                    return;
+                }
+                Some(piece)
+            }
+            None => None,
+        };
+        self.current_piece = held;
+        self.hold_piece = Some(kind);
+        self.can_hold = false;
+        self.gravity_ticks = 0;
+        self.rotated = false;
+    }
+
+    /// Applies a player action to the current piece. Returns the number of
+    /// cleared lines if the action locked the piece.
+    pub fn apply_action(&mut self, action: Action) -> Option<u32> {
+        self.statistics.on_action(action);
+        self.play(action)
+    }
+
+    /// `apply_action` without counting an input, for the gravity.
+    fn play(&mut self, action: Action) -> Option<u32> {
+        // Between the lock and the spawn, with or without delays.
+        if self.phase != Phase::Falling || self.current_piece.is_none() {
+            match action {
+                Action::Rotate => self.initial_actions.0 = self.initial_actions.0.saturating_add(1),
+                Action::Hold => self.initial_actions.1 = true,
+                _ => {}
+            }
+            return None;
+        }
+        if action == Action::Hold {
+            self.hold();
+            return None;
+        }
+        let mut make_permanent = false;
+        if let Some(ref mut piece) = self.current_piece {
+            let (x, y, state) = (piece.x, piece.y, piece.current_state);
+            match action {
+                Action::Left => {
+                    piece.change_position(&self.game_map, x - 1, y);
+                }
+                Action::Right => {
+                    piece.change_position(&self.game_map, x + 1, y);
+                }
+                Action::SoftDrop => {
+                    self.gravity_ticks = 0;
+                    make_permanent = !piece.change_position(&self.game_map, x, y + 1);
+                }
+                Action::Rotate => piece.rotate(&self.game_map),
+                Action::HardDrop => {
+                    let mut y = y;
+                    while piece.change_position(&self.game_map, x, y + 1) {
+                        y += 1;
+                    }
+                    make_permanent = true;
+                }
+                Action::Hold => unreachable!(),
+            }
+            if (piece.x, piece.y, piece.current_state) != (x, y, state) {
+                self.rotated = action == Action::Rotate;
+            }
+        }
+        if make_permanent {
+            self.gravity_ticks = 0;
+            Some(self.make_permanent())
+        } else {
+            None
+        }
+    }
+
+    /// Tick-based equivalent of the level timer: drops the current piece by
+    /// one line every `LEVEL_TIMES` milliseconds, counted in ticks. During
+    /// the delays, counts them down instead.
+    pub fn gravity_tick(&mut self) -> Option<u32> {
+        self.statistics.ticks += 1;
+        match self.phase {
+            Phase::Falling => {}
+            Phase::LineClear(ticks) if ticks > 1 => {
+                self.phase = Phase::LineClear(ticks - 1);
+                return None;
+            }
+            Phase::LineClear(_) => {
+                self.remove_lines();
+                self.phase = self.entry_phase();
+                return None;
+            }
+            Phase::Entry(ticks) => {
+                self.phase = match ticks {
+                    0 | 1 => Phase::Falling,
+                    ticks => Phase::Entry(ticks - 1),
+                };
+                return None;
+            }
+        }
+        self.gravity_ticks += 1;
+        if self.gravity_ticks * 1000
+            > LEVEL_TIMES[self.current_level as usize - 1] * TICKS_PER_SECOND
+        {
+            self.play(Action::SoftDrop)
+        } else {
+            None
+        }
+    }
+
+    /// Every random number of the game comes from here, to be counted.
+    fn next_random(&mut self) -> u32 {
+        self.draws += 1;
+        self.rng.next_u32()
+    }
+
+    /// Column of the hole of the next garbage line.
+    pub fn garbage_hole(&mut self) -> usize {
+        let high = self.next_random() as u64;
+        let low = self.next_random() as u64;
+        ((high << 32 | low) as usize) % self.game_map[0].len()
+    }
+
+    /// FNV-1a hash of everything that affects the rest of the game, used to
+    /// detect two simulations going out of sync.
+    pub fn state_hash(&self) -> u64 {
+        let mut hash = 0xcbf2_9ce4_8422_2325u64;
+        let mut feed = |value: u64| {
+            hash ^= value;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        };
+        for line in &self.game_map {
+            for case in line {
+                feed(*case as u64);
+            }
+        }
+        feed(self.score as u64);
+        feed(self.nb_lines as u64);
+        feed(self.current_level as u64);
+        feed(self.hold_piece.unwrap_or(0) as u64);
+        feed(self.can_hold as u64);
+        for kind in self.next_pieces.iter().chain(&self.queued) {
+            feed(*kind as u64);
+        }
+        // Only fed when there are some, for the hashes to stay the same
+        // without delays.
+        match self.phase {
+            Phase::Falling => {}
+            Phase::LineClear(ticks) => {
+                feed(1);
+                feed(ticks as u64);
+            }
+            Phase::Entry(ticks) => {
+                feed(2);
+                feed(ticks as u64);
+            }
+        }
+        if self.initial_actions != (0, false) {
+            feed(self.initial_actions.0 as u64);
+            feed(self.initial_actions.1 as u64);
+        }
+        if let Some(ref piece) = self.current_piece {
+            feed(piece.x as u64);
+            feed(piece.y as u64);
+            feed(piece.current_state as u64);
+            for case in piece.states.iter().flatten().flatten() {
+                feed(*case as u64);
+            }
+        }
+        hash
+    }
+
+    /// Pushes `nb_lines` garbage rows from the bottom of the map, each with a
+    /// single hole at column `hole`. Returns `false` if blocks were pushed
+    /// out of the top of the map.
+    pub fn add_garbage(&mut self, nb_lines: u32, hole: usize) -> bool {
+        let mut topped_out = false;
+        for _ in 0..nb_lines {
+            let removed = self.game_map.remove(0);
+            if removed.iter().any(|case| *case != 0) {
+                topped_out = true;
+            }
+            let mut line = vec![GARBAGE; removed.len()];
+            line[hole % removed.len()] = 0;
+            self.game_map.push(line);
+        }
+        !topped_out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitboard::{BitBoard, PieceMasks};
+    use crate::bot;
+
+    use proptest::prelude::*;
+
+    /// Columns and rows of the blocks of a state, as `(min, max)`.
+    fn bounds(state: &Piece) -> ((usize, usize), (usize, usize)) {
+        let blocks = state.iter().enumerate().flat_map(|(y, line)| {
+            line.iter()
+                .enumerate()
+                .filter(|(_, case)| **case != 0)
+                .map(move |(x, _)| (x, y))
+        });
+        let (xs, ys): (Vec<_>, Vec<_>) = blocks.unzip();
+        (
+            (*xs.iter().min().unwrap(), *xs.iter().max().unwrap()),
+            (*ys.iter().min().unwrap(), *ys.iter().max().unwrap()),
+        )
+    }
+
+    /// A full line but for the case `hole`.
+    fn line_with_hole(hole: usize) -> Vec<u8> {
+        let mut line = vec![GARBAGE; MAP_WIDTH];
+        line[hole] = 0;
+        line
+    }
+
+    fn empty_map() -> Vec<Vec<u8>> {
+        vec![vec![0; MAP_WIDTH]; MAP_HEIGHT]
+    }
+
+    #[test]
+    fn pieces_stop_at_every_wall() {
+        let pieces = PieceSet::builtin();
+        let game_map = empty_map();
+        for kind in 1..=pieces.len() as u8 {
+            let piece = pieces.piece(kind);
+            for (nb, state) in piece.states.iter().enumerate() {
+                let ((min_x, max_x), (_, max_y)) = bounds(state);
+                let left = -(min_x as isize);
+                let right = (MAP_WIDTH - 1 - max_x) as isize;
+                let bottom = MAP_HEIGHT - 1 - max_y;
+                assert!(piece.test_position(&game_map, nb, left, 5));
+                assert!(!piece.test_position(&game_map, nb, left - 1, 5));
+                assert!(piece.test_position(&game_map, nb, right, 5));
+                assert!(!piece.test_position(&game_map, nb, right + 1, 5));
+                assert!(piece.test_position(&game_map, nb, 3, bottom));
+                assert!(!piece.test_position(&game_map, nb, 3, bottom + 1));
+            }
+        }
+    }
+
+    #[test]
+    fn pieces_collide_with_blocks() {
+        let pieces = PieceSet::builtin();
+        let mut game_map = empty_map();
+        game_map[10][4] = GARBAGE;
+        let o = pieces.piece(4);
+        assert!(o.test_position(&game_map, 0, 4, 8));
+        assert!(!o.test_position(&game_map, 0, 4, 9));
+        assert!(!o.test_position(&game_map, 0, 3, 9));
+        assert!(o.test_position(&game_map, 0, 5, 9));
+        assert!(o.test_position(&game_map, 0, 2, 9));
+    }
+
+    #[test]
+    fn rotations_go_through_every_state() {
+        let pieces = PieceSet::builtin();
+        let game_map = empty_map();
+        for kind in 1..=pieces.len() as u8 {
+            let mut piece = pieces.piece(kind);
+            piece.y = 5;
+            let start = (piece.x, piece.y);
+            for nb in 1..=piece.states.len() {
+                piece.rotate(&game_map);
+                assert_eq!(piece.current_state as usize, nb % piece.states.len());
+                assert_eq!((piece.x, piece.y), start);
+            }
+        }
+    }
+
+    #[test]
+    fn rotations_kick_off_the_walls() {
+        let pieces = PieceSet::builtin();
+        let game_map = empty_map();
+        // A vertical I against the left wall.
+        let mut i = pieces.piece(1);
+        i.current_state = 1;
+        i.x = -1;
+        i.y = 5;
+        i.rotate(&game_map);
+        assert_eq!((i.current_state, i.x, i.y), (0, 0, 5));
+        // And against the right one.
+        i.current_state = 1;
+        i.x = MAP_WIDTH as isize - 2;
+        i.rotate(&game_map);
+        assert_eq!((i.current_state, i.x, i.y), (0, MAP_WIDTH as isize - 4, 5));
+    }
+
+    #[test]
+    fn rotations_without_room_do_nothing() {
+        let pieces = PieceSet::builtin();
+        // A well one case wide.
+        let game_map = (0..MAP_HEIGHT)
+            .map(|_| line_with_hole(4))
+            .collect::<Vec<_>>();
+        let mut i = pieces.piece(1);
+        i.current_state = 1;
+        i.x = 3;
+        i.y = 8;
+        assert!(i.test_current_position(&game_map));
+        i.rotate(&game_map);
+        assert_eq!((i.current_state, i.x, i.y), (1, 3, 8));
+    }
+
+    #[test]
+    fn locking_clears_several_lines() {
+        let mut tetris = Tetris::with_seed(1);
+        for y in MAP_HEIGHT - 4..MAP_HEIGHT {
+            tetris.game_map[y] = line_with_hole(9);
+        }
+        tetris.game_map[MAP_HEIGHT - 5][0] = GARBAGE;
+        let mut i = tetris.pieces.piece(1);
+        i.current_state = 1;
+        i.x = 8;
+        i.y = MAP_HEIGHT - 4;
+        tetris.current_piece = Some(i);
+        assert_eq!(tetris.make_permanent(), 4);
+        assert_eq!(tetris.nb_lines, 4);
+        // A point for the piece, and one per line at level 1.
+        assert_eq!(tetris.score, 5);
+        let mut expected = empty_map();
+        expected[MAP_HEIGHT - 1][0] = GARBAGE;
+        assert_eq!(tetris.game_map, expected);
+    }
+
+    #[test]
+    fn locking_clears_the_top_row() {
+        let mut tetris = Tetris::with_seed(1);
+        tetris.game_map[0] = vec![GARBAGE; MAP_WIDTH];
+        tetris.game_map[1] = line_with_hole(0);
+        tetris.game_map[MAP_HEIGHT - 1] = vec![GARBAGE; MAP_WIDTH];
+        assert_eq!(tetris.make_permanent(), 2);
+        let mut expected = empty_map();
+        expected[2] = line_with_hole(0);
+        assert_eq!(tetris.game_map, expected);
+        assert_eq!(tetris.score, 2);
+    }
+
+    #[test]
+    fn clearing_the_whole_map_earns_a_bonus() {
+        let mut tetris = Tetris::with_seed(1);
+        tetris.game_map = vec![vec![GARBAGE; MAP_WIDTH]; MAP_HEIGHT];
+        assert_eq!(tetris.make_permanent(), MAP_HEIGHT as u32);
+        assert_eq!(tetris.score, MAP_HEIGHT as u32 + 1000);
+        assert_eq!(tetris.game_map, empty_map());
+    }
+
+    #[test]
+    fn levels_go_up_after_their_lines() {
+        let mut tetris = Tetris::with_seed(1);
+        for _ in 0..LEVEL_LINES[0] {
+            tetris.increase_line();
+        }
+        assert_eq!(tetris.current_level, 1);
+        tetris.increase_line();
+        assert_eq!(tetris.current_level, 2);
+        for _ in 0..1000 {
+            tetris.increase_line();
+        }
+        assert_eq!(tetris.current_level as usize, LEVEL_LINES.len());
+    }
+
+    #[test]
+    fn scores_count_the_level_before_the_clear() {
+        let mut tetris = Tetris::with_seed(1);
+        tetris.nb_lines = LEVEL_LINES[0] - 1;
+        for y in MAP_HEIGHT - 2..MAP_HEIGHT {
+            tetris.game_map[y] = line_with_hole(4);
+        }
+        let mut o = tetris.pieces.piece(4);
+        o.x = 4;
+        o.y = MAP_HEIGHT - 2;
+        // Only half of the O fits in the holes.
+        o.states[0] = vec![vec![4, 0], vec![4, 0]];
+        tetris.current_piece = Some(o);
+        assert_eq!(tetris.make_permanent(), 2);
+        assert_eq!(tetris.score, 3);
+        assert_eq!(tetris.current_level, 2);
+
+        tetris.game_map[MAP_HEIGHT - 1] = line_with_hole(0);
+        let mut i = tetris.pieces.piece(1);
+        i.current_state = 1;
+        i.x = -1;
+        i.y = MAP_HEIGHT - 4;
+        tetris.current_piece = Some(i);
+        assert_eq!(tetris.make_permanent(), 1);
+        assert_eq!(tetris.score, 3 + 2 + 2);
+    }
+
+    #[test]
+    fn gravity_follows_the_level() {
+        for level in [1, 5, 10] {
+            let mut tetris = Tetris::with_seed(1);
+            tetris.current_level = level;
+            assert!(tetris.spawn());
+            let y = tetris.current_piece.as_ref().unwrap().y;
+            let ticks = LEVEL_TIMES[level as usize - 1] * TICKS_PER_SECOND / 1000;
+            for _ in 0..ticks {
+                tetris.gravity_tick();
+            }
+            assert_eq!(tetris.current_piece.as_ref().unwrap().y, y);
+            tetris.gravity_tick();
+            assert_eq!(tetris.current_piece.as_ref().unwrap().y, y + 1);
+        }
+    }
+
+    #[test]
+    fn delays_wait_for_the_lines_then_the_entry() {
+        let mut tetris = Tetris::with_seed(1);
+        tetris.delays = Delays {
+            entry: 3,
+            line_clear: 2,
+        };
+        tetris.game_map[MAP_HEIGHT - 1] = vec![GARBAGE; MAP_WIDTH];
+        assert_eq!(tetris.make_permanent(), 1);
+        assert_eq!(tetris.nb_lines, 1);
+        // The complete line stays during the line clear delay.
+        assert_eq!(tetris.phase(), Phase::LineClear(2));
+        assert!(tetris.spawn());
+        assert!(tetris.current_piece.is_none());
+        tetris.gravity_tick();
+        assert_eq!(tetris.game_map[MAP_HEIGHT - 1], vec![GARBAGE; MAP_WIDTH]);
+        tetris.gravity_tick();
+        assert_eq!(tetris.game_map, empty_map());
+        assert_eq!(tetris.phase(), Phase::Entry(3));
+        for _ in 0..3 {
+            assert!(tetris.spawn());
+            assert!(tetris.current_piece.is_none());
+            tetris.gravity_tick();
+        }
+        assert_eq!(tetris.phase(), Phase::Falling);
+        assert!(tetris.spawn());
+        assert!(tetris.current_piece.is_some());
+
+        // Without lines, only the entry delay is waited.
+        tetris.apply_action(Action::HardDrop);
+        assert_eq!(tetris.phase(), Phase::Entry(3));
+        tetris.skip_delays();
+        assert!(tetris.spawn());
+        assert!(tetris.current_piece.is_some());
+    }
+
+    #[test]
+    fn no_delays_spawn_on_the_lock() {
+        let mut tetris = Tetris::with_seed(1);
+        tetris.game_map[MAP_HEIGHT - 1] = vec![GARBAGE; MAP_WIDTH];
+        assert_eq!(tetris.make_permanent(), 1);
+        assert_eq!(tetris.phase(), Phase::Falling);
+        assert_eq!(tetris.game_map, empty_map());
+        // Rotating before the spawn still counts.
+        tetris.next_pieces[0] = 7;
+        tetris.apply_action(Action::Rotate);
+        assert!(tetris.spawn());
+        assert_eq!(tetris.current_piece.as_ref().unwrap().current_state, 1);
+    }
+
+    #[test]
+    fn rotations_and_hold_during_the_delays_apply_on_spawn() {
+        let mut tetris = Tetris::with_seed(1);
+        tetris.delays = Delays {
+            entry: 2,
+            line_clear: 0,
+        };
+        assert!(tetris.spawn());
+        tetris.apply_action(Action::HardDrop);
+        assert_eq!(tetris.phase(), Phase::Entry(2));
+        // A T comes after the held piece.
+        tetris.next_pieces[1] = 7;
+        let next = tetris.next_pieces.clone();
+        tetris.apply_action(Action::Hold);
+        tetris.apply_action(Action::Rotate);
+        tetris.apply_action(Action::Left);
+        tetris.gravity_tick();
+        // A state in the middle of the delays picks up where it was.
+        let mut tetris = Tetris::from_state(tetris.state()).unwrap();
+        assert_eq!(tetris.phase(), Phase::Entry(1));
+        tetris.gravity_tick();
+        assert!(tetris.spawn());
+        // The first piece is held and the second one comes rotated.
+        let piece = tetris.current_piece.as_ref().unwrap();
+        assert_eq!(tetris.hold_piece, Some(next[0]));
+        assert_eq!(piece.kind(), next[1]);
+        assert_eq!(piece.current_state, 1);
+        assert_eq!(piece.x, tetris.pieces.piece(next[1]).x);
+        assert!(!tetris.can_hold);
+    }
+
+    /// The state of a game, but for the inputs played, which undoing
+    /// doesn't take back.
+    fn played(tetris: &Tetris) -> GameState {
+        let mut state = tetris.state();
+        state.statistics.inputs = 0;
+        state
+    }
+
+    #[test]
+    fn placements_are_only_undone_with_a_history() {
+        let mut tetris = Tetris::with_seed(1);
+        assert!(tetris.spawn());
+        tetris.apply_action(Action::HardDrop);
+        assert!(!tetris.undo());
+
+        tetris.enable_history(2);
+        let mut states = Vec::new();
+        for _ in 0..3 {
+            assert!(tetris.spawn());
+            states.push(played(&tetris));
+            tetris.apply_action(Action::Left);
+            tetris.apply_action(Action::HardDrop);
+        }
+        let placed = played(&tetris);
+        assert_eq!(tetris.history_len(), (2, 0));
+        // The piece is back where it spawned, with the queue and the
+        // randomizer of then.
+        assert!(tetris.undo());
+        assert_eq!(played(&tetris), states[2]);
+        assert!(tetris.undo());
+        assert_eq!(played(&tetris), states[1]);
+        assert!(!tetris.undo());
+        assert!(tetris.redo());
+        assert!(tetris.redo());
+        assert_eq!(played(&tetris), placed);
+        assert!(!tetris.redo());
+
+        // Playing another placement forgets the ones undone.
+        assert!(tetris.undo());
+        tetris.apply_action(Action::HardDrop);
+        assert_eq!(tetris.history_len(), (2, 0));
+    }
+
+    #[test]
+    fn placements_that_cant_be_restored_stay_in_the_history() {
+        let mut tetris = Tetris::with_seed(1);
+        tetris.enable_history(2);
+        for _ in 0..2 {
+            assert!(tetris.spawn());
+            tetris.apply_action(Action::HardDrop);
+        }
+        let placed = played(&tetris);
+        let history = tetris.history.as_mut().unwrap();
+        history.undo[1].current_level = 0;
+        assert!(!tetris.undo());
+        assert_eq!(tetris.history_len(), (2, 0));
+        assert_eq!(played(&tetris), placed);
+
+        let history = tetris.history.as_mut().unwrap();
+        history.undo[1].current_level = 1;
+        assert!(tetris.undo());
+        let history = tetris.history.as_mut().unwrap();
+        history.redo[0].current_level = 0;
+        assert!(!tetris.redo());
+        assert_eq!(tetris.history_len(), (1, 1));
+    }
+
+    fn action(nb: u8) -> Option<Action> {
+        match nb {
+            0 => Some(Action::Left),
+            1 => Some(Action::Right),
+            2 => Some(Action::SoftDrop),
+            3 => Some(Action::Rotate),
+            4 => Some(Action::HardDrop),
+            5 => Some(Action::Hold),
+            _ => None,
+        }
+    }
+
+    proptest! {
+        /// Whatever the inputs, a piece only locks where it rests, on empty
+        /// cases, and leaves no complete line behind.
+        #[test]
+        fn locks_keep_the_map_consistent(
+            seed in any::<u64>(),
+            inputs in prop::collection::vec(0..8u8, 1..600),
+        ) {
+            let mut tetris = Tetris::with_seed(seed);
+            for input in inputs {
+                if !tetris.spawn() {
+                    break;
+                }
+                let game_map = tetris.game_map.clone();
+                let mut piece = tetris.current_piece.clone().unwrap();
+                let cleared = match action(input) {
+                    Some(action) => tetris.apply_action(action),
+                    None => tetris.gravity_tick(),
+                };
+                if let Some(ref piece) = tetris.current_piece {
+                    prop_assert!(piece.test_current_position(&tetris.game_map));
+                }
+                let cleared = match cleared {
+                    Some(cleared) => cleared,
+                    None => continue,
+                };
+                while piece.change_position(&game_map, piece.x, piece.y + 1) {}
+                let (expected, expected_cleared) = bot::lock_piece(&game_map, &piece);
+                prop_assert_eq!(cleared, expected_cleared);
+                prop_assert_eq!(&tetris.game_map, &expected);
+                prop_assert!(tetris.game_map.len() == MAP_HEIGHT);
+                prop_assert!(tetris.game_map.iter().all(|line| line.len() == MAP_WIDTH));
+                prop_assert!(tetris.game_map.iter().all(|line| line.contains(&0)));
+                let count = |game_map: &[Vec<u8>]| {
+                    game_map.iter().flatten().filter(|case| **case != 0).count()
+                };
+                let blocks = piece.states[piece.current_state as usize]
+                    .iter()
+                    .flatten()
+                    .filter(|case| **case != 0)
+                    .count();
+                prop_assert_eq!(
+                    count(&tetris.game_map),
+                    count(&game_map) + blocks - cleared as usize * MAP_WIDTH
+                );
+            }
+        }
+
+        /// A row at a time, the lock positions are those the bot reaches
+        /// move by move.
+        #[test]
+        fn lock_positions_are_reached_move_by_move(
+            seed in any::<u64>(),
+            inputs in prop::collection::vec(0..8u8, 1..300),
+        ) {
+            let mut tetris = Tetris::with_seed(seed);
+            for input in inputs {
+                if !tetris.spawn() {
+                    break;
+                }
+                let piece = tetris.current_piece.clone().unwrap();
+                let mut locks = BitBoard::from_map(&tetris.game_map).lock_positions(
+                    &PieceMasks::new(&piece),
+                    (piece.current_state, piece.x, piece.y),
+                );
+                let mut expected = bot::placements(&tetris)
+                    .into_iter()
+                    .filter(|placement| !placement.hold)
+                    .map(|placement| (placement.current_state, placement.x, placement.y))
+                    .collect::<Vec<_>>();
+                locks.sort_unstable();
+                expected.sort_unstable();
+                prop_assert_eq!(locks, expected);
+                match action(input) {
+                    Some(action) => tetris.apply_action(action),
+                    None => tetris.gravity_tick(),
+                };
+            }
+        }
+
+        /// Games from the same seed and inputs stay the same, through a save.
+        #[test]
+        fn states_continue_the_same_game(
+            seed in any::<u64>(),
+            inputs in prop::collection::vec(0..8u8, 1..300),
+            split in 0..300usize,
+        ) {
+            let mut tetris = Tetris::with_seed(seed);
+            let mut copy = None;
+            for (nb, input) in inputs.iter().enumerate() {
+                if nb == split {
+                    copy = Some(Tetris::from_state(tetris.state()).unwrap());
+                }
+                for tetris in [Some(&mut tetris), copy.as_mut()].into_iter().flatten() {
+                    if tetris.spawn() {
+                        match action(*input) {
+                            Some(action) => tetris.apply_action(action),
+                            None => tetris.gravity_tick(),
+                        };
+                    }
+                }
+            }
+            if let Some(copy) = copy {
+                prop_assert_eq!(copy.state(), tetris.state());
+            }
+        }
+    }
+}
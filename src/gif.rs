@@ -0,0 +1,173 @@
+//! A small GIF89a encoder for looping animations. Every frame uses the same
+//! palette, a 6x6x6 colour cube followed by 40 greys, which is close enough
+//! for the flat colours of the game.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+const NB_GREYS: usize = 40;
+const MIN_CODE_SIZE: u8 = 8;
+const MAX_CODE_SIZE: u8 = 12;
+const CLEAR_CODE: u16 = 1 << MIN_CODE_SIZE;
+const END_CODE: u16 = CLEAR_CODE + 1;
+
+fn palette() -> Vec<u8> {
+    let mut palette = Vec::with_capacity(256 * 3);
+    for r in 0..6 {
+        for g in 0..6 {
+            for b in 0..6 {
+                palette.extend([r * 51, g * 51, b * 51]);
+            }
+        }
+    }
+    for grey in 0..NB_GREYS {
+        palette.extend([(grey * 255 / (NB_GREYS - 1)) as u8; 3]);
+    }
+    palette
+}
+
+/// The closest colour of the palette, the cube or the greys.
+fn palette_index((r, g, b): (u8, u8, u8)) -> u8 {
+    let level = |component: u8| (component as u32 + 25) / 51;
+    let cube = (level(r) * 36 + level(g) * 6 + level(b)) as u8;
+    let (min, max) = (r.min(g).min(b), r.max(g).max(b));
+    if max - min > 24 {
+        return cube;
+    }
+    let distance = |(pr, pg, pb): (u32, u32, u32)| {
+        let square = |a: u32, b: u8| (a as i32 - b as i32).pow(2);
+        square(pr, r) + square(pg, g) + square(pb, b)
+    };
+    let grey_nb = ((r as u32 + g as u32 + b as u32) * (NB_GREYS as u32 - 1) + 382) / 765;
+    let grey = grey_nb * 255 / (NB_GREYS as u32 - 1);
+    let cube_rgb = (level(r) * 51, level(g) * 51, level(b) * 51);
+    if distance((grey, grey, grey)) < distance(cube_rgb) {
+        (216 + grey_nb) as u8
+    } else {
+        cube
+    }
+}
+
+/// Packs variable-size codes, least significant bit first.
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    nb_bits: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u8) {
+        self.buffer |= (code as u32) << self.nb_bits;
+        self.nb_bits += size;
+        while self.nb_bits >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.nb_bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.nb_bits > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+fn lzw_encode(indices: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter {
+        bytes: Vec::new(),
+        buffer: 0,
+        nb_bits: 0,
+    };
+    let mut table = HashMap::<(u16, u8), u16>::new();
+    let mut code_size = MIN_CODE_SIZE + 1;
+    let mut next_code = END_CODE + 1;
+    writer.write(CLEAR_CODE, code_size);
+
+    let mut prefix = None;
+    for &index in indices {
+        let current = match prefix {
+            None => {
+                prefix = Some(index as u16);
+                continue;
+            }
+            Some(current) => current,
+        };
+        if let Some(&code) = table.get(&(current, index)) {
+            prefix = Some(code);
+            continue;
+        }
+        writer.write(current, code_size);
+        if next_code < 1 << MAX_CODE_SIZE {
+            table.insert((current, index), next_code);
+            next_code += 1;
+            // The decoder adds its entries one code late.
+            if next_code > 1 << code_size && code_size < MAX_CODE_SIZE {
+                code_size += 1;
+            }
+        } else {
+            writer.write(CLEAR_CODE, code_size);
+            table.clear();
+            code_size = MIN_CODE_SIZE + 1;
+            next_code = END_CODE + 1;
+        }
+        prefix = Some(index as u16);
+    }
+    if let Some(current) = prefix {
+        writer.write(current, code_size);
+    }
+    writer.write(END_CODE, code_size);
+    writer.finish()
+}
+
+pub struct GifEncoder<W: Write> {
+    writer: W,
+    width: u16,
+    height: u16,
+}
+
+impl<W: Write> GifEncoder<W> {
+    /// Writes the header of an animation looping forever.
+    pub fn new(mut writer: W, width: u16, height: u16) -> io::Result<GifEncoder<W>> {
+        writer.write_all(b"GIF89a")?;
+        writer.write_all(&width.to_le_bytes())?;
+        writer.write_all(&height.to_le_bytes())?;
+        // A global palette of 256 colours.
+        writer.write_all(&[0xf7, 0, 0])?;
+        writer.write_all(&palette())?;
+        writer.write_all(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+        Ok(GifEncoder {
+            writer,
+            width,
+            height,
+        })
+    }
+
+    /// Adds a frame of `width * height` RGB pixels, shown for `delay`
+    /// hundredths of a second.
+    pub fn add_frame(&mut self, rgb: &[u8], delay: u16) -> io::Result<()> {
+        let indices = rgb
+            .chunks(3)
+            .take(self.width as usize * self.height as usize)
+            .map(|pixel| palette_index((pixel[0], pixel[1], pixel[2])))
+            .collect::<Vec<_>>();
+        let delay = delay.to_le_bytes();
+        self.writer
+            .write_all(&[0x21, 0xf9, 4, 0, delay[0], delay[1], 0, 0])?;
+        self.writer.write_all(&[0x2c, 0, 0, 0, 0])?;
+        self.writer.write_all(&self.width.to_le_bytes())?;
+        self.writer.write_all(&self.height.to_le_bytes())?;
+        self.writer.write_all(&[0, MIN_CODE_SIZE])?;
+        for block in lzw_encode(&indices).chunks(255) {
+            self.writer.write_all(&[block.len() as u8])?;
+            self.writer.write_all(block)?;
+        }
+        self.writer.write_all(&[0])
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.write_all(&[0x3b])?;
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,137 @@
+//! The best scores and numbers of lines of the solo games, saved as two
+//! lines of numbers, best first:
+//!
+//! ```text
+//! 5400 3200 1200
+//! 62 40 18
+//! ```
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+pub const HIGHSCORE_FILE: &str = "scores.txt";
+pub const NB_HIGHSCORES: usize = 5;
+
+/// Puts `value` at its rank in `ranking`, sorted best first, keeping the
+/// `NB_HIGHSCORES` best entries. Returns whether it made it to the ranking.
+pub fn insert(ranking: &mut Vec<u32>, value: u32) -> bool {
+    // Ties go after the entries already there.
+    let rank = ranking.partition_point(|entry| *entry >= value);
+    if rank >= NB_HIGHSCORES {
+        return false;
+    }
+    ranking.insert(rank, value);
+    ranking.truncate(NB_HIGHSCORES);
+    true
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Highscores {
+    pub scores: Vec<u32>,
+    pub lines: Vec<u32>,
+}
+
+impl Highscores {
+    /// Adds a game. Returns whether its score and its number of lines made
+    /// it to the rankings.
+    pub fn add(&mut self, score: u32, lines: u32) -> (bool, bool) {
+        (
+            insert(&mut self.scores, score),
+            insert(&mut self.lines, lines),
+        )
+    }
+
+    /// Files written by hand, or before they were kept sorted, are ranked
+    /// again.
+    pub fn parse(content: &str) -> Option<Highscores> {
+        let mut lines = content.lines();
+        let mut ranking = || -> Option<Vec<u32>> {
+            let mut ranking = lines
+                .next()?
+                .split_whitespace()
+                .map(|nb| nb.parse::<u32>().ok())
+                .collect::<Option<Vec<u32>>>()?;
+            ranking.sort_unstable_by(|a, b| b.cmp(a));
+            ranking.truncate(NB_HIGHSCORES);
+            Some(ranking)
+        };
+        Some(Highscores {
+            scores: ranking()?,
+            lines: ranking()?,
+        })
+    }
+
+    pub fn load(file_name: &str) -> Option<Highscores> {
+        let mut f = File::open(file_name).ok()?;
+        let mut content = String::new();
+        f.read_to_string(&mut content).ok()?;
+        Highscores::parse(&content)
+    }
+
+    pub fn save(&self, file_name: &str) -> io::Result<()> {
+        let mut f = File::create(file_name)?;
+        f.write_all(self.to_string().as_bytes())
+    }
+}
+
+impl fmt::Display for Highscores {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for ranking in [&self.scores, &self.lines] {
+            let ranking = ranking.iter().map(|nb| nb.to_string()).collect::<Vec<_>>();
+            writeln!(f, "{}", ranking.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_keeps_the_ranking_sorted() {
+        let mut ranking = Vec::new();
+        for value in [30, 10, 50, 20, 40] {
+            assert!(insert(&mut ranking, value));
+        }
+        assert_eq!(ranking, [50, 40, 30, 20, 10]);
+        assert!(insert(&mut ranking, 35));
+        assert_eq!(ranking, [50, 40, 35, 30, 20]);
+        assert!(insert(&mut ranking, 60));
+        assert_eq!(ranking, [60, 50, 40, 35, 30]);
+    }
+
+    #[test]
+    fn insert_rejects_values_below_a_full_ranking() {
+        let mut ranking = vec![50, 40, 30, 20, 10];
+        assert!(!insert(&mut ranking, 5));
+        // A tie with the last entry doesn't push it out.
+        assert!(!insert(&mut ranking, 10));
+        assert_eq!(ranking, [50, 40, 30, 20, 10]);
+    }
+
+    #[test]
+    fn parse_sorts_and_keeps_every_entry() {
+        let highscores = Highscores::parse("10 300 20 5 40 60\n7 9\n").unwrap();
+        assert_eq!(highscores.scores, [300, 60, 40, 20, 10]);
+        assert_eq!(highscores.lines, [9, 7]);
+        assert_eq!(Highscores::parse(&highscores.to_string()), Some(highscores));
+    }
+
+    #[test]
+    fn parse_rejects_broken_files() {
+        assert_eq!(Highscores::parse("10 20\n"), None);
+        assert_eq!(Highscores::parse("10 x\n1 2\n"), None);
+    }
+
+    #[test]
+    fn add_reports_each_ranking() {
+        let mut highscores = Highscores {
+            scores: vec![500, 400, 300, 200, 100],
+            lines: vec![5, 4, 3, 2, 1],
+        };
+        assert_eq!(highscores.add(50, 3), (false, true));
+        assert_eq!(highscores.lines, [5, 4, 3, 3, 2]);
+    }
+}
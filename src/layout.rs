@@ -0,0 +1,129 @@
+//! Where everything goes on screen, computed from the window size.
+//!
+//! The game is laid out on a grid of `BOARD_COLUMNS` x `BOARD_ROWS` cells per
+//! player, scaled to the largest cell size fitting the window. What's left
+//! around it is letterboxing.
+
+use sdl2::rect::Rect;
+
+/// Cells per player, including the margins around the grid.
+pub const BOARD_COLUMNS: u32 = 15;
+pub const BOARD_ROWS: u32 = 20;
+pub const GRID_COLUMNS: u32 = 10;
+pub const GRID_ROWS: u32 = 16;
+/// Cell size of the initial window.
+pub const DEFAULT_CELL: u32 = 40;
+
+/// Where one player's board is drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BoardLayout {
+    pub grid: Rect,
+    pub border: Rect,
+    pub garbage_meter: Rect,
+    /// Top-left corner of the hold piece, drawn with half cells.
+    pub preview: (i32, i32),
+    /// Top-left corner of the score, lines and level.
+    pub hud: (i32, i32),
+    /// Top-left corner of the next pieces, drawn with half cells one under
+    /// the other.
+    pub next: (i32, i32),
+}
+
+impl BoardLayout {
+    /// The same board moved by `(x, y)` pixels.
+    pub fn shifted(&self, (x, y): (i32, i32)) -> BoardLayout {
+        let shift = |rect: Rect| Rect::new(rect.x() + x, rect.y() + y, rect.width(), rect.height());
+        BoardLayout {
+            grid: shift(self.grid),
+            border: shift(self.border),
+            garbage_meter: shift(self.garbage_meter),
+            preview: (self.preview.0 + x, self.preview.1 + y),
+            hud: (self.hud.0 + x, self.hud.1 + y),
+            next: (self.next.0 + x, self.next.1 + y),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Layout {
+    pub cell: u32,
+    /// The part of the window the game is drawn in.
+    pub viewport: Rect,
+    pub boards: Vec<BoardLayout>,
+}
+
+impl Layout {
+    pub fn new(nb_players: usize, (width, height): (u32, u32)) -> Layout {
+        let columns = BOARD_COLUMNS * nb_players.max(1) as u32;
+        let cell = (width / columns).min(height / BOARD_ROWS).max(1);
+        let viewport = Rect::new(
+            (width as i32 - (columns * cell) as i32) / 2,
+            (height as i32 - (BOARD_ROWS * cell) as i32) / 2,
+            columns * cell,
+            BOARD_ROWS * cell,
+        );
+
+        let border = (cell / 4).max(1);
+        let boards = (0..nb_players)
+            .map(|nb| {
+                let grid = Rect::new(
+                    viewport.x()
+                        + (nb as u32 * BOARD_COLUMNS * cell) as i32
+                        + ((BOARD_COLUMNS - GRID_COLUMNS) * cell / 2) as i32,
+                    viewport.y() + ((BOARD_ROWS - GRID_ROWS) * cell / 2) as i32,
+                    GRID_COLUMNS * cell,
+                    GRID_ROWS * cell,
+                );
+                let right = grid.right() + (cell * 3 / 8) as i32;
+                BoardLayout {
+                    grid,
+                    border: Rect::new(
+                        grid.x() - border as i32,
+                        grid.y() - border as i32,
+                        grid.width() + border * 2,
+                        grid.height() + border * 2,
+                    ),
+                    garbage_meter: Rect::new(
+                        grid.x() - (cell * 3 / 4) as i32,
+                        grid.y(),
+                        (cell * 3 / 10).max(1),
+                        grid.height(),
+                    ),
+                    preview: (right, grid.y()),
+                    hud: (right, grid.y() + (cell * 2 + cell / 2) as i32),
+                    next: (right, grid.y() + (cell * 5 + cell / 2) as i32),
+                }
+            })
+            .collect();
+        Layout {
+            cell,
+            viewport,
+            boards,
+        }
+    }
+
+    /// Size of the window at the default scale.
+    pub fn default_size(nb_players: usize) -> (u32, u32) {
+        (
+            BOARD_COLUMNS * DEFAULT_CELL * nb_players as u32,
+            BOARD_ROWS * DEFAULT_CELL,
+        )
+    }
+
+    /// The case of the grid of player `nb` under the pixel `(x, y)`.
+    pub fn case_at(&self, nb: usize, (x, y): (i32, i32)) -> Option<(usize, usize)> {
+        let grid = self.boards.get(nb)?.grid;
+        if !grid.contains_point((x, y)) {
+            return None;
+        }
+        Some((
+            ((x - grid.x()) / self.cell as i32) as usize,
+            ((y - grid.y()) / self.cell as i32) as usize,
+        ))
+    }
+
+    /// Point size of the HUD text, which scales with the cells.
+    pub fn font_size(&self) -> u16 {
+        (self.cell * 2 / 5).max(6) as u16
+    }
+}
@@ -0,0 +1,27 @@
+extern crate rand;
+extern crate serde;
+extern crate serde_json;
+
+pub mod bitboard;
+pub mod bot;
+pub mod delays;
+pub mod editor;
+pub mod effects;
+pub mod fumen;
+pub mod game;
+pub mod highscores;
+pub mod net;
+pub mod options;
+pub mod pieces;
+pub mod polyomino;
+pub mod replay;
+pub mod sandbox;
+pub mod save;
+pub mod session;
+pub mod solver;
+pub mod stats;
+pub mod tbp;
+#[cfg(test)]
+mod test_utils;
+pub mod theme;
+pub mod versus;
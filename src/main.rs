@@ -1,654 +1,623 @@
-extern crate rand;
 extern crate sdl2;
-
-use sdl2::event::Event;
+extern crate tetris;
+
+mod audio;
+mod edit_events;
+mod export;
+mod gif;
+mod layout;
+mod render;
+mod screenshot;
+mod skin;
+
+use sdl2::controller::{Button, GameController};
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
-use sdl2::rect::Rect;
-use sdl2::render::{Canvas, Texture, TextureCreator};
-use sdl2::video::{Window, WindowContext};
-
-use std::fs::File;
-use std::io::{self, Read, Write};
+use sdl2::render::{Canvas, RenderTarget, TextureCreator};
+use sdl2::ttf::Sdl2TtfContext;
+use sdl2::video::{FullscreenType, Window};
+
+use tetris::editor::Edit;
+use tetris::effects::EffectSettings;
+use tetris::game::{Action, Tetris, TICKS_PER_SECOND};
+use tetris::highscores::{Highscores, HIGHSCORE_FILE};
+use tetris::options::{self, arg_value, LocalGame};
+use tetris::replay::Replay;
+use tetris::save::{self, SAVE_FILE};
+use tetris::session::{BotPlayer, Brain, Keymap, Session};
+use tetris::stats::{GameRecord, RECORD_FILE};
+use tetris::theme::Theme;
+use tetris::versus::Versus;
+
+use audio::{Audio, Sound, Volumes};
+use edit_events::{edit_from_event, CaseAt};
+use export::{ExportOptions, Format, DEFAULT_FPS};
+use layout::Layout;
+use render::{Renderer, Text, Textures, FONT_FILE};
+use screenshot::Offscreen;
+
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
 use std::thread::sleep;
-use std::time::{Duration, SystemTime};
-
-const TETRIS_HEIGHT: usize = 40;
-const HIGHSCORE_FILE: &'static str = "scores.txt";
-const LEVEL_TIMES: [u32; 10] = [1000, 850, 700, 600, 500, 400, 300, 250, 221, 190];
-const LEVEL_LINES: [u32; 10] = [20, 40, 60, 80, 100, 120, 140, 160, 180, 200];
-const NB_HIGHSCORES: usize = 5;
-
-#[derive(Clone, Copy)]
-enum TextureColor {
-    Green,
-    Blue,
-    Red,
-    Black,
-    White,
-}
-
-type Piece = Vec<Vec<u8>>;
-type States = Vec<Piece>;
-
-trait TetriminoGenerator {
-    fn new() -> Tetrimino;
-}
-
-struct TetriminoI;
-
-impl TetriminoGenerator for TetriminoI {
-    fn new() -> Tetrimino {
-        Tetrimino {
-            states: vec![
-                vec![
-                    vec![1, 1, 1, 1],
-                    vec![0, 0, 0, 0],
-                    vec![0, 0, 0, 0],
-                    vec![0, 0, 0, 0],
-                ],
-                vec![
-                    vec![0, 1, 0, 0],
-                    vec![0, 1, 0, 0],
-                    vec![0, 1, 0, 0],
-                    vec![0, 1, 0, 0],
-                ],
-            ],
-            x: 4,
-            y: 0,
-            current_state: 0,
-        }
+use std::time::{Duration, Instant};
+
+const SOLO_KEYMAP: Keymap<Keycode> = Keymap {
+    left: Keycode::Left,
+    right: Keycode::Right,
+    soft_drop: Keycode::Down,
+    rotate: Keycode::Up,
+    hard_drop: Keycode::Space,
+    hold: Keycode::C,
+};
+
+const VERSUS_KEYMAPS: [Keymap<Keycode>; 2] = [
+    Keymap {
+        left: Keycode::A,
+        right: Keycode::D,
+        soft_drop: Keycode::S,
+        rotate: Keycode::W,
+        hard_drop: Keycode::Space,
+        hold: Keycode::LShift,
+    },
+    Keymap {
+        left: Keycode::Left,
+        right: Keycode::Right,
+        soft_drop: Keycode::Down,
+        rotate: Keycode::Up,
+        hard_drop: Keycode::Return,
+        hold: Keycode::RShift,
+    },
+];
+
+fn controller_action(button: Button) -> Option<Action> {
+    match button {
+        Button::DPadLeft => Some(Action::Left),
+        Button::DPadRight => Some(Action::Right),
+        Button::DPadDown => Some(Action::SoftDrop),
+        Button::A | Button::B => Some(Action::Rotate),
+        Button::DPadUp => Some(Action::HardDrop),
+        Button::LeftShoulder | Button::RightShoulder => Some(Action::Hold),
+        _ => None,
     }
 }
 
-struct TetriminoJ;
-
-impl TetriminoGenerator for TetriminoJ {
-    fn new() -> Tetrimino {
-        Tetrimino {
-            states: vec![
-                vec![
-                    vec![2, 2, 2, 0],
-                    vec![2, 0, 0, 0],
-                    vec![0, 0, 0, 0],
-                    vec![0, 0, 0, 0],
-                ],
-                vec![
-                    vec![2, 2, 0, 0],
-                    vec![0, 2, 0, 0],
-                    vec![0, 2, 0, 0],
-                    vec![0, 0, 0, 0],
-                ],
-                vec![
-                    vec![0, 0, 2, 0],
-                    vec![2, 2, 2, 0],
-                    vec![0, 0, 0, 0],
-                    vec![0, 0, 0, 0],
-                ],
-                vec![
-                    vec![2, 0, 0, 0],
-                    vec![2, 0, 0, 0],
-                    vec![2, 2, 0, 0],
-                    vec![0, 0, 0, 0],
-                ],
-            ],
-            x: 4,
-            y: 0,
-            current_state: 0,
-        }
-    }
+struct Controls {
+    keymap: &'static Keymap<Keycode>,
+    controller: Option<GameController>,
 }
 
-struct TetriminoL;
-
-impl TetriminoGenerator for TetriminoL {
-    fn new() -> Tetrimino {
-        Tetrimino {
-            states: vec![
-                vec![
-                    vec![3, 3, 3, 0],
-                    vec![0, 0, 3, 0],
-                    vec![0, 0, 0, 0],
-                    vec![0, 0, 0, 0],
-                ],
-                vec![
-                    vec![0, 3, 0, 0],
-                    vec![0, 3, 0, 0],
-                    vec![3, 3, 0, 0],
-                    vec![0, 0, 0, 0],
-                ],
-                vec![
-                    vec![3, 0, 0, 0],
-                    vec![3, 3, 3, 0],
-                    vec![0, 0, 0, 0],
-                    vec![0, 0, 0, 0],
-                ],
-                vec![
-                    vec![3, 3, 0, 0],
-                    vec![3, 0, 0, 0],
-                    vec![3, 0, 0, 0],
-                    vec![0, 0, 0, 0],
-                ],
-            ],
-            x: 4,
-            y: 0,
-            current_state: 0,
+impl Controls {
+    fn new(keymap: &'static Keymap<Keycode>) -> Controls {
+        Controls {
+            keymap,
+            controller: None,
         }
     }
 }
 
-struct TetriminoO;
-
-impl TetriminoGenerator for TetriminoO {
-    fn new() -> Tetrimino {
-        Tetrimino {
-            states: vec![vec![
-                vec![4, 4, 0, 0],
-                vec![4, 4, 0, 0],
-                vec![0, 0, 0, 0],
-                vec![0, 0, 0, 0],
-            ]],
-            x: 5,
-            y: 0,
-            current_state: 0,
-        }
-    }
+/// Runs the loop at `TICKS_PER_SECOND`, whatever the time spent drawing.
+struct Pacer {
+    next_tick: Instant,
 }
 
-struct TetriminoS;
-
-impl TetriminoGenerator for TetriminoS {
-    fn new() -> Tetrimino {
-        Tetrimino {
-            states: vec![
-                vec![
-                    vec![0, 5, 5, 0],
-                    vec![5, 5, 0, 0],
-                    vec![0, 0, 0, 0],
-                    vec![0, 0, 0, 0],
-                ],
-                vec![
-                    vec![0, 5, 0, 0],
-                    vec![0, 5, 5, 0],
-                    vec![0, 0, 5, 0],
-                    vec![0, 0, 0, 0],
-                ],
-            ],
-            x: 4,
-            y: 0,
-            current_state: 0,
+impl Pacer {
+    fn new() -> Pacer {
+        Pacer {
+            next_tick: Instant::now(),
         }
     }
-}
 
-struct TetriminoZ;
-
-impl TetriminoGenerator for TetriminoZ {
-    fn new() -> Tetrimino {
-        Tetrimino {
-            states: vec![
-                vec![
-                    vec![6, 6, 0, 0],
-                    vec![0, 6, 6, 0],
-                    vec![0, 0, 0, 0],
-                    vec![0, 0, 0, 0],
-                ],
-                vec![
-                    vec![0, 0, 6, 0],
-                    vec![0, 6, 6, 0],
-                    vec![0, 6, 0, 0],
-                    vec![0, 0, 0, 0],
-                ],
-            ],
-            x: 4,
-            y: 0,
-            current_state: 0,
-        }
-    }
-}
-
-struct TetriminoT;
-
-impl TetriminoGenerator for TetriminoT {
-    fn new() -> Tetrimino {
-        Tetrimino {
-            states: vec![
-                vec![
-                    vec![7, 7, 7, 0],
-                    vec![0, 7, 0, 0],
-                    vec![0, 0, 0, 0],
-                    vec![0, 0, 0, 0],
-                ],
-                vec![
-                    vec![0, 7, 0, 0],
-                    vec![7, 7, 0, 0],
-                    vec![0, 7, 0, 0],
-                    vec![0, 0, 0, 0],
-                ],
-                vec![
-                    vec![0, 7, 0, 0],
-                    vec![7, 7, 7, 0],
-                    vec![0, 0, 0, 0],
-                    vec![0, 0, 0, 0],
-                ],
-                vec![
-                    vec![0, 7, 0, 0],
-                    vec![0, 7, 7, 0],
-                    vec![0, 7, 0, 0],
-                    vec![0, 0, 0, 0],
-                ],
-            ],
-            x: 4,
-            y: 0,
-            current_state: 0,
+    fn wait(&mut self) {
+        self.next_tick += Duration::from_secs(1) / TICKS_PER_SECOND;
+        let now = Instant::now();
+        if self.next_tick > now {
+            sleep(self.next_tick - now);
+        } else {
+            // Too late already, the next ticks aren't hurried to catch up.
+            self.next_tick = now;
         }
     }
 }
 
-struct Tetrimino {
-    states: States,
-    x: isize,
-    y: usize,
-    current_state: u8,
-}
-
-fn create_texture_rect<'a>(
-    canvas: &mut Canvas<Window>,
-    texture_creator: &'a TextureCreator<WindowContext>,
-    r: u8,
-    g: u8,
-    b: u8,
-    size_w: u32,
-    size_h: u32,
-) -> Option<Texture<'a>> {
-    if let Ok(mut square_texture) = texture_creator.create_texture_target(None, size_w, size_h) {
-        canvas
-            .with_texture_canvas(&mut square_texture, |texture| {
-                //match color {
-                // For now, TextureColor only handles two colors.
-                //TextureColor::Green => texture.set_draw_color(Color::RGB(0, 255, 0)),
-                //TextureColor::Blue => texture.set_draw_color(Color::RGB(0, 0, 255)),
-                //TextureColor::Red => texture.set_draw_color(Color::RGB(255, 0, 0)),
-                //TextureColor::Black => texture.set_draw_color(Color::RGB(0, 0, 0)),
-                //TextureColor::White => texture.set_draw_color(Color::RGB(255, 255, 255)),
-                //}
-                texture.set_draw_color(Color::RGB(r, g, b));
-                texture.clear();
-            })
-            .expect("Failed to color a texture");
-        Some(square_texture)
-    } else {
-        None
-    }
+/// What the player asked for besides playing.
+#[derive(Default)]
+struct Commands {
+    quit: bool,
+    next_theme: bool,
+    toggle_fullscreen: bool,
+    resized: bool,
+    screenshot: bool,
+    suspend: bool,
+    /// Changes to the sandbox game.
+    edits: Vec<Edit>,
 }
 
-//fn create_texture_from_text<'a>(
-//    texture_creator: &'a TextureCreator<WindowContext>,
-//    font: &sdl2::ttf::Font,
-//    text: &str,
-//    r: u8, g: u8, b: u8,) -> Option<Texture<'a>> {
-//    if let Ok(surface) = font.render(text).blended(Color::RGB(r, g, b)) {
-//        texture_creator.create_texture_from_surface(&surface).ok()
-//    } else {
-//        None
-//    }
-//}
-
-impl Tetrimino {
-    fn rotate(&mut self, game_map: &[Vec<u8>]) {
-        let mut tmp_state = self.current_state + 1;
-        if tmp_state as usize >= self.states.len() {
-            tmp_state = 0;
-        }
-        let x_pos = [0, -1, 1, -2, 2, -3];
-        for x in x_pos.iter() {
-            if self.test_position(game_map, tmp_state as usize, self.x + x, self.y) == true {
-                self.current_state = tmp_state;
-                self.x += *x;
+/// Translates the pending events into actions, as `(player, action)`, and
+/// into edits of the game if `editing`, which gives the case of the map
+/// under a point of the window.
+fn handle_events(
+    controls: &[Controls],
+    commands: &mut Commands,
+    editing: Option<&CaseAt<'_>>,
+    event_pump: &mut sdl2::EventPump,
+) -> Vec<(usize, Action)> {
+    let mut actions = Vec::new();
+
+    for event in event_pump.poll_iter() {
+        if let Some(edit) = editing.and_then(|case_at| edit_from_event(&event, case_at)) {
+            commands.edits.push(edit);
+            continue;
+        }
+        match event {
+            Event::Quit { .. }
+            | Event::KeyDown {
+                keycode: Some(Keycode::Escape),
+                ..
+            } => {
+                commands.quit = true;
                 break;
             }
-        }
-    }
-
-    fn test_position(&self, game_map: &[Vec<u8>], tmp_state: usize, x: isize, y: usize) -> bool {
-        for shift_y in 0..4 {
-            for shift_x in 0..4 {
-                let x = x + shift_x;
-                if self.states[tmp_state][shift_y][shift_x as usize] != 0
-                    && (y + shift_y >= game_map.len()
-                        || x < 0
-                        || x as usize >= game_map[y + shift_y].len()
-                        || game_map[y + shift_y][x as usize] != 0)
-                {
-                    return false;
+            Event::KeyDown {
+                keycode: Some(Keycode::F2),
+                ..
+            } => commands.next_theme = true,
+            Event::KeyDown {
+                keycode: Some(Keycode::F5),
+                ..
+            } => {
+                commands.suspend = true;
+                break;
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::F11),
+                ..
+            } => commands.toggle_fullscreen = true,
+            Event::KeyDown {
+                keycode: Some(Keycode::F12),
+                ..
+            } => commands.screenshot = true,
+            Event::Window {
+                win_event: WindowEvent::Resized(..) | WindowEvent::SizeChanged(..),
+                ..
+            } => commands.resized = true,
+            Event::KeyDown {
+                keycode: Some(keycode),
+                ..
+            } => {
+                for (nb, control) in controls.iter().enumerate() {
+                    if let Some(action) = control.keymap.action(keycode) {
+                        actions.push((nb, action));
+                    }
                 }
             }
+            Event::ControllerButtonDown { which, button, .. } => {
+                for (nb, control) in controls.iter().enumerate() {
+                    let is_owner = match control.controller {
+                        Some(ref controller) => controller.instance_id() == which,
+                        None => false,
+                    };
+                    if let (true, Some(action)) = (is_owner, controller_action(button)) {
+                        actions.push((nb, action));
+                    }
+                }
+            }
+            _ => {}
         }
-        return true;
     }
+    actions
+}
 
-    fn test_current_position(&self, game_map: &[Vec<u8>]) -> bool {
-        self.test_position(game_map, self.current_state as usize, self.x, self.y)
+fn print_game_information(tetris: &Tetris) {
+    let mut highscores = Highscores::load(HIGHSCORE_FILE).unwrap_or_default();
+    let (new_highest_highscore, new_highest_lines_sent) =
+        highscores.add(tetris.score, tetris.nb_lines);
+    if new_highest_highscore || new_highest_lines_sent {
+        if let Err(e) = highscores.save(HIGHSCORE_FILE) {
+            eprintln!("Couldn't save the highscores: {}", e);
+        }
     }
-
-    fn change_position(&mut self, game_map: &[Vec<u8>], new_x: isize, new_y: usize) -> bool {
-        if self.test_position(game_map, self.current_state as usize, new_x, new_y) == true {
-            self.x = new_x as isize;
-            self.y = new_y;
-            true
+    println!("Game over...");
+    println!(
+        "Score:           {}{}",
+        tetris.score,
+        if new_highest_highscore {
+            " [NEW HIGHSCORE]"
+        } else {
+            ""
+        }
+    );
+    println!(
+        "Number of lines: {}{}",
+        tetris.nb_lines,
+        if new_highest_lines_sent {
+            " [NEW HIGHSCORE]"
         } else {
-            false
+            ""
         }
+    );
+    println!("Current level:   {}", tetris.current_level);
+    for line in tetris.statistics.summary(&tetris.pieces) {
+        println!("{}", line);
+    }
+    if let Err(e) = GameRecord::new(tetris).save(RECORD_FILE) {
+        eprintln!("{}", e);
     }
 }
 
-struct Tetris {
-    game_map: Vec<Vec<u8>>,
-    current_level: u32,
-    score: u32,
-    nb_lines: u32,
-    current_piece: Option<Tetrimino>,
+fn print_versus_result(games: &[&Tetris], loser: Option<usize>) {
+    println!("Game over...");
+    for (nb, tetris) in games.iter().enumerate() {
+        println!(
+            "Player {}: {} points, {} lines{}",
+            nb + 1,
+            tetris.score,
+            tetris.nb_lines,
+            match loser {
+                Some(loser) if loser != nb => " [WINNER]",
+                _ => "",
+            }
+        );
+        for line in tetris.statistics.summary(&tetris.pieces) {
+            println!("    {}", line);
+        }
+    }
 }
 
-impl Tetris {
-    fn new() -> Tetris {
-        let mut game_map = Vec::new();
-        for _ in 0..16 {
-            game_map.push(vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
-        }
-        Tetris {
-            game_map: game_map,
-            current_level: 1,
-            score: 0,
-            nb_lines: 0,
-            current_piece: None,
+/// Reads `--sfx-volume N` and `--music-volume N`, from 0 to 100.
+fn volumes() -> Volumes {
+    let mut volumes = Volumes::default();
+    for (name, volume) in [
+        ("--sfx-volume", &mut volumes.sfx),
+        ("--music-volume", &mut volumes.music),
+    ] {
+        if let Some(value) = arg_value(name).and_then(|value| value?.parse::<u8>().ok()) {
+            *volume = value.min(100);
         }
     }
+    volumes
+}
 
-    fn update_score(&mut self, to_add: u32) {
-        self.score += to_add;
+fn play_sound(audio: Option<&Audio>, sound: Sound) {
+    if let Some(audio) = audio {
+        audio.play(sound);
     }
+}
 
-    fn increase_level(&mut self) {
-        self.current_level += 1;
+/// Plays the game over sound and lets it finish before the game exits.
+fn play_game_over(audio: Option<&Audio>) {
+    if let Some(audio) = audio {
+        audio.stop_music();
+        audio.play(Sound::GameOver);
+        sleep(Duration::from_secs(1));
     }
+}
 
-    fn increase_line(&mut self) {
-        self.nb_lines += 1;
-        if self.nb_lines > LEVEL_LINES[self.current_level as usize - 1] {
-            self.increase_level();
-        }
+/// The heading of each player's game over summary.
+fn game_over_headings(nb_players: usize, loser: Option<usize>) -> Vec<String> {
+    if nb_players == 1 {
+        return vec!["GAME OVER".to_owned()];
     }
+    (0..nb_players)
+        .map(|nb| match loser {
+            Some(loser) if loser != nb => format!("PLAYER {} WINS", nb + 1),
+            _ => format!("PLAYER {}", nb + 1),
+        })
+        .collect()
+}
 
-    fn check_lines(&mut self) {
-        let mut y = 0;
-        let mut score_add = 0;
-
-        while y < self.game_map.len() {
-            let mut complete = true;
-
-            for x in &self.game_map[y] {
-                if *x == 0 {
-                    complete = false;
-                    break;
+/// Shows the game over screen until a key or a button is pressed, or the
+/// window is closed. The keys pressed while the game over sound plays are
+/// left out, as they were meant for the game.
+fn show_game_over(
+    (canvas, renderer): (&mut Canvas<Window>, &mut Renderer<Window>),
+    text: Option<&Text>,
+    (games, loser): (&[&Tetris], Option<usize>),
+    audio: Option<&Audio>,
+    event_pump: &mut sdl2::EventPump,
+) {
+    let headings = game_over_headings(games.len(), loser);
+    let mut draw = |canvas: &mut Canvas<Window>| {
+        let size = canvas.output_size().unwrap_or((0, 0));
+        let layout = Layout::new(games.len(), size);
+        let drawn = renderer.draw_game_over(canvas, &layout, text, games, &headings);
+        canvas.present();
+        drawn
+    };
+    if let Err(e) = draw(canvas) {
+        eprintln!("Couldn't draw the game over screen: {}", e);
+        return;
+    }
+    play_game_over(audio);
+    let ignored = Instant::now() + Duration::from_millis(500);
+    loop {
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } => return,
+                Event::KeyDown { repeat: false, .. } | Event::ControllerButtonDown { .. }
+                    if Instant::now() >= ignored =>
+                {
+                    return
                 }
+                _ => {}
             }
-            if complete == true {
-                score_add += self.current_level;
-                self.game_map.remove(y);
-                y -= 1;
-            }
-            y += 1;
-        }
-        if self.game_map.len() == 0 {
-            // A "tetris"!
-            score_add += 1000;
         }
-        self.update_score(score_add);
-        while self.game_map.len() < 16 {
-            self.increase_line();
-            self.game_map.insert(0, vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        if let Err(e) = draw(canvas) {
+            eprintln!("Couldn't draw the game over screen: {}", e);
+            return;
         }
+        sleep(Duration::from_millis(50));
     }
+}
 
-    fn create_new_tetrimino(&self) -> Tetrimino {
-        static mut PREV: u8 = 7;
-        let mut rand_nb = rand::random::<u8>() % 7;
-        if unsafe { PREV } == rand_nb {
-            rand_nb = rand::random::<u8>() % 7;
-        }
-        unsafe {
-            PREV = rand_nb;
-        }
-        match rand_nb {
-            0 => TetriminoI::new(),
-            1 => TetriminoJ::new(),
-            2 => TetriminoL::new(),
-            3 => TetriminoO::new(),
-            4 => TetriminoS::new(),
-            5 => TetriminoZ::new(),
-            6 => TetriminoT::new(),
-            _ => unreachable!(),
-        }
+fn toggle_fullscreen(canvas: &mut Canvas<Window>) {
+    let window = canvas.window_mut();
+    let fullscreen = match window.fullscreen_state() {
+        FullscreenType::Off => FullscreenType::Desktop,
+        _ => FullscreenType::Off,
+    };
+    if let Err(e) = window.set_fullscreen(fullscreen) {
+        eprintln!("Couldn't toggle fullscreen: {}", e);
     }
+}
 
-    fn make_permanent(&mut self) {
-        let mut to_add = 0;
-        if let Some(ref mut piece) = self.current_piece {
-            let mut shift_y = 0;
-
-            while shift_y < piece.states[piece.current_state as usize].len()
-                && piece.y + shift_y < self.game_map.len()
-            {
-                let mut shift_x = 0;
+fn next_theme<'a>(themes: &'a [Theme], theme: &Theme) -> &'a Theme {
+    let position = themes.iter().position(|known| known == theme).unwrap_or(0);
+    &themes[(position + 1) % themes.len()]
+}
 
-                while shift_x < piece.states[piece.current_state as usize][shift_y].len()
-                    && (piece.x + shift_x as isize)
-                        < self.game_map[piece.y + shift_y].len() as isize
-                {
-                    if piece.states[piece.current_state as usize][shift_y][shift_x] != 0 {
-                        let x = piece.x + shift_x as isize;
-                        self.game_map[piece.y + shift_y][x as usize] =
-                            piece.states[piece.current_state as usize][shift_y][shift_x];
-                    }
-                    shift_x += 1;
-                }
-                shift_y += 1;
-            }
-            to_add += self.current_level;
+/// Creates the textures of `theme`, with the blocks of the skin in
+/// `skin` if there's one. A skin that fails to load is dropped for good.
+fn load_textures<'a, T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    texture_creator: &'a TextureCreator<T::Context>,
+    theme: &Theme,
+    skin: &mut Option<PathBuf>,
+) -> Textures<'a> {
+    let mut textures = match Textures::new(canvas, texture_creator, theme) {
+        Ok(textures) => textures,
+        Err(e) => {
+            eprintln!("Couldn't create the textures: {}", e);
+            process::exit(1);
+        }
+    };
+    if let Some(ref directory) = *skin {
+        if let Err(e) = textures.apply_skin(canvas, texture_creator, directory) {
+            eprintln!("Couldn't load the skin, using flat colours: {}", e);
+            *skin = None;
         }
-        self.update_score(to_add);
-        self.check_lines();
-        self.current_piece = None;
     }
+    textures
 }
 
-fn handle_events(
-    tetris: &mut Tetris,
-    quit: &mut bool,
-    timer: &mut SystemTime,
-    event_pump: &mut sdl2::EventPump,
-) -> bool {
-    let mut make_permanent = false;
-    if let Some(ref mut piece) = tetris.current_piece {
-        let mut tmp_x = piece.x;
-        let mut tmp_y = piece.y;
+/// The HUD font at the size of `layout`, if TTF could be initialized.
+fn load_text<'a>(ttf_context: Option<&'a Sdl2TtfContext>, layout: &Layout) -> Option<Text<'a>> {
+    let size = layout.font_size();
+    let font = ttf_context?.load_font(FONT_FILE, size);
+    font.ok().map(|font| Text { font, size })
+}
 
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => {
-                    *quit = true;
-                    break;
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::Down),
-                    ..
-                } => {
-                    *timer = SystemTime::now();
-                    tmp_y += 1;
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::Right),
-                    ..
-                } => {
-                    tmp_x += 1;
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::Left),
-                    ..
-                } => {
-                    tmp_x -= 1;
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::Up),
-                    ..
-                } => {
-                    piece.rotate(&tetris.game_map);
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::Space),
-                    ..
-                } => {
-                    let x = piece.x;
-                    let mut y = piece.y;
-                    while piece.change_position(&tetris.game_map, x, y + 1) == true {
-                        y += 1;
-                    }
-                    make_permanent = true;
-                }
-                _ => {}
+/// Plays `ticks` ticks with the bot as the only input, so that the result
+/// is the same from one run to the next with `--seed`.
+fn simulate(session: &mut Session, bot_player: &mut Option<BotPlayer>, ticks: u64) {
+    while session.tick < ticks {
+        let mut actions = Vec::new();
+        if let Some(ref mut bot_player) = *bot_player {
+            if let Err(e) = bot_player.play(session, &mut actions) {
+                eprintln!("{}", e);
+                return;
             }
         }
-        if !make_permanent {
-            if piece.change_position(&tetris.game_map, tmp_x, tmp_y) == false && tmp_y != piece.y {
-                make_permanent = true;
-            }
+        let outcome = session.step(&actions);
+        if let Some(ref mut bot_player) = *bot_player {
+            bot_player.on_tick(&outcome);
+        }
+        if outcome.loser.is_some() {
+            return;
         }
     }
-    if make_permanent {
-        tetris.make_permanent();
-        *timer = SystemTime::now();
-    }
-    make_permanent
 }
 
-fn write_into_file(content: &str, file_name: &str) -> io::Result<()> {
-    let mut f = File::create(file_name)?;
-    f.write_all(content.as_bytes())
-}
-
-fn read_from_file(file_name: &str) -> io::Result<String> {
-    let mut f = File::open(file_name)?;
-    let mut content = String::new();
-    f.read_to_string(&mut content)?;
-    Ok(content)
-}
-
-fn slice_to_string(slice: &[u32]) -> String {
-    slice
-        .iter()
-        .map(|highscore| highscore.to_string())
-        .collect::<Vec<String>>()
-        .join(" ")
+/// Draws the games with a software renderer, without any window, and saves
+/// them as a PNG image.
+fn save_offscreen(
+    games: &[&Tetris],
+    versus: Option<&Versus>,
+    theme: &Theme,
+    skin: &mut Option<PathBuf>,
+    file_name: &str,
+) -> Result<(), String> {
+    let _image_context = sdl2::image::init(sdl2::image::INIT_PNG);
+    let mut offscreen = Offscreen::new(games.len(), Layout::default_size(games.len()))?;
+    let textures = load_textures(
+        &mut offscreen.canvas,
+        &offscreen.texture_creator,
+        theme,
+        skin,
+    );
+    let mut renderer = Renderer::new(&offscreen.texture_creator, textures);
+    let ttf_context = sdl2::ttf::init().ok();
+    let text = load_text(ttf_context.as_ref(), &offscreen.layout);
+    renderer.draw_frame(
+        &mut offscreen.canvas,
+        &offscreen.layout,
+        text.as_ref(),
+        games,
+        versus,
+        &[],
+    )?;
+    screenshot::save_png(&offscreen.canvas, file_name)
 }
 
-fn save_highscores_and_lines(highscores: &[u32], number_of_lines: &[u32]) -> bool {
-    let s_highscores = slice_to_string(highscores);
-    let s_number_of_lines = slice_to_string(number_of_lines);
-    write_into_file(
-        &format!("{}\n{}\n", s_highscores, s_number_of_lines),
-        HIGHSCORE_FILE,
-    )
-    .is_ok()
+/// Saves the frame drawn on `canvas`, before it's presented.
+fn take_screenshot(canvas: &Canvas<Window>) {
+    let file_name = screenshot::file_name();
+    match screenshot::save_png(canvas, &file_name) {
+        Ok(()) => println!("Saved {}", file_name),
+        Err(e) => eprintln!("Couldn't save the screenshot: {}", e),
+    }
 }
 
-fn line_to_slice(line: &str) -> Vec<u32> {
-    line.split(" ")
-        .filter_map(|nb| nb.parse::<u32>().ok())
-        .collect()
+/// "WIDTHxHEIGHT", e.g. "640x480".
+fn parse_size(size: &str) -> Option<(u32, u32)> {
+    let (width, height) = size.split_once('x')?;
+    Some((width.parse().ok()?, height.parse().ok()?))
 }
 
-fn load_highscores_and_lines() -> Option<(Vec<u32>, Vec<u32>)> {
-    if let Ok(content) = read_from_file(HIGHSCORE_FILE) {
-        let mut lines = content
-            .splitn(2, "\n")
-            .map(|line| line_to_slice(line))
-            .collect::<Vec<_>>();
-        if lines.len() == 2 {
-            let (lines_sent, highscores) = (lines.pop().unwrap(), lines.pop().unwrap());
-            Some((highscores, lines_sent))
-        } else {
-            None
+/// Saves the frames of the replay in `file_name`, with the `--format`,
+/// `--output`, `--size` and `--fps` options.
+fn export_replay(
+    file_name: &str,
+    (theme, skin): (&Theme, &mut Option<PathBuf>),
+    effect_settings: EffectSettings,
+) {
+    let replay = match Replay::load(file_name) {
+        Some(replay) => replay,
+        None => {
+            eprintln!("Couldn't read the replay {:?}", file_name);
+            process::exit(1);
+        }
+    };
+    let format = match arg_value("--format").and_then(|format| format).as_deref() {
+        None | Some("gif") => Format::Gif,
+        Some("png") => Format::Png,
+        Some(format) => {
+            eprintln!("Unknown format {:?}, expected gif or png", format);
+            process::exit(1);
+        }
+    };
+    let size = match arg_value("--size").and_then(|size| size) {
+        Some(size) => parse_size(&size).unwrap_or_else(|| {
+            eprintln!("Invalid size {:?}, expected WIDTHxHEIGHT", size);
+            process::exit(1);
+        }),
+        None => Layout::default_size(replay.nb_players),
+    };
+    let options = ExportOptions {
+        format,
+        output: arg_value("--output")
+            .and_then(|output| output)
+            .unwrap_or_else(|| match format {
+                Format::Gif => "replay.gif".to_owned(),
+                Format::Png => "frames".to_owned(),
+            }),
+        size,
+        fps: arg_value("--fps")
+            .and_then(|fps| fps?.parse().ok())
+            .unwrap_or(DEFAULT_FPS),
+    };
+    let theme = match replay.pieces {
+        Some(ref pieces) => theme.clone().with_piece_colours(pieces),
+        None => theme.clone(),
+    };
+    let _image_context = sdl2::image::init(sdl2::image::INIT_PNG);
+    match export::export(&replay, &options, (&theme, skin), effect_settings) {
+        Ok(nb_frames) => println!("Saved {} frames to {}", nb_frames, options.output),
+        Err(e) => {
+            eprintln!("Couldn't export the replay: {}", e);
+            process::exit(1);
         }
-    } else {
-        None
     }
 }
 
-fn update_vec(v: &mut Vec<u32>, value: u32) -> bool {
-    if v.len() < NB_HIGHSCORES {
-        v.push(value);
-        true
-    } else {
-        for entry in v.iter_mut() {
-            if value > *entry {
-                *entry = value;
-                return true;
+fn main() {
+    let pieces = Arc::new(options::load_pieces());
+    let mut brain = options::load_brain(&pieces);
+    if let (Some(brain), true) = (&mut brain, options::has_flag("--headless")) {
+        let mut tetris = Tetris::with_pieces(rand::random(), pieces);
+        match brain {
+            Brain::Builtin(ref bot) => {
+                bot.play(&mut tetris, u32::MAX);
+            }
+            Brain::External(ref mut bot) => {
+                if let Err(e) = bot.play(&mut tetris, u32::MAX) {
+                    eprintln!("{}", e);
+                }
             }
         }
-        false
+        print_game_information(&tetris);
+        return;
     }
-}
 
-fn print_game_information(tetris: &Tetris) {
-    let mut new_highest_highscore = true;
-    let mut new_highest_lines_sent = true;
-    if let Some((mut highscores, mut lines_sent)) = load_highscores_and_lines() {
-        new_highest_highscore = update_vec(&mut highscores, tetris.score);
-        new_highest_lines_sent = update_vec(&mut lines_sent, tetris.nb_lines);
-        if new_highest_highscore || new_highest_lines_sent {
-            save_highscores_and_lines(&highscores, &lines_sent);
-        }
+    let delays = options::load_delays();
+    let mut net_game = options::connect(&pieces, delays);
+    let pieces = match net_game {
+        Some(ref net_game) => net_game.players[0].pieces.clone(),
+        None => pieces,
+    };
+    let versus_mode = net_game.is_some() || options::has_flag("--versus");
+    let sandbox = options::sandbox_options(versus_mode);
+    let screenshot_tick = arg_value("--screenshot-at-tick");
+    // Only solo games played on screen can be suspended.
+    let resumed = if versus_mode
+        || sandbox.is_some()
+        || arg_value("--export-replay").is_some()
+        || screenshot_tick.is_some()
+    {
+        None
     } else {
-        save_highscores_and_lines(&[tetris.score], &[tetris.nb_lines]);
+        options::offer_resume()
+    };
+    let pieces = match resumed {
+        Some(ref tetris) => tetris.pieces.clone(),
+        None => pieces,
+    };
+
+    let effect_settings = options::load_effect_settings();
+    let themes = options::load_themes(&pieces);
+    let mut theme = &themes[0];
+    let mut skin = arg_value("--skin").map(|name| skin::skin_directory(&name.unwrap_or_default()));
+
+    if let Some(file_name) = arg_value("--export-replay") {
+        export_replay(
+            &file_name.unwrap_or_default(),
+            (theme, &mut skin),
+            effect_settings,
+        );
+        return;
     }
-    println!("Game over...");
-    println!(
-        "Score:           {}{}",
-        tetris.score,
-        if new_highest_highscore {
-            " [NEW HIGHSCORE]"
-        } else {
-            ""
-        }
-    );
-    println!(
-        "Number of lines: {}{}",
-        tetris.nb_lines,
-        if new_highest_lines_sent {
-            " [NEW HIGHSCORE]"
-        } else {
-            ""
-        }
+
+    let nb_players: usize = if versus_mode { 2 } else { 1 };
+    let mut game = LocalGame::new(
+        nb_players,
+        (pieces, delays),
+        resumed,
+        sandbox,
+        brain,
+        // Particles and shaking are random, they're left out of screenshots.
+        match screenshot_tick {
+            Some(_) => EffectSettings::none(),
+            None => effect_settings,
+        },
     );
-    println!("Current level:   {}", tetris.current_level);
-}
 
-fn is_time_over(tetris: &Tetris, timer: &SystemTime) -> bool {
-    match timer.elapsed() {
-        Ok(elapsed) => {
-            let millis = elapsed.as_secs() as u32 * 1000 + elapsed.subsec_nanos() / 1_000_000;
-            millis > LEVEL_TIMES[tetris.current_level as usize - 1]
-        }
-        Err(_) => false,
+    if let Some(tick) = screenshot_tick {
+        let tick = match tick.and_then(|tick| tick.parse::<u64>().ok()) {
+            Some(tick) => tick,
+            None => {
+                eprintln!("--screenshot-at-tick needs a number of ticks");
+                process::exit(1);
+            }
+        };
+        simulate(&mut game.session, &mut game.bot_player, tick);
+        let file_name = arg_value("--screenshot-file")
+            .and_then(|file_name| file_name)
+            .unwrap_or_else(|| format!("tick-{}.png", tick));
+        let games = game.session.games();
+        if let Err(e) = save_offscreen(
+            &games,
+            game.session.versus.as_ref(),
+            theme,
+            &mut skin,
+            &file_name,
+        ) {
+            eprintln!("Couldn't save the screenshot: {}", e);
+            process::exit(1);
+        }
+        println!("Saved {}", file_name);
+        return;
     }
-}
 
-fn main() {
     let sdl_context = sdl2::init().expect(
         "SDL initialization
          failed",
@@ -657,22 +626,39 @@ fn main() {
         "Couldn't get
           SDL video subsystem",
     );
-    let width = 600;
-    let height = 800;
 
-    let mut tetris = Tetris::new();
-    let mut timer = SystemTime::now();
+    let audio = Audio::new(&sdl_context, volumes());
+
+    let mut controls = if versus_mode && net_game.is_none() {
+        VERSUS_KEYMAPS.iter().map(Controls::new).collect::<Vec<_>>()
+    } else {
+        vec![Controls::new(&SOLO_KEYMAP)]
+    };
+    if versus_mode {
+        // Controllers are handed out to the players in the order SDL lists them.
+        if let Ok(controller_subsystem) = sdl_context.game_controller() {
+            let nb_joysticks = controller_subsystem.num_joysticks().unwrap_or(0);
+            let mut controls_left = controls.iter_mut();
+            for id in (0..nb_joysticks).filter(|id| controller_subsystem.is_game_controller(*id)) {
+                match (controls_left.next(), controller_subsystem.open(id)) {
+                    (Some(control), Ok(controller)) => control.controller = Some(controller),
+                    (None, _) => break,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    let (width, height) = Layout::default_size(nb_players);
 
     let mut event_pump = sdl_context
         .event_pump()
         .expect("Failed to get SDL event pump");
 
-    let grid_x = (width - TETRIS_HEIGHT as u32 * 10) as i32 / 2;
-    let grid_y = (height - TETRIS_HEIGHT as u32 * 16) as i32 / 2;
-
     let window = video_subsystem
         .window("Tetris", width, height)
         .position_centered() // to put it in the middle of the screen
+        .resizable()
         .build() // to create the window
         .expect("Failed to create window");
     let mut canvas = window
@@ -682,160 +668,196 @@ fn main() {
         .build()
         .expect("Couldn't get window's canvas");
     let texture_creator: TextureCreator<_> = canvas.texture_creator();
-    let grid = create_texture_rect(
-        &mut canvas,
-        &texture_creator,
-        0,
-        0,
-        0,
-        TETRIS_HEIGHT as u32 * 10,
-        TETRIS_HEIGHT as u32 * 16,
-    )
-    .expect("Failed to create a texture");
-    let border = create_texture_rect(
-        &mut canvas,
+
+    let _image_context = sdl2::image::init(sdl2::image::INIT_PNG);
+    let mut renderer = Renderer::new(
         &texture_creator,
-        255,
-        255,
-        255,
-        TETRIS_HEIGHT as u32 * 10 + 20,
-        TETRIS_HEIGHT as u32 * 16 + 20,
-    )
-    .expect("Failed to create a texture");
-
-    macro_rules! texture {
-        ($r:expr, $g:expr, $b:expr) => {
-            create_texture_rect(
-                &mut canvas,
-                &texture_creator,
-                $r,
-                $g,
-                $b,
-                TETRIS_HEIGHT as u32,
-                TETRIS_HEIGHT as u32,
-            )
-            .unwrap()
+        load_textures(&mut canvas, &texture_creator, theme, &mut skin),
+    );
+
+    let output_size = |canvas: &Canvas<Window>| canvas.output_size().unwrap_or((width, height));
+    let mut layout = Layout::new(nb_players, output_size(&canvas));
+    let ttf_context = sdl2::ttf::init().ok();
+    let mut text = load_text(ttf_context.as_ref(), &layout);
+
+    // Handles what isn't part of the game itself, the same way in every mode.
+    macro_rules! apply_commands {
+        ($commands:expr) => {
+            if $commands.next_theme {
+                theme = next_theme(&themes, theme);
+                renderer.set_textures(load_textures(
+                    &mut canvas,
+                    &texture_creator,
+                    theme,
+                    &mut skin,
+                ));
+            }
+            if $commands.toggle_fullscreen {
+                toggle_fullscreen(&mut canvas);
+            }
+            if $commands.resized {
+                layout = Layout::new(nb_players, output_size(&canvas));
+                text = load_text(ttf_context.as_ref(), &layout);
+            }
         };
     }
 
-    let textures = [
-        texture!(255, 69, 69),
-        texture!(255, 220, 69),
-        texture!(237, 150, 37),
-        texture!(171, 99, 237),
-        texture!(77, 149, 239),
-        texture!(39, 218, 225),
-        texture!(45, 216, 47),
-    ];
-
-    loop {
-        if is_time_over(&tetris, &timer) {
-            let mut make_permanent = false;
-            if let Some(ref mut piece) = tetris.current_piece {
-                let x = piece.x;
-                let y = piece.y + 1;
-                make_permanent = !piece.change_position(&tetris.game_map, x, y);
+    let mut pacer = Pacer::new();
+    if let Some(ref mut net_game) = net_game {
+        loop {
+            let mut commands = Commands::default();
+            let actions = handle_events(&controls, &mut commands, None, &mut event_pump)
+                .into_iter()
+                .map(|(_, action)| action)
+                .collect();
+            apply_commands!(commands);
+            let result = if commands.quit {
+                net_game.forfeit()
+            } else {
+                net_game.send_inputs(actions).and_then(|_| net_game.step())
+            };
+            let games = net_game.players.iter().collect::<Vec<_>>();
+            if let Err(e) = result {
+                eprintln!("{}", e);
+                print_versus_result(&games, None);
+                break;
             }
-            if make_permanent {
-                tetris.make_permanent();
+            if net_game.loser.is_some() {
+                print_versus_result(&games, net_game.loser);
+                show_game_over(
+                    (&mut canvas, &mut renderer),
+                    text.as_ref(),
+                    (&games, net_game.loser),
+                    audio.as_ref(),
+                    &mut event_pump,
+                );
+                break;
             }
-            timer = SystemTime::now();
-        }
-
-        // We need to draw the tetris "grid" in here.
-        canvas.set_draw_color(Color::RGB(255, 0, 0));
-        canvas.clear();
-        canvas
-            .copy(
-                &border,
-                None,
-                Rect::new(
-                    (width - TETRIS_HEIGHT as u32 * 10) as i32 / 2 - 10,
-                    (height - TETRIS_HEIGHT as u32 * 16) as i32 / 2 - 10,
-                    TETRIS_HEIGHT as u32 * 10 + 20,
-                    TETRIS_HEIGHT as u32 * 16 + 20,
-                ),
-            )
-            .expect("Couldn't copy texture into window");
-        canvas
-            .copy(
-                &grid,
-                None,
-                Rect::new(
-                    (width - TETRIS_HEIGHT as u32 * 10) as i32 / 2,
-                    (height - TETRIS_HEIGHT as u32 * 16) as i32 / 2,
-                    TETRIS_HEIGHT as u32 * 10,
-                    TETRIS_HEIGHT as u32 * 16,
-                ),
-            )
-            .expect("Couldn't copy texture into window");
-
-        if tetris.current_piece.is_none() {
-            let current_piece = tetris.create_new_tetrimino();
-            if !current_piece.test_current_position(&tetris.game_map) {
-                print_game_information(&tetris);
+            if let Err(e) = renderer.draw_frame(
+                &mut canvas,
+                &layout,
+                text.as_ref(),
+                &games,
+                Some(&net_game.versus),
+                &[],
+            ) {
+                eprintln!("Couldn't draw the game: {}", e);
                 break;
             }
-            tetris.current_piece = Some(current_piece);
-        }
-        let mut quit = false;
-        if !handle_events(&mut tetris, &mut quit, &mut timer, &mut event_pump) {
-            if let Some(ref mut piece) = tetris.current_piece {
-                // We need to draw our current tetrimino in here.
-                for (line_nb, line) in piece.states[piece.current_state as usize]
-                    .iter()
-                    .enumerate()
-                {
-                    for (case_nb, case) in line.iter().enumerate() {
-                        if *case == 0 {
-                            continue;
-                        }
-                        // The new part is here:
-                        canvas
-                            .copy(
-                                &textures[*case as usize - 1],
-                                None,
-                                Rect::new(
-                                    grid_x
-                                        + (piece.x + case_nb as isize) as i32
-                                            * TETRIS_HEIGHT as i32,
-                                    grid_y + (piece.y + line_nb) as i32 * TETRIS_HEIGHT as i32,
-                                    TETRIS_HEIGHT as u32,
-                                    TETRIS_HEIGHT as u32,
-                                ),
-                            )
-                            .expect("Couldn't copy texture into window");
-                    }
+            if commands.screenshot {
+                take_screenshot(&canvas);
+            }
+            canvas.present();
+            pacer.wait();
+        }
+        return;
+    }
+
+    let mut loser = None;
+    loop {
+        let mut commands = Commands::default();
+        // Mouse positions are in window coordinates, which can differ from
+        // pixels on high density screens.
+        let (window_width, window_height) = canvas.window().size();
+        let (width, height) = output_size(&canvas);
+        let scale = (
+            width as f32 / window_width.max(1) as f32,
+            height as f32 / window_height.max(1) as f32,
+        );
+        let case_at = |(x, y): (i32, i32)| {
+            layout.case_at(
+                0,
+                ((x as f32 * scale.0) as i32, (y as f32 * scale.1) as i32),
+            )
+        };
+        let editing = game.editor.as_ref().map(|_| &case_at as _);
+        let mut actions = handle_events(&controls, &mut commands, editing, &mut event_pump);
+        apply_commands!(commands);
+        if let Some(ref mut editor) = game.editor {
+            for edit in commands.edits.drain(..) {
+                match editor.apply(edit, &mut game.session.players[0].tetris) {
+                    Ok(Some(message)) => println!("{}", message),
+                    Ok(None) => {}
+                    Err(e) => eprintln!("{}", e),
                 }
             }
         }
-        if quit {
-            print_game_information(&tetris);
-            break;
+        if let Some(ref mut bot_player) = game.bot_player {
+            if let Err(e) = bot_player.play(&game.session, &mut actions) {
+                eprintln!("{}", e);
+                loser = Some(bot_player.player);
+            }
+        }
+        game.record(&actions);
+        let outcome = game.session.step(&actions);
+        for sound in Sound::for_tick(&outcome) {
+            play_sound(audio.as_ref(), sound);
+        }
+        if let Some(ref mut bot_player) = game.bot_player {
+            bot_player.on_tick(&outcome);
+        }
+        loser = loser.or(outcome.loser);
+        if let Some(ref mut editor) = game.editor {
+            let tetris = &game.session.players[0].tetris;
+            editor.sandbox.after_tick(tetris);
+            let title = editor.title(tetris);
+            if canvas.window().title() != title {
+                let _ = canvas.window_mut().set_title(&title);
+            }
         }
 
-        // We need to draw the game map in here.
-        for (line_nb, line) in tetris.game_map.iter().enumerate() {
-            for (case_nb, case) in line.iter().enumerate() {
-                if *case == 0 {
-                    continue;
+        if commands.suspend && !versus_mode && game.editor.is_none() && loser.is_none() {
+            let games = game.session.games();
+            match save::save(games[0], SAVE_FILE) {
+                Ok(()) => println!("Game suspended, it can be resumed at the next launch"),
+                Err(e) => {
+                    eprintln!("Couldn't suspend the game: {}", e);
+                    print_game_information(games[0]);
                 }
-                canvas
-                    .copy(
-                        &textures[*case as usize - 1],
-                        None,
-                        Rect::new(
-                            grid_x + case_nb as i32 * TETRIS_HEIGHT as i32,
-                            grid_y + line_nb as i32 * TETRIS_HEIGHT as i32,
-                            TETRIS_HEIGHT as u32,
-                            TETRIS_HEIGHT as u32,
-                        ),
-                    )
-                    .expect("Couldn't copy texture into window");
             }
+            break;
+        }
+        if commands.quit || commands.suspend || loser.is_some() {
+            let games = game.session.games();
+            if versus_mode {
+                print_versus_result(&games, loser);
+            } else if game.editor.is_none() {
+                print_game_information(games[0]);
+            }
+            match game.save_recording() {
+                Some(Ok(message)) => println!("{}", message),
+                Some(Err(e)) => eprintln!("{}", e),
+                None => {}
+            }
+            if game.editor.is_none() {
+                show_game_over(
+                    (&mut canvas, &mut renderer),
+                    text.as_ref(),
+                    (&game.session.games(), loser),
+                    audio.as_ref(),
+                    &mut event_pump,
+                );
+            } else if loser.is_some() {
+                play_game_over(audio.as_ref());
+            }
+            break;
         }
-        canvas.present();
 
-        sleep(Duration::new(0, 1_000_000_000u32 / 60));
+        if let Err(e) = renderer.draw_frame(
+            &mut canvas,
+            &layout,
+            text.as_ref(),
+            &game.session.games(),
+            game.session.versus.as_ref(),
+            &game.session.effects,
+        ) {
+            eprintln!("Couldn't draw the game: {}", e);
+            break;
+        }
+        if commands.screenshot {
+            take_screenshot(&canvas);
+        }
+        canvas.present();
+        pacer.wait();
     }
 }
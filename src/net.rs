@@ -0,0 +1,535 @@
+use crate::delays::Delays;
+use crate::game::{Action, Tetris};
+use crate::pieces::PieceSet;
+use crate::versus::{AttackTable, Versus};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+pub const PROTOCOL_VERSION: u32 = 5;
+pub const DEFAULT_PORT: u16 = 7878;
+pub const DEFAULT_INPUT_DELAY: u64 = 2;
+/// Both peers exchange the hash of their simulation every this many ticks.
+const HASH_INTERVAL: u64 = 60;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One line of the protocol. Every message is a keyword followed by
+/// space-separated arguments, e.g. `INPUT 42 LLH`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    Hello(u32),
+    Seed(u64),
+    Rules(Vec<u32>),
+    /// The piece set, as JSON on the rest of the line.
+    Pieces(PieceSet),
+    /// The entry and line clear delays.
+    Delays(Delays),
+    /// Actions of the sender for the given tick.
+    Input(u64, Vec<Action>),
+    /// Garbage lines the sender sent during the given tick.
+    Garbage(u64, u32),
+    Hash(u64, u64),
+    /// Tick and player who lost, either by topping out or by leaving.
+    GameOver(u64, usize),
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Message::Hello(version) => write!(f, "HELLO {}", version),
+            Message::Seed(seed) => write!(f, "SEED {}", seed),
+            Message::Rules(ref attack_table) => write!(
+                f,
+                "RULES {}",
+                attack_table
+                    .iter()
+                    .map(|nb| nb.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+            Message::Pieces(ref pieces) => write!(
+                f,
+                "PIECES {}",
+                serde_json::to_string(pieces).map_err(|_| fmt::Error)?
+            ),
+            Message::Delays(delays) => {
+                write!(f, "DELAYS {} {}", delays.entry, delays.line_clear)
+            }
+            Message::Input(tick, ref actions) => {
+                let actions = actions
+                    .iter()
+                    .map(|action| action.to_char())
+                    .collect::<String>();
+                write!(
+                    f,
+                    "INPUT {} {}",
+                    tick,
+                    if actions.is_empty() { "-" } else { &actions }
+                )
+            }
+            Message::Garbage(tick, nb_lines) => write!(f, "GARBAGE {} {}", tick, nb_lines),
+            Message::Hash(tick, hash) => write!(f, "HASH {} {}", tick, hash),
+            Message::GameOver(tick, loser) => write!(f, "GAMEOVER {} {}", tick, loser),
+        }
+    }
+}
+
+impl Message {
+    pub fn parse(line: &str) -> Option<Message> {
+        if let Some(pieces) = line.strip_prefix("PIECES ") {
+            return PieceSet::parse(pieces).ok().map(Message::Pieces);
+        }
+        let mut words = line.split_whitespace();
+        let keyword = words.next()?;
+        let args = words.collect::<Vec<_>>();
+        let number = |nb: usize| args.get(nb).and_then(|arg| arg.parse::<u64>().ok());
+
+        match (keyword, args.len()) {
+            ("HELLO", 1) => Some(Message::Hello(number(0)? as u32)),
+            ("SEED", 1) => Some(Message::Seed(number(0)?)),
+            ("RULES", _) => args
+                .iter()
+                .map(|arg| arg.parse::<u32>().ok())
+                .collect::<Option<Vec<_>>>()
+                .map(Message::Rules),
+            ("DELAYS", 2) => Some(Message::Delays(Delays {
+                entry: number(0)? as u32,
+                line_clear: number(1)? as u32,
+            })),
+            ("INPUT", 2) => {
+                let actions = if args[1] == "-" {
+                    Vec::new()
+                } else {
+                    args[1]
+                        .chars()
+                        .map(Action::from_char)
+                        .collect::<Option<Vec<_>>>()?
+                };
+                Some(Message::Input(number(0)?, actions))
+            }
+            ("GARBAGE", 2) => Some(Message::Garbage(number(0)?, number(1)? as u32)),
+            ("HASH", 2) => Some(Message::Hash(number(0)?, number(1)?)),
+            ("GAMEOVER", 2) => Some(Message::GameOver(number(0)?, number(1)? as usize)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum NetError {
+    Io(io::Error),
+    Disconnected,
+    Timeout,
+    BadMessage(String),
+    VersionMismatch(u32),
+    /// The two simulations disagree at the given tick.
+    Desync(u64),
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NetError::Io(ref e) => write!(f, "network error: {}", e),
+            NetError::Disconnected => write!(f, "the other player disconnected"),
+            NetError::Timeout => write!(f, "the other player stopped responding"),
+            NetError::BadMessage(ref line) => write!(f, "unexpected message: {:?}", line),
+            NetError::VersionMismatch(version) => write!(
+                f,
+                "protocol version mismatch (ours: {}, theirs: {})",
+                PROTOCOL_VERSION, version
+            ),
+            NetError::Desync(tick) => write!(f, "games went out of sync at tick {}", tick),
+        }
+    }
+}
+
+impl From<io::Error> for NetError {
+    fn from(e: io::Error) -> NetError {
+        NetError::Io(e)
+    }
+}
+
+/// A TCP connection exchanging `Message`s. Incoming lines are read by a
+/// separate thread so that waiting for them can time out.
+pub struct Connection {
+    stream: TcpStream,
+    incoming: Receiver<Result<Message, String>>,
+}
+
+impl Connection {
+    /// Waits for a single player to join on `port`.
+    pub fn host(port: u16) -> io::Result<Connection> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        let (stream, _) = listener.accept()?;
+        Connection::new(stream)
+    }
+
+    pub fn join(address: &str) -> io::Result<Connection> {
+        Connection::new(TcpStream::connect(address)?)
+    }
+
+    fn new(stream: TcpStream) -> io::Result<Connection> {
+        stream.set_nodelay(true)?;
+        let reader = BufReader::new(stream.try_clone()?);
+        let (sender, incoming) = channel();
+        thread::spawn(move || {
+            for line in reader.lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                let message = Message::parse(&line).ok_or(line);
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Connection { stream, incoming })
+    }
+
+    pub fn send(&mut self, message: &Message) -> Result<(), NetError> {
+        writeln!(self.stream, "{}", message)?;
+        Ok(())
+    }
+
+    pub fn recv(&self) -> Result<Message, NetError> {
+        match self.incoming.recv_timeout(TIMEOUT) {
+            Ok(Ok(message)) => Ok(message),
+            Ok(Err(line)) => Err(NetError::BadMessage(line)),
+            Err(RecvTimeoutError::Timeout) => Err(NetError::Timeout),
+            Err(RecvTimeoutError::Disconnected) => Err(NetError::Disconnected),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Check {
+    Hash(u64),
+    Garbage(u64),
+}
+
+/// Values computed by both peers that must be equal, kept until the other
+/// side's value arrives.
+#[derive(Default)]
+struct Checks {
+    local: HashMap<Check, u64>,
+    remote: HashMap<Check, u64>,
+}
+
+impl Checks {
+    fn record(&mut self, check: Check, value: u64, local: bool) -> Result<(), NetError> {
+        let (mine, theirs) = if local {
+            (&mut self.local, &mut self.remote)
+        } else {
+            (&mut self.remote, &mut self.local)
+        };
+        match theirs.remove(&check) {
+            Some(other) if other != value => Err(NetError::Desync(match check {
+                Check::Hash(tick) | Check::Garbage(tick) => tick,
+            })),
+            Some(_) => Ok(()),
+            None => {
+                mine.insert(check, value);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A versus game kept in lockstep over the network: both peers simulate both
+/// games and a tick only runs once the inputs of both players are known.
+pub struct NetGame {
+    connection: Connection,
+    pub local_player: usize,
+    pub players: Vec<Tetris>,
+    pub versus: Versus,
+    pub tick: u64,
+    pub loser: Option<usize>,
+    input_delay: u64,
+    inputs: [HashMap<u64, Vec<Action>>; 2],
+    checks: Checks,
+}
+
+impl NetGame {
+    /// Waits for a player to join, then sends them the seed, the rules, the
+    /// pieces and the delays.
+    pub fn host(
+        port: u16,
+        input_delay: u64,
+        attack_table: AttackTable,
+        pieces: PieceSet,
+        delays: Delays,
+    ) -> Result<NetGame, NetError> {
+        let mut connection = Connection::host(port)?;
+        connection.send(&Message::Hello(PROTOCOL_VERSION))?;
+        expect_hello(&connection)?;
+
+        let seed = rand::random::<u64>();
+        connection.send(&Message::Seed(seed))?;
+        connection.send(&Message::Rules(attack_table.lines().to_vec()))?;
+        connection.send(&Message::Pieces(pieces.clone()))?;
+        connection.send(&Message::Delays(delays))?;
+        let rules = (attack_table, Arc::new(pieces), delays);
+        NetGame::start(connection, 0, seed, input_delay, rules)
+    }
+
+    pub fn join(address: &str, input_delay: u64) -> Result<NetGame, NetError> {
+        let mut connection = Connection::join(address)?;
+        connection.send(&Message::Hello(PROTOCOL_VERSION))?;
+        expect_hello(&connection)?;
+
+        let seed = match connection.recv()? {
+            Message::Seed(seed) => seed,
+            message => return Err(NetError::BadMessage(message.to_string())),
+        };
+        let attack_table = match connection.recv()? {
+            Message::Rules(lines) => AttackTable::from_lines(lines.clone())
+                .ok_or_else(|| NetError::BadMessage(Message::Rules(lines).to_string()))?,
+            message => return Err(NetError::BadMessage(message.to_string())),
+        };
+        let pieces = match connection.recv()? {
+            Message::Pieces(pieces) => Arc::new(pieces),
+            message => return Err(NetError::BadMessage(message.to_string())),
+        };
+        let delays = match connection.recv()? {
+            Message::Delays(delays) => delays,
+            message => return Err(NetError::BadMessage(message.to_string())),
+        };
+        let rules = (attack_table, pieces, delays);
+        NetGame::start(connection, 1, seed, input_delay, rules)
+    }
+
+    fn start(
+        mut connection: Connection,
+        local_player: usize,
+        seed: u64,
+        input_delay: u64,
+        (attack_table, pieces, delays): (AttackTable, Arc<PieceSet>, Delays),
+    ) -> Result<NetGame, NetError> {
+        let mut inputs = [HashMap::new(), HashMap::new()];
+        // Nobody can act before the input delay is over.
+        for tick in 0..input_delay {
+            connection.send(&Message::Input(tick, Vec::new()))?;
+            inputs[local_player].insert(tick, Vec::new());
+        }
+        let mut players = vec![
+            Tetris::with_pieces(seed, pieces.clone()),
+            Tetris::with_pieces(seed, pieces),
+        ];
+        for tetris in &mut players {
+            tetris.delays = delays;
+        }
+        Ok(NetGame {
+            connection,
+            local_player,
+            players,
+            versus: Versus::new(2, attack_table),
+            tick: 0,
+            loser: None,
+            input_delay,
+            inputs,
+            checks: Checks::default(),
+        })
+    }
+
+    fn remote_player(&self) -> usize {
+        1 - self.local_player
+    }
+
+    /// Schedules the local actions of this frame, to be played after the
+    /// input delay.
+    pub fn send_inputs(&mut self, actions: Vec<Action>) -> Result<(), NetError> {
+        let tick = self.tick + self.input_delay;
+        self.connection
+            .send(&Message::Input(tick, actions.clone()))?;
+        self.inputs[self.local_player].insert(tick, actions);
+        Ok(())
+    }
+
+    /// Tells the other player that we are leaving.
+    pub fn forfeit(&mut self) -> Result<(), NetError> {
+        self.loser = Some(self.local_player);
+        self.connection
+            .send(&Message::GameOver(self.tick, self.local_player))
+    }
+
+    fn handle_message(&mut self, message: Message) -> Result<(), NetError> {
+        let remote_player = self.remote_player();
+        match message {
+            Message::Input(tick, actions) => {
+                self.inputs[remote_player].insert(tick, actions);
+            }
+            Message::Garbage(tick, nb_lines) => {
+                self.checks
+                    .record(Check::Garbage(tick), nb_lines as u64, false)?;
+            }
+            Message::Hash(tick, hash) => {
+                self.checks.record(Check::Hash(tick), hash, false)?;
+            }
+            Message::GameOver(_, loser) => {
+                self.loser = Some(loser);
+            }
+            message => return Err(NetError::BadMessage(message.to_string())),
+        }
+        Ok(())
+    }
+
+    /// Runs the next tick, waiting for the other player's inputs if needed.
+    pub fn step(&mut self) -> Result<(), NetError> {
+        let remote_player = self.remote_player();
+        while !self.inputs[remote_player].contains_key(&self.tick) {
+            let message = self.connection.recv()?;
+            self.handle_message(message)?;
+            if self.loser.is_some() {
+                return Ok(());
+            }
+        }
+
+        let tick = self.tick;
+        for player in 0..self.players.len() {
+            let actions = self.inputs[player].remove(&tick).unwrap_or_default();
+            let mut sent = 0;
+            let mut alive = true;
+            let tetris = &mut self.players[player];
+            let mut locks = actions
+                .into_iter()
+                .filter_map(|action| tetris.apply_action(action))
+                .collect::<Vec<_>>();
+            locks.extend(tetris.gravity_tick());
+            for cleared in locks {
+                let outcome = self.versus.on_lock(tetris, player, cleared);
+                sent += outcome.sent;
+                alive &= outcome.alive;
+            }
+            if !tetris.spawn() {
+                alive = false;
+            }
+            if sent > 0 {
+                let local = player == self.local_player;
+                if local {
+                    self.connection.send(&Message::Garbage(tick, sent))?;
+                }
+                self.checks
+                    .record(Check::Garbage(tick), sent as u64, local)?;
+            }
+            if !alive && self.loser.is_none() {
+                self.loser = Some(player);
+            }
+        }
+
+        if tick.is_multiple_of(HASH_INTERVAL) {
+            let hash = self.players.iter().fold(0, |hash: u64, tetris| {
+                hash.rotate_left(17) ^ tetris.state_hash()
+            });
+            self.connection.send(&Message::Hash(tick, hash))?;
+            self.checks.record(Check::Hash(tick), hash, true)?;
+        }
+        if let Some(loser) = self.loser {
+            self.connection.send(&Message::GameOver(tick, loser))?;
+        }
+        self.tick += 1;
+        Ok(())
+    }
+}
+
+fn expect_hello(connection: &Connection) -> Result<(), NetError> {
+    match connection.recv()? {
+        Message::Hello(PROTOCOL_VERSION) => Ok(()),
+        Message::Hello(version) => Err(NetError::VersionMismatch(version)),
+        message => Err(NetError::BadMessage(message.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_read_back() {
+        let delays = Delays {
+            entry: 6,
+            line_clear: 12,
+        };
+        for message in [
+            Message::Hello(PROTOCOL_VERSION),
+            Message::Seed(u64::MAX),
+            Message::Rules(vec![0, 0, 1, 2, 4]),
+            Message::Rules(Vec::new()),
+            Message::Pieces(PieceSet::builtin()),
+            Message::Delays(delays),
+            Message::Input(42, vec![Action::Left, Action::Rotate, Action::HardDrop]),
+            Message::Input(0, Vec::new()),
+            Message::Input(7, vec![Action::Right, Action::SoftDrop, Action::Hold]),
+            Message::Garbage(3, 4),
+            Message::Hash(120, 0xcbf2_9ce4_8422_2325),
+            Message::GameOver(900, 1),
+        ] {
+            assert_eq!(Message::parse(&message.to_string()), Some(message));
+        }
+        assert_eq!(Message::parse("INPUT 1 LX"), None);
+        assert_eq!(Message::parse("HASH 1"), None);
+        assert_eq!(Message::parse("NOPE"), None);
+    }
+
+    /// Actions of `player` at `tick`, different for both.
+    fn inputs(player: usize, tick: u64) -> Vec<Action> {
+        let column = (tick / 30 % 4) as usize;
+        match (player, tick % 30) {
+            (0, 10) => vec![Action::Left; 2 * column],
+            (0, 12) => vec![Action::HardDrop],
+            (1, 5) => vec![Action::Rotate],
+            (1, 20) => vec![Action::Right; 2 * column],
+            (1, 25) => vec![Action::HardDrop],
+            _ => Vec::new(),
+        }
+    }
+
+    fn play(net_game: &mut NetGame, ticks: u64) -> Result<(), NetError> {
+        for tick in 0..ticks {
+            net_game.send_inputs(inputs(net_game.local_player, tick))?;
+            net_game.step()?;
+        }
+        Ok(())
+    }
+
+    fn hashes(net_game: &NetGame) -> Vec<u64> {
+        net_game.players.iter().map(Tetris::state_hash).collect()
+    }
+
+    #[test]
+    fn games_on_localhost_stay_in_sync() {
+        let port = TcpListener::bind(("127.0.0.1", 0))
+            .and_then(|listener| listener.local_addr())
+            .unwrap()
+            .port();
+        let ticks = 2 * HASH_INTERVAL + 10;
+        let host = thread::spawn(move || {
+            let attack_table = AttackTable::default();
+            let delays = Delays::named("modern").unwrap();
+            let mut net_game =
+                NetGame::host(port, 2, attack_table, PieceSet::builtin(), delays).unwrap();
+            play(&mut net_game, ticks).unwrap();
+            // Still connected until the other side is done.
+            net_game
+        });
+        let mut net_game = loop {
+            match NetGame::join(&format!("127.0.0.1:{}", port), 2) {
+                Ok(net_game) => break net_game,
+                Err(NetError::Io(_)) => thread::sleep(Duration::from_millis(10)),
+                Err(e) => panic!("{}", e),
+            }
+        };
+        assert_eq!(net_game.players[0].delays, Delays::named("modern").unwrap());
+        play(&mut net_game, ticks).unwrap();
+        assert_eq!(hashes(&host.join().unwrap()), hashes(&net_game));
+        assert_eq!(net_game.loser, None);
+        assert!(net_game.players[0].statistics.locked > 0);
+        assert!(net_game.players[1].statistics.locked > 0);
+    }
+}
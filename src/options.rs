@@ -0,0 +1,332 @@
+//! The command line options the window and the terminal frontends share,
+//! and the games they set up. Options that can't be used end the program
+//! with a message, like the frontends themselves do.
+
+use crate::bot::{Bot, Weights, WEIGHTS_FILE};
+use crate::delays::Delays;
+use crate::editor::Editor;
+use crate::effects::{EffectSettings, EFFECTS_FILE};
+use crate::fumen;
+use crate::game::{Action, Tetris};
+use crate::net::{NetGame, DEFAULT_INPUT_DELAY, DEFAULT_PORT};
+use crate::pieces::PieceSet;
+use crate::replay::Replay;
+use crate::sandbox::{Sandbox, Setup, SETUP_FILE};
+use crate::save::{self, SAVE_FILE};
+use crate::session::{BotPlayer, Brain, Player, Session};
+use crate::tbp::{ExternalBot, TbpError};
+use crate::theme::{Theme, THEME_FILE};
+use crate::versus::{AttackTable, Versus, ATTACK_TABLE_FILE};
+
+use std::env;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+
+pub const REPLAY_FILE: &str = "replay.txt";
+
+/// Value following `name` on the command line, if `name` is there.
+pub fn arg_value(name: &str) -> Option<Option<String>> {
+    let mut args = env::args().skip_while(|arg| arg != name);
+    args.next()?;
+    Some(args.next().filter(|arg| !arg.starts_with("--")))
+}
+
+pub fn has_flag(name: &str) -> bool {
+    env::args().any(|arg| arg == name)
+}
+
+/// The pieces named by `--pieces NAME`, see `PieceSet::named`, or those of
+/// `--pieces FILE`. Without it, those of `PIECES_FILE`, or the built-in ones
+/// if it's missing or invalid.
+pub fn load_pieces() -> PieceSet {
+    match arg_value("--pieces").and_then(|name| name) {
+        Some(name) => PieceSet::named_or_load(&name).unwrap_or_else(|e| {
+            eprintln!("Couldn't load the pieces {:?}: {}", name, e);
+            process::exit(1);
+        }),
+        None => PieceSet::load_or_builtin().unwrap_or_else(|e| {
+            eprintln!("Couldn't load the pieces, {}", e);
+            PieceSet::builtin()
+        }),
+    }
+}
+
+/// The delays named by `--delays NAME`, see `Delays::named`, or those of
+/// `--delays FILE`. Without it, those of `DELAYS_FILE`, or none if it's
+/// missing or invalid.
+pub fn load_delays() -> Delays {
+    match arg_value("--delays").and_then(|name| name) {
+        Some(name) => Delays::named_or_load(&name).unwrap_or_else(|e| {
+            eprintln!("Couldn't load the delays {:?}: {}", name, e);
+            process::exit(1);
+        }),
+        None => Delays::load_or_default().unwrap_or_else(|e| {
+            eprintln!("Couldn't load the delays, {}", e);
+            Delays::default()
+        }),
+    }
+}
+
+/// The built-in themes, and the one of `THEME_FILE` if there's one, with
+/// the colours of `pieces`. The theme named by `--theme NAME` comes first.
+pub fn load_themes(pieces: &PieceSet) -> Vec<Theme> {
+    let mut themes = Theme::builtins();
+    if let Some(theme) = Theme::load(THEME_FILE) {
+        themes.insert(0, theme);
+    }
+    let mut themes = themes
+        .into_iter()
+        .map(|theme| theme.with_piece_colours(pieces))
+        .collect::<Vec<_>>();
+    if let Some(name) = arg_value("--theme").and_then(|name| name) {
+        match themes.iter().position(|theme| theme.name == name) {
+            Some(position) => {
+                let theme = themes.remove(position);
+                themes.insert(0, theme);
+            }
+            None => eprintln!("Unknown theme {:?}", name),
+        }
+    }
+    themes
+}
+
+/// The effects of `EFFECTS_FILE`, or none with `--no-effects`.
+pub fn load_effect_settings() -> EffectSettings {
+    if has_flag("--no-effects") {
+        EffectSettings::none()
+    } else {
+        EffectSettings::load(EFFECTS_FILE).unwrap_or_default()
+    }
+}
+
+/// Loads the bot weights from `--weights FILE`, or from `WEIGHTS_FILE`.
+pub fn load_weights() -> Weights {
+    let file_name = arg_value("--weights")
+        .and_then(|file_name| file_name)
+        .unwrap_or_else(|| WEIGHTS_FILE.to_owned());
+    Weights::load(&file_name).unwrap_or_default()
+}
+
+/// The bot asked for with `--tbp-bot COMMAND` or `--bot`, to play
+/// `pieces`.
+pub fn load_brain(pieces: &PieceSet) -> Option<Brain> {
+    if let Some(command) = arg_value("--tbp-bot") {
+        let command = command.unwrap_or_default();
+        match ExternalBot::launch(&command) {
+            Ok(bot) if bot.knows(pieces) => Some(Brain::External(bot)),
+            Ok(_) => {
+                eprintln!("Couldn't start the bot: {}", TbpError::Pieces);
+                process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("Couldn't start the bot {:?}: {}", command, e);
+                process::exit(1);
+            }
+        }
+    } else if has_flag("--bot") {
+        Some(Brain::Builtin(Bot::new(load_weights())))
+    } else {
+        None
+    }
+}
+
+/// Connects to the other player if `--host` or `--join` was given. The
+/// host's pieces and delays are played.
+pub fn connect(pieces: &PieceSet, delays: Delays) -> Option<NetGame> {
+    let input_delay = arg_value("--input-delay")
+        .and_then(|delay| delay?.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_INPUT_DELAY);
+    let net_game = if let Some(port) = arg_value("--host") {
+        let port = port
+            .and_then(|port| port.parse::<u16>().ok())
+            .unwrap_or(DEFAULT_PORT);
+        let attack_table = AttackTable::load(ATTACK_TABLE_FILE).unwrap_or_default();
+        println!("Waiting for a player on port {}...", port);
+        NetGame::host(port, input_delay, attack_table, pieces.clone(), delays)
+    } else if let Some(address) = arg_value("--join") {
+        let address = address.unwrap_or_else(|| format!("127.0.0.1:{}", DEFAULT_PORT));
+        println!("Joining {}...", address);
+        NetGame::join(&address, input_delay)
+    } else {
+        return None;
+    };
+    match net_game {
+        Ok(net_game) => Some(net_game),
+        Err(e) => {
+            eprintln!("Couldn't start the network game: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// The setup file of `--sandbox FILE`, `SETUP_FILE` without a name, and the
+/// fumen of `--fumen FUMEN`, which is practiced in a sandbox too. Versus
+/// games have no sandbox.
+pub fn sandbox_options(versus_mode: bool) -> Option<(String, Option<String>)> {
+    if versus_mode {
+        return None;
+    }
+    let fumen = arg_value("--fumen").map(|fumen| fumen.unwrap_or_default());
+    let file_name = arg_value("--sandbox")
+        .map(|file_name| file_name.unwrap_or_else(|| SETUP_FILE.to_owned()))
+        .or_else(|| fumen.as_ref().map(|_| SETUP_FILE.to_owned()))?;
+    Some((file_name, fumen))
+}
+
+/// Offers to continue the game suspended in `SAVE_FILE`, if there's one.
+/// The save is removed once answered, so that a game is only resumed once.
+pub fn offer_resume() -> Option<Tetris> {
+    if !Path::new(SAVE_FILE).exists() {
+        return None;
+    }
+    let tetris = match save::load(SAVE_FILE) {
+        Ok(tetris) => tetris,
+        Err(e) => {
+            eprintln!("Couldn't resume the suspended game, {}", e);
+            let _ = save::remove(SAVE_FILE);
+            return None;
+        }
+    };
+    print!(
+        "Resume the suspended game, {} points at level {}? [Y/n] ",
+        tetris.score, tetris.current_level
+    );
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    // Without anyone to answer, the save is kept for later.
+    if io::stdin().read_line(&mut answer).unwrap_or(0) == 0 {
+        println!();
+        return None;
+    }
+    if let Err(e) = save::remove(SAVE_FILE) {
+        eprintln!("Couldn't remove the save, {}", e);
+    }
+    match answer.trim() {
+        "" | "y" | "Y" | "yes" => Some(tetris),
+        _ => None,
+    }
+}
+
+/// The games played on this machine, and what goes with them.
+pub struct LocalGame {
+    pub session: Session,
+    pub editor: Option<Editor>,
+    /// The file the replay goes to, and the replay recorded so far.
+    pub recording: Option<(String, Replay)>,
+    pub bot_player: Option<BotPlayer>,
+}
+
+impl LocalGame {
+    /// The games asked for on the command line: `resumed` if there's one,
+    /// otherwise a sandbox if `sandbox` has its setup file and fumen, or
+    /// new games seeded by `--seed` and recorded with `--record`. The bot
+    /// takes the last player, so that it plays against a human in versus.
+    pub fn new(
+        nb_players: usize,
+        (pieces, delays): (Arc<PieceSet>, Delays),
+        resumed: Option<Tetris>,
+        sandbox: Option<(String, Option<String>)>,
+        brain: Option<Brain>,
+        effect_settings: EffectSettings,
+    ) -> LocalGame {
+        // Every game is seeded, so that it can be recorded.
+        let seed = arg_value("--seed")
+            .and_then(|seed| seed?.parse::<u64>().ok())
+            .unwrap_or_else(rand::random);
+        // Replays start from the first tick of a game, and can't be edited.
+        let replayable = resumed.is_none() && sandbox.is_none();
+        // Resumed games keep their delays.
+        let delays = Some(delays).filter(|_| resumed.is_none());
+        let mut editor = None;
+        let mut players = match (resumed, sandbox) {
+            (Some(tetris), _) => vec![Player::new(tetris)],
+            (None, Some((file_name, fumen))) => {
+                let game = match fumen {
+                    Some(ref fumen) => fumen::decode(fumen, &pieces)
+                        .and_then(|pages| Sandbox::practice(pages, 0, seed, pieces.clone())),
+                    None => {
+                        let setup = if Path::new(&file_name).exists() {
+                            Setup::load(&file_name, &pieces)
+                        } else {
+                            Ok(Setup::new())
+                        };
+                        setup.and_then(|setup| {
+                            let mut tetris = setup.game(seed, pieces.clone())?;
+                            Ok((Sandbox::new(&mut tetris, &setup), tetris))
+                        })
+                    }
+                };
+                let (sandbox, tetris) = game.unwrap_or_else(|e| {
+                    eprintln!("Couldn't set up the sandbox: {}", e);
+                    process::exit(1);
+                });
+                editor = Some(Editor { sandbox, file_name });
+                vec![Player::new(tetris)]
+            }
+            (None, None) => (0..nb_players)
+                .map(|nb| {
+                    Player::new(Tetris::with_pieces(
+                        seed.wrapping_add(nb as u64),
+                        pieces.clone(),
+                    ))
+                })
+                .collect::<Vec<_>>(),
+        };
+        if let Some(delays) = delays {
+            for player in &mut players {
+                player.tetris.delays = delays;
+            }
+        }
+        if !replayable && arg_value("--record").is_some() {
+            eprintln!("Resumed and sandbox games can't be recorded");
+        }
+        let versus = if nb_players > 1 {
+            let attack_table = AttackTable::load(ATTACK_TABLE_FILE).unwrap_or_default();
+            Some(Versus::new(nb_players, attack_table))
+        } else {
+            None
+        };
+        let recording = arg_value("--record")
+            .filter(|_| replayable)
+            .map(|file_name| {
+                let mut replay = Replay::new(seed, nb_players);
+                replay.attack_table = versus
+                    .as_ref()
+                    .map(|versus| versus.attack_table.lines().to_vec());
+                if *pieces != PieceSet::builtin() {
+                    replay.pieces = Some((*pieces).clone());
+                }
+                replay.delays = delays.unwrap_or_default();
+                (file_name.unwrap_or_else(|| REPLAY_FILE.to_owned()), replay)
+            });
+        let bot_player = brain.map(|brain| BotPlayer::new(brain, players.len() - 1));
+        LocalGame {
+            session: Session::new(players, versus, effect_settings),
+            editor,
+            recording,
+            bot_player,
+        }
+    }
+
+    /// Records `actions` if the game is recorded.
+    pub fn record(&mut self, actions: &[(usize, Action)]) {
+        if let Some((_, ref mut replay)) = self.recording {
+            for &(nb, action) in actions {
+                replay.record(self.session.tick, nb, action);
+            }
+        }
+    }
+
+    /// Saves the replay, if the game was recorded. Returns what to tell the
+    /// player.
+    pub fn save_recording(&mut self) -> Option<Result<String, String>> {
+        let (file_name, replay) = self.recording.as_mut()?;
+        replay.end = self.session.tick;
+        Some(match replay.save(file_name) {
+            Ok(()) => Ok(format!("Replay saved to {}", file_name)),
+            Err(e) => Err(format!("Couldn't save the replay: {}", e)),
+        })
+    }
+}
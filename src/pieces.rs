@@ -0,0 +1,308 @@
+//! Piece sets: the shapes, rotation states, spawn positions, colours and
+//! wall kicks of the pieces, read from a JSON file such as the built-in
+//! `assets/pieces.json`.
+//!
+//! ```json
+//! {
+//!   "name": "tetrominoes",
+//!   "kicks": [[[0, 0], [-1, 0], [1, 0]]],
+//!   "pieces": [
+//!     {
+//!       "name": "T",
+//!       "spawn": [4, 0],
+//!       "colour": [160, 0, 240],
+//!       "states": [["###.", ".#..", "....", "...."], [".#..", "##..", ".#..", "...."]]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! States are rows of `#` for the blocks and `.` for the holes, of any size,
+//! in the order the piece rotates through. `kicks` are the `[x, y]` offsets
+//! tried in turn when rotating, `y` going down, either one list for every
+//! state or one per state rotated from. A piece may have kicks of its own.
+//! Without any, it only rotates in place, as with `[[0, 0]]`. `colour` is
+//! optional and replaces the theme's colour of the piece.
+
+use crate::game::{Kicks, States, Tetrimino, GARBAGE};
+use crate::polyomino;
+use crate::theme::Rgb;
+
+use serde::{Deserialize, Serialize};
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+pub const PIECES_FILE: &str = "pieces.json";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PieceDefinition {
+    pub name: String,
+    /// Position of the top left corner of the box at spawn.
+    pub spawn: (isize, usize),
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub colour: Option<Rgb>,
+    pub states: Vec<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kicks: Kicks,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PieceSet {
+    pub name: String,
+    /// Kicks of the pieces without their own.
+    #[serde(default)]
+    pub kicks: Kicks,
+    pub pieces: Vec<PieceDefinition>,
+    /// The pieces at spawn, by kind minus one.
+    #[serde(skip)]
+    tetriminos: Vec<Tetrimino>,
+}
+
+impl PartialEq for PieceSet {
+    fn eq(&self, other: &PieceSet) -> bool {
+        self.name == other.name && self.kicks == other.kicks && self.pieces == other.pieces
+    }
+}
+
+impl Eq for PieceSet {}
+
+impl Default for PieceSet {
+    fn default() -> PieceSet {
+        PieceSet::builtin()
+    }
+}
+
+/// The blocks of a state, as the kind of the piece.
+fn parse_state(rows: &[String], kind: u8) -> Result<Vec<Vec<u8>>, String> {
+    let width = rows.first().map_or(0, |row| row.chars().count());
+    if width == 0 {
+        return Err("empty state".to_owned());
+    }
+    rows.iter()
+        .map(|row| {
+            if row.chars().count() != width {
+                return Err(format!("{:?} isn't {} cases wide", row, width));
+            }
+            row.chars()
+                .map(|case| match case {
+                    '#' => Ok(kind),
+                    '.' => Ok(0),
+                    _ => Err(format!("unexpected {:?} in {:?}", case, row)),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+impl PieceSet {
+    /// The seven tetrominoes.
+    pub fn builtin() -> PieceSet {
+        PieceSet::parse(include_str!("../assets/pieces.json"))
+            .expect("The built-in pieces are valid")
+    }
+
+    pub fn new(
+        name: String,
+        kicks: Kicks,
+        pieces: Vec<PieceDefinition>,
+    ) -> Result<PieceSet, String> {
+        // Kinds are the values of the cases of the map, and garbage comes
+        // after them.
+        if pieces.is_empty() || pieces.len() >= GARBAGE as usize {
+            return Err(format!(
+                "a set has 1 to {} pieces, not {}",
+                GARBAGE - 1,
+                pieces.len()
+            ));
+        }
+        let tetriminos = pieces
+            .iter()
+            .enumerate()
+            .map(|(nb, piece)| {
+                let error = |e: String| format!("piece {}: {}", piece.name, e);
+                let kind = nb as u8 + 1;
+                let states = piece
+                    .states
+                    .iter()
+                    .map(|rows| parse_state(rows, kind))
+                    .collect::<Result<States, String>>()
+                    .map_err(error)?;
+                if states.is_empty() {
+                    return Err(error("no states".to_owned()));
+                }
+                if states
+                    .iter()
+                    .any(|state| state.iter().flatten().all(|case| *case == 0))
+                {
+                    return Err(error("empty state".to_owned()));
+                }
+                let kicks = match (&piece.kicks, &kicks) {
+                    (own, _) if !own.is_empty() => own.clone(),
+                    (_, set) if !set.is_empty() => set.clone(),
+                    _ => vec![vec![(0, 0)]],
+                };
+                if kicks.len() != 1 && kicks.len() != states.len() {
+                    return Err(error(format!(
+                        "{} kick lists, not 1 or one per state ({})",
+                        kicks.len(),
+                        states.len()
+                    )));
+                }
+                Ok(Tetrimino {
+                    states,
+                    x: piece.spawn.0,
+                    y: piece.spawn.1,
+                    current_state: 0,
+                    kicks,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(PieceSet {
+            name,
+            kicks,
+            pieces,
+            tetriminos,
+        })
+    }
+
+    pub fn parse(content: &str) -> Result<PieceSet, String> {
+        let set = serde_json::from_str::<PieceSet>(content).map_err(|e| e.to_string())?;
+        PieceSet::new(set.name, set.kicks, set.pieces)
+    }
+
+    /// A set by name: `tetrominoes` for the built-in pieces, or the
+    /// polyominoes of a size, e.g. `pentominoes`, with their mirror images
+    /// unless it starts with `free-`.
+    pub fn named(name: &str) -> Option<PieceSet> {
+        let (free, size_name) = match name.strip_prefix("free-") {
+            Some(size_name) => (true, size_name),
+            None => (false, name),
+        };
+        let size = polyomino::SIZE_NAMES
+            .iter()
+            .position(|known| *known == size_name)?
+            + 1;
+        if size == 4 && !free {
+            return Some(PieceSet::builtin());
+        }
+        Some(polyomino::piece_set(size, free))
+    }
+
+    /// The set named `name`, see `PieceSet::named`, or the one of the file
+    /// `name`.
+    pub fn named_or_load(name: &str) -> Result<PieceSet, String> {
+        match PieceSet::named(name) {
+            Some(set) => Ok(set),
+            None => PieceSet::load(name),
+        }
+    }
+
+    pub fn load(file_name: &str) -> Result<PieceSet, String> {
+        let mut f = File::open(file_name).map_err(|e| format!("{}: {}", file_name, e))?;
+        let mut content = String::new();
+        f.read_to_string(&mut content)
+            .map_err(|e| format!("{}: {}", file_name, e))?;
+        PieceSet::parse(&content).map_err(|e| format!("{}: {}", file_name, e))
+    }
+
+    /// `PIECES_FILE` if there is one, the built-in pieces otherwise.
+    pub fn load_or_builtin() -> Result<PieceSet, String> {
+        if Path::new(PIECES_FILE).exists() {
+            PieceSet::load(PIECES_FILE)
+        } else {
+            Ok(PieceSet::builtin())
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tetriminos.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tetriminos.is_empty()
+    }
+
+    /// The kind of the piece named `name`.
+    pub fn kind(&self, name: &str) -> Option<u8> {
+        self.pieces
+            .iter()
+            .position(|piece| piece.name == name)
+            .map(|nb| nb as u8 + 1)
+    }
+
+    /// Creates a piece at its spawn position from its kind, which is also
+    /// the value of its cases: 1 for the first piece of the set, and so on.
+    pub fn piece(&self, kind: u8) -> Tetrimino {
+        match self.tetriminos.get((kind as usize).wrapping_sub(1)) {
+            Some(piece) => piece.clone(),
+            None => panic!("Unknown tetrimino kind {}", kind),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definition(name: &str, states: &[&[&str]], kicks: Kicks) -> PieceDefinition {
+        PieceDefinition {
+            name: name.to_owned(),
+            spawn: (4, 0),
+            colour: None,
+            states: states
+                .iter()
+                .map(|rows| rows.iter().map(|row| row.to_string()).collect())
+                .collect(),
+            kicks,
+        }
+    }
+
+    const BAR: &[&[&str]] = &[&["###", "...", "..."], &[".#.", ".#.", ".#."]];
+
+    #[test]
+    fn pieces_without_kicks_rotate_in_place() {
+        let set = PieceSet::new(
+            "bars".to_owned(),
+            Kicks::new(),
+            vec![definition("bar", BAR, Kicks::new())],
+        )
+        .unwrap();
+        let mut piece = set.piece(1);
+        assert_eq!(piece.kicks, vec![vec![(0, 0)]]);
+        piece.rotate(&vec![vec![0; 10]; 16]);
+        assert_eq!((piece.x, piece.y, piece.current_state), (4, 0, 1));
+    }
+
+    #[test]
+    fn kick_lists_are_one_or_one_per_state() {
+        let three = vec![vec![(0, 0)]; 3];
+        let error = PieceSet::new(
+            "bars".to_owned(),
+            Kicks::new(),
+            vec![definition("bar", BAR, three.clone())],
+        )
+        .unwrap_err();
+        assert!(error.contains("piece bar"), "{}", error);
+
+        // The kicks of the set are checked against every piece using them.
+        let error = PieceSet::new(
+            "bars".to_owned(),
+            three,
+            vec![
+                definition("bar", BAR, vec![vec![(0, 0)]; 2]),
+                definition("other bar", BAR, Kicks::new()),
+            ],
+        )
+        .unwrap_err();
+        assert!(error.contains("piece other bar"), "{}", error);
+
+        assert!(PieceSet::new(
+            "bars".to_owned(),
+            vec![vec![(0, 0)]; 2],
+            vec![definition("bar", BAR, Kicks::new())],
+        )
+        .is_ok());
+    }
+}
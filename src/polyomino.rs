@@ -0,0 +1,155 @@
+//! Generates every polyomino of a size, as a piece set: the blocks grow one
+//! at a time from a single one, and the shapes equal after a rotation, or a
+//! reflection too for free polyominoes, are kept once.
+
+use crate::game::{Kicks, MAP_WIDTH};
+use crate::pieces::{PieceDefinition, PieceSet};
+
+use std::collections::BTreeSet;
+
+/// Polyominoes by size, from 1 block. The largest sets still have fewer
+/// kinds than the map can tell apart.
+pub const SIZE_NAMES: [&str; 7] = [
+    "monominoes",
+    "dominoes",
+    "trominoes",
+    "tetrominoes",
+    "pentominoes",
+    "hexominoes",
+    "heptominoes",
+];
+
+/// `(x, y)` of the blocks, `y` going down, sorted and moved against the top
+/// and left sides.
+type Shape = Vec<(i32, i32)>;
+
+fn normalize(mut shape: Shape) -> Shape {
+    let min_x = shape.iter().map(|(x, _)| *x).min().unwrap_or(0);
+    let min_y = shape.iter().map(|(_, y)| *y).min().unwrap_or(0);
+    for (x, y) in shape.iter_mut() {
+        *x -= min_x;
+        *y -= min_y;
+    }
+    shape.sort_unstable();
+    shape
+}
+
+/// A quarter turn clockwise.
+fn rotate(shape: &Shape) -> Shape {
+    normalize(shape.iter().map(|&(x, y)| (-y, x)).collect())
+}
+
+fn mirror(shape: &Shape) -> Shape {
+    normalize(shape.iter().map(|&(x, y)| (-x, y)).collect())
+}
+
+/// The shape and its different rotations, in order.
+fn rotations(shape: &Shape) -> Vec<Shape> {
+    let mut rotations = vec![shape.clone()];
+    loop {
+        let next = rotate(rotations.last().unwrap_or(shape));
+        if next == *shape {
+            return rotations;
+        }
+        rotations.push(next);
+    }
+}
+
+/// The same shape for all its rotations, and reflections if `free`.
+fn canonical(shape: &Shape, free: bool) -> Shape {
+    let mut shapes = rotations(shape);
+    if free {
+        shapes.extend(rotations(&mirror(shape)));
+    }
+    shapes.into_iter().min().unwrap_or_default()
+}
+
+/// Width and height.
+fn size(shape: &Shape) -> (i32, i32) {
+    (
+        shape.iter().map(|(x, _)| x + 1).max().unwrap_or(0),
+        shape.iter().map(|(_, y)| y + 1).max().unwrap_or(0),
+    )
+}
+
+/// Every polyomino of `nb_blocks` blocks, told apart up to rotation, and
+/// reflection too if `free`.
+pub fn polyominoes(nb_blocks: usize, free: bool) -> Vec<Shape> {
+    let mut shapes = BTreeSet::new();
+    if nb_blocks > 0 {
+        shapes.insert(vec![(0, 0)]);
+    }
+    for _ in 1..nb_blocks {
+        let mut bigger = BTreeSet::new();
+        for shape in &shapes {
+            for &(x, y) in shape {
+                for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                    let block = (x + dx, y + dy);
+                    if shape.contains(&block) {
+                        continue;
+                    }
+                    let mut grown = shape.clone();
+                    grown.push(block);
+                    bigger.insert(canonical(&normalize(grown), false));
+                }
+            }
+        }
+        shapes = bigger;
+    }
+    if free {
+        shapes = shapes.iter().map(|shape| canonical(shape, true)).collect();
+    }
+    shapes.into_iter().collect()
+}
+
+/// The rows of `shape` in a `side` x `side` box.
+fn rows(shape: &Shape, side: i32) -> Vec<String> {
+    (0..side)
+        .map(|y| {
+            (0..side)
+                .map(|x| if shape.contains(&(x, y)) { '#' } else { '.' })
+                .collect()
+        })
+        .collect()
+}
+
+/// The polyominoes of `nb_blocks` blocks as pieces, mirror images being
+/// pieces of their own unless `free`. Pieces spawn lying flat in the middle
+/// of the map, and rotate in a square box.
+pub fn piece_set(nb_blocks: usize, free: bool) -> PieceSet {
+    let pieces = polyominoes(nb_blocks, free)
+        .iter()
+        .enumerate()
+        .map(|(nb, shape)| {
+            let mut states = rotations(shape);
+            let flattest = (0..states.len())
+                .min_by_key(|&state| size(&states[state]).1)
+                .unwrap_or(0);
+            states.rotate_left(flattest);
+            let (width, height) = size(&states[0]);
+            PieceDefinition {
+                name: (nb + 1).to_string(),
+                spawn: ((MAP_WIDTH as isize - width as isize + 1) / 2, 0),
+                colour: None,
+                states: states
+                    .iter()
+                    .map(|state| rows(state, width.max(height)))
+                    .collect(),
+                kicks: Kicks::new(),
+            }
+        })
+        .collect();
+    // Sideways as far as the longest piece, closest first.
+    let kicks = vec![(0..nb_blocks as isize)
+        .flat_map(|distance| [(-distance, 0), (distance, 0)])
+        .skip(1)
+        .collect()];
+    let name = format!(
+        "{}{}",
+        if free { "free-" } else { "" },
+        SIZE_NAMES
+            .get(nb_blocks.wrapping_sub(1))
+            .unwrap_or(&"polyominoes")
+    );
+    PieceSet::new(name, kicks, pieces).expect("Generated pieces are valid")
+}
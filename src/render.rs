@@ -0,0 +1,621 @@
+//! Draws the games. Every block comes from a single atlas texture, and the
+//! locked blocks of each board are kept in a texture of their own, only
+//! redrawn when the map changes. Text is rendered once into textures too,
+//! kept until it changes.
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas, RenderTarget, Texture, TextureCreator};
+use sdl2::ttf::Font;
+
+use tetris::game::{Tetrimino, Tetris};
+use tetris::stats;
+use tetris::theme::{Rgb, Theme};
+use tetris::versus::Versus;
+
+use crate::layout::{BoardLayout, Layout, DEFAULT_CELL};
+use crate::skin;
+use tetris::effects::Effects;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+pub const FONT_FILE: &str = "assets/lucon.ttf";
+
+/// Tiles per row of the atlas.
+const ATLAS_COLUMNS: u32 = 16;
+
+fn color((r, g, b): Rgb) -> Color {
+    Color::RGB(r, g, b)
+}
+
+/// The theme a game is drawn with, and its blocks in an atlas: the pieces
+/// by kind minus one, garbage, then ghost. They're scaled to the cell size
+/// when drawn.
+pub struct Textures<'a> {
+    pub theme: Theme,
+    atlas: Texture<'a>,
+}
+
+impl<'a> Textures<'a> {
+    pub fn new<T: RenderTarget>(
+        canvas: &mut Canvas<T>,
+        texture_creator: &'a TextureCreator<T::Context>,
+        theme: &Theme,
+    ) -> Result<Textures<'a>, String> {
+        let nb_tiles = theme.pieces.len() as u32 + 2;
+        let mut atlas = texture_creator
+            .create_texture_target(
+                None,
+                DEFAULT_CELL * nb_tiles.min(ATLAS_COLUMNS),
+                DEFAULT_CELL * nb_tiles.div_ceil(ATLAS_COLUMNS),
+            )
+            .map_err(|e| e.to_string())?;
+        let mut colors = theme.pieces.to_vec();
+        colors.push(theme.garbage);
+        colors.push(theme.ghost);
+        let mut filled = Ok(());
+        canvas
+            .with_texture_canvas(&mut atlas, |atlas| {
+                filled = colors.iter().enumerate().try_for_each(|(tile, rgb)| {
+                    atlas.set_draw_color(color(*rgb));
+                    atlas.fill_rect(Textures::tile(tile as u32))
+                });
+            })
+            .map_err(|e| e.to_string())?;
+        filled?;
+        Ok(Textures {
+            theme: theme.clone(),
+            atlas,
+        })
+    }
+
+    fn tile(tile: u32) -> Rect {
+        Rect::new(
+            (tile % ATLAS_COLUMNS * DEFAULT_CELL) as i32,
+            (tile / ATLAS_COLUMNS * DEFAULT_CELL) as i32,
+            DEFAULT_CELL,
+            DEFAULT_CELL,
+        )
+    }
+
+    /// Where the block of a case of the game map is in the atlas.
+    fn block(&self, kind: u8) -> Rect {
+        let nb_pieces = self.theme.pieces.len() as u32;
+        match kind as u32 {
+            kind if kind <= nb_pieces => Textures::tile(kind - 1),
+            _ => Textures::tile(nb_pieces),
+        }
+    }
+
+    fn ghost(&self) -> Rect {
+        Textures::tile(self.theme.pieces.len() as u32 + 1)
+    }
+
+    /// Replaces the blocks found in the skin in `directory`.
+    pub fn apply_skin<T: RenderTarget>(
+        &mut self,
+        canvas: &mut Canvas<T>,
+        texture_creator: &'a TextureCreator<T::Context>,
+        directory: &Path,
+    ) -> Result<(), String> {
+        let tiles = skin::load_tiles(canvas, texture_creator, directory, DEFAULT_CELL)?;
+        let blocks = tiles
+            .iter()
+            .map(|(kind, _)| self.block(*kind))
+            .collect::<Vec<_>>();
+        let mut copied = Ok(());
+        canvas
+            .with_texture_canvas(&mut self.atlas, |atlas| {
+                copied = tiles
+                    .iter()
+                    .zip(&blocks)
+                    .try_for_each(|((_, tile), block)| atlas.copy(tile, None, *block));
+            })
+            .map_err(|e| e.to_string())?;
+        copied
+    }
+}
+
+/// The HUD font, loaded at a size depending on the layout.
+pub struct Text<'a> {
+    pub font: Font<'a, 'static>,
+    /// Point size of `font`.
+    pub size: u16,
+}
+
+/// Where a line of text is drawn: the line of the HUD or of the game over
+/// summary of a board.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum TextSlot {
+    Hud(usize, usize),
+    GameOver(usize, usize),
+}
+
+/// A line of text, rendered again only when it, its colour or the font size
+/// changes.
+struct TextCache<'a> {
+    texture: Texture<'a>,
+    content: String,
+    rgb: Rgb,
+    size: u16,
+}
+
+/// The locked blocks of a board, drawn over the grid colour.
+struct BoardCache<'a> {
+    texture: Texture<'a>,
+    game_map: Vec<Vec<u8>>,
+    cell: u32,
+}
+
+pub struct Renderer<'a, T: RenderTarget> {
+    texture_creator: &'a TextureCreator<T::Context>,
+    textures: Textures<'a>,
+    boards: Vec<Option<BoardCache<'a>>>,
+    texts: HashMap<TextSlot, TextCache<'a>>,
+}
+
+/// Draws a piece in its first state, with `cell` sized cases. Returns the
+/// height drawn, down to its lowest case.
+fn draw_preview<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    textures: &Textures,
+    piece: &Tetrimino,
+    (x, y): (i32, i32),
+    cell: u32,
+) -> Result<u32, String> {
+    let mut height = 0;
+    for (line_nb, line) in piece.states[0].iter().enumerate() {
+        for (case_nb, case) in line.iter().enumerate() {
+            if *case == 0 {
+                continue;
+            }
+            canvas.copy(
+                &textures.atlas,
+                textures.block(*case),
+                Rect::new(
+                    x + case_nb as i32 * cell as i32,
+                    y + line_nb as i32 * cell as i32,
+                    cell,
+                    cell,
+                ),
+            )?;
+            height = (line_nb as u32 + 1) * cell;
+        }
+    }
+    Ok(height)
+}
+
+/// Half cells, smaller for pieces wider than four of them.
+fn preview_cell(piece: &Tetrimino, cell: u32) -> u32 {
+    let width = piece.states[0][0].len().max(4) as u32;
+    (cell * 2 / width).max(1)
+}
+
+/// Draws the cases of `piece` with the `tile` of the atlas.
+fn draw_piece<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    textures: &Textures,
+    tile: Rect,
+    piece: &Tetrimino,
+    (grid, cell): (Rect, u32),
+) -> Result<(), String> {
+    for (line_nb, line) in piece.states[piece.current_state as usize]
+        .iter()
+        .enumerate()
+    {
+        for (case_nb, case) in line.iter().enumerate() {
+            if *case == 0 {
+                continue;
+            }
+            canvas.copy(
+                &textures.atlas,
+                tile,
+                Rect::new(
+                    grid.x() + (piece.x + case_nb as isize) as i32 * cell as i32,
+                    grid.y() + (piece.y + line_nb) as i32 * cell as i32,
+                    cell,
+                    cell,
+                ),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Fills the case `(x, y)` of the grid with a possibly translucent colour.
+fn fill_case<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    grid: Rect,
+    cell: u32,
+    (x, y): (i32, i32),
+    rgba: (Rgb, u8),
+) -> Result<(), String> {
+    let ((r, g, b), a) = rgba;
+    canvas.set_draw_color(Color::RGBA(r, g, b, a));
+    canvas.fill_rect(Rect::new(
+        grid.x() + x * cell as i32,
+        grid.y() + y * cell as i32,
+        cell,
+        cell,
+    ))
+}
+
+fn draw_effects<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    theme: &Theme,
+    effects: &Effects,
+    grid: Rect,
+    cell: u32,
+) -> Result<(), String> {
+    const WHITE: Rgb = (255, 255, 255);
+
+    if let Some(ref line_clear) = effects.line_clear {
+        // The cleared lines flash, then dissolve from the middle outwards.
+        let progress = line_clear.progress();
+        let alpha = (220. * (1. - progress)) as u8;
+        for &row in &line_clear.rows {
+            let middle = line_clear.width as f32 / 2.;
+            for x in 0..line_clear.width {
+                let distance = (x as f32 + 0.5 - middle).abs();
+                let case = (x as i32, row as i32);
+                if distance < progress * (middle + 0.5) {
+                    fill_case(canvas, grid, cell, case, (theme.grid, 255))?;
+                } else {
+                    fill_case(canvas, grid, cell, case, (WHITE, alpha))?;
+                }
+            }
+        }
+    }
+    for trail in &effects.trails {
+        let rgb = theme.block(trail.kind).unwrap_or(WHITE);
+        let alpha = Effects::trail_alpha(trail);
+        for &(x, y) in &trail.cases {
+            for step in 1..=trail.length {
+                // Fades out with the distance to the piece.
+                let alpha = (alpha as usize * (trail.length + 1 - step) / (trail.length + 1)) as u8;
+                fill_case(
+                    canvas,
+                    grid,
+                    cell,
+                    (x as i32, (y - step) as i32),
+                    (rgb, alpha),
+                )?;
+            }
+        }
+    }
+    for flash in &effects.flashes {
+        let alpha = Effects::flash_alpha(flash);
+        for &(x, y) in &flash.cases {
+            fill_case(canvas, grid, cell, (x as i32, y as i32), (WHITE, alpha))?;
+        }
+    }
+    let size = (cell / 4).max(1);
+    for particle in &effects.particles {
+        let (r, g, b) = theme.block(particle.kind).unwrap_or(WHITE);
+        canvas.set_draw_color(Color::RGBA(r, g, b, Effects::particle_alpha(particle)));
+        canvas.fill_rect(Rect::new(
+            grid.x() + (particle.x * cell as f32) as i32,
+            grid.y() + (particle.y * cell as f32) as i32,
+            size,
+            size,
+        ))?;
+    }
+    Ok(())
+}
+
+impl<'a, T: RenderTarget> Renderer<'a, T> {
+    pub fn new(
+        texture_creator: &'a TextureCreator<T::Context>,
+        textures: Textures<'a>,
+    ) -> Renderer<'a, T> {
+        Renderer {
+            texture_creator,
+            textures,
+            boards: Vec::new(),
+            texts: HashMap::new(),
+        }
+    }
+
+    pub fn set_textures(&mut self, textures: Textures<'a>) {
+        self.textures = textures;
+        self.boards.clear();
+    }
+
+    /// Draws `content` at `(x, y)`, rendering it again only if it isn't what
+    /// was last drawn in `slot`.
+    fn draw_text(
+        &mut self,
+        canvas: &mut Canvas<T>,
+        (slot, text): (TextSlot, &Text),
+        content: &str,
+        rgb: Rgb,
+        (x, y): (i32, i32),
+    ) -> Result<(), String> {
+        let fresh = match self.texts.get(&slot) {
+            Some(cache) => cache.content == content && cache.rgb == rgb && cache.size == text.size,
+            None => false,
+        };
+        if !fresh {
+            let surface = text
+                .font
+                .render(content)
+                .blended(color(rgb))
+                .map_err(|e| e.to_string())?;
+            let texture = self
+                .texture_creator
+                .create_texture_from_surface(&surface)
+                .map_err(|e| e.to_string())?;
+            self.texts.insert(
+                slot,
+                TextCache {
+                    texture,
+                    content: content.to_owned(),
+                    rgb,
+                    size: text.size,
+                },
+            );
+        }
+        let texture = &self.texts[&slot].texture;
+        let query = texture.query();
+        canvas.copy(texture, None, Rect::new(x, y, query.width, query.height))
+    }
+
+    /// Score, lines and level, under the hold piece.
+    fn draw_hud(
+        &mut self,
+        canvas: &mut Canvas<T>,
+        (nb, tetris): (usize, &Tetris),
+        text: &Text,
+        (x, y): (i32, i32),
+    ) -> Result<(), String> {
+        let lines = [
+            format!("Score {}", tetris.score),
+            format!("Lines {}", tetris.nb_lines),
+            format!("Level {}", tetris.current_level),
+        ];
+        let rgb = self.textures.theme.text;
+        let line_height = text.font.height() + text.font.height() / 2;
+        for (line_nb, line) in lines.iter().enumerate() {
+            self.draw_text(
+                canvas,
+                (TextSlot::Hud(nb, line_nb), text),
+                line,
+                rgb,
+                (x, y + line_nb as i32 * line_height),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Redraws the cached board `nb` if `game_map` or the cell size changed.
+    fn update_board(
+        &mut self,
+        canvas: &mut Canvas<T>,
+        nb: usize,
+        game_map: &[Vec<u8>],
+        cell: u32,
+    ) -> Result<(), String> {
+        if self.boards.len() <= nb {
+            self.boards.resize_with(nb + 1, || None);
+        }
+        if let Some(ref board) = self.boards[nb] {
+            if board.cell == cell && board.game_map == game_map {
+                return Ok(());
+            }
+        }
+        let width = game_map.first().map(|line| line.len()).unwrap_or(0) as u32;
+        let height = game_map.len() as u32;
+        let mut texture = match self.boards[nb].take() {
+            Some(board) if board.cell == cell => board.texture,
+            _ => self
+                .texture_creator
+                .create_texture_target(None, (width * cell).max(1), (height * cell).max(1))
+                .map_err(|e| e.to_string())?,
+        };
+
+        let textures = &self.textures;
+        let mut drawn = Ok(());
+        canvas
+            .with_texture_canvas(&mut texture, |board| {
+                board.set_draw_color(color(textures.theme.grid));
+                board.clear();
+                drawn = game_map.iter().enumerate().try_for_each(|(line_nb, line)| {
+                    line.iter()
+                        .enumerate()
+                        .filter(|(_, case)| **case != 0)
+                        .try_for_each(|(case_nb, case)| {
+                            board.copy(
+                                &textures.atlas,
+                                textures.block(*case),
+                                Rect::new(
+                                    case_nb as i32 * cell as i32,
+                                    line_nb as i32 * cell as i32,
+                                    cell,
+                                    cell,
+                                ),
+                            )
+                        })
+                });
+            })
+            .map_err(|e| e.to_string())?;
+        drawn?;
+        self.boards[nb] = Some(BoardCache {
+            texture,
+            game_map: game_map.to_vec(),
+            cell,
+        });
+        Ok(())
+    }
+
+    fn draw_player(
+        &mut self,
+        canvas: &mut Canvas<T>,
+        (nb, tetris): (usize, &Tetris),
+        pending_garbage: u32,
+        effects: Option<&Effects>,
+        (board, cell): (&BoardLayout, u32),
+    ) -> Result<(), String> {
+        let board = match effects {
+            Some(effects) => {
+                let (x, y) = effects.shake_offset();
+                board.shifted(((x * cell as f32) as i32, (y * cell as f32) as i32))
+            }
+            None => *board,
+        };
+        self.update_board(canvas, nb, &tetris.game_map, cell)?;
+
+        let textures = &self.textures;
+        let theme = &textures.theme;
+        canvas.set_draw_color(color(theme.border));
+        canvas.fill_rect(board.border)?;
+        if let Some(Some(ref cache)) = self.boards.get(nb) {
+            canvas.copy(&cache.texture, None, board.grid)?;
+        }
+
+        if pending_garbage > 0 {
+            // The garbage meter grows from the bottom of the grid, on its left.
+            let meter = board.garbage_meter;
+            let meter_height = pending_garbage.min(16) * meter.height() / 16;
+            canvas.set_draw_color(color(theme.border));
+            canvas.fill_rect(Rect::new(
+                meter.x(),
+                meter.bottom() - meter_height as i32,
+                meter.width(),
+                meter_height,
+            ))?;
+        }
+
+        if let Some(kind) = tetris.hold_piece {
+            let piece = tetris.pieces.piece(kind);
+            draw_preview(
+                canvas,
+                textures,
+                &piece,
+                board.preview,
+                preview_cell(&piece, cell),
+            )?;
+        }
+
+        // The next pieces, half a cell apart.
+        let (x, mut y) = board.next;
+        for &kind in &tetris.next_pieces {
+            let piece = tetris.pieces.piece(kind);
+            let height =
+                draw_preview(canvas, textures, &piece, (x, y), preview_cell(&piece, cell))?;
+            y += (height + cell / 2) as i32;
+        }
+
+        if let Some(ref piece) = tetris.current_piece {
+            // The ghost shows where the piece would land.
+            let mut ghost = piece.clone();
+            while ghost.change_position(&tetris.game_map, ghost.x, ghost.y + 1) {}
+            draw_piece(
+                canvas,
+                textures,
+                textures.ghost(),
+                &ghost,
+                (board.grid, cell),
+            )?;
+            draw_piece(
+                canvas,
+                textures,
+                textures.block(piece.kind()),
+                piece,
+                (board.grid, cell),
+            )?;
+        }
+
+        if let Some(effects) = effects {
+            draw_effects(canvas, theme, effects, board.grid, cell)?;
+        }
+        Ok(())
+    }
+
+    /// Draws the games in the viewport of `layout`, the rest of the canvas
+    /// being left black. `effects` may be empty if there are none. The frame
+    /// still has to be presented.
+    pub fn draw_frame(
+        &mut self,
+        canvas: &mut Canvas<T>,
+        layout: &Layout,
+        text: Option<&Text>,
+        games: &[&Tetris],
+        versus: Option<&Versus>,
+        effects: &[Effects],
+    ) -> Result<(), String> {
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+        canvas.set_draw_color(color(self.textures.theme.background));
+        canvas.fill_rect(layout.viewport)?;
+        for (nb, (tetris, board)) in games.iter().zip(&layout.boards).enumerate() {
+            let pending_garbage = match versus {
+                Some(versus) => versus.meters[nb].total(),
+                None => 0,
+            };
+            self.draw_player(
+                canvas,
+                (nb, tetris),
+                pending_garbage,
+                effects.get(nb),
+                (board, layout.cell),
+            )?;
+            if let Some(text) = text {
+                self.draw_hud(canvas, (nb, tetris), text, board.hud)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Draws the games ended, each covered by its summary headed by its
+    /// line of `headings`. Without text, only the boards are drawn.
+    pub fn draw_game_over(
+        &mut self,
+        canvas: &mut Canvas<T>,
+        layout: &Layout,
+        text: Option<&Text>,
+        games: &[&Tetris],
+        headings: &[String],
+    ) -> Result<(), String> {
+        self.draw_frame(canvas, layout, text, games, None, &[])?;
+        let text = match text {
+            Some(text) => text,
+            None => return Ok(()),
+        };
+        let (r, g, b) = self.textures.theme.background;
+        let rgb = self.textures.theme.text;
+        let width = layout.viewport.width() / games.len().max(1) as u32;
+        let line_height = text.font.height() + text.font.height() / 4;
+        for (nb, (tetris, heading)) in games.iter().zip(headings).enumerate() {
+            // The board still shows through.
+            let area = Rect::new(
+                layout.viewport.x() + (nb as u32 * width) as i32,
+                layout.viewport.y(),
+                width,
+                layout.viewport.height(),
+            );
+            canvas.set_draw_color(Color::RGBA(r, g, b, 220));
+            canvas.fill_rect(area)?;
+            let lines = std::iter::once(heading.clone())
+                .chain(std::iter::once(String::new()))
+                .chain(stats::game_over_lines(tetris));
+            for (line_nb, line) in lines.enumerate() {
+                if line.is_empty() {
+                    continue;
+                }
+                self.draw_text(
+                    canvas,
+                    (TextSlot::GameOver(nb, line_nb), text),
+                    &line,
+                    rgb,
+                    (
+                        area.x() + layout.cell as i32,
+                        area.y() + layout.cell as i32 + line_nb as i32 * line_height,
+                    ),
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
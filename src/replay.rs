@@ -0,0 +1,164 @@
+//! Recorded games. Games created with the same seed and fed the same inputs
+//! at the same ticks stay identical, so a replay only stores those.
+//!
+//! A replay file has one entry per line:
+//!
+//! ```text
+//! seed 1234
+//! players 2
+//! attack 0 0 1 2 4
+//! input 120 0 L
+//! input 121 1 H
+//! end 5400
+//! ```
+//!
+//! `attack` is only there for versus games, `pieces`, followed by a piece
+//! set on one line, for games without the built-in pieces, and `delays
+//! ENTRY LINE_CLEAR` for games with delays between the pieces. An `input
+//! TICK PLAYER ACTION` entry is an action of a player at a tick, written like
+//! in `Action::to_char`. `end` is the tick the game stopped at.
+
+use crate::delays::Delays;
+use crate::game::{Action, Tetris};
+use crate::pieces::PieceSet;
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Replay {
+    /// Player `nb` plays the game of `seed + nb`.
+    pub seed: u64,
+    pub nb_players: usize,
+    /// The attack table of a versus game.
+    pub attack_table: Option<Vec<u32>>,
+    /// The piece set, if not the built-in one.
+    pub pieces: Option<PieceSet>,
+    /// The delays between the pieces of the games.
+    pub delays: Delays,
+    /// `(tick, player, action)`, by tick.
+    pub inputs: Vec<(u64, usize, Action)>,
+    pub end: u64,
+}
+
+impl Replay {
+    pub fn new(seed: u64, nb_players: usize) -> Replay {
+        Replay {
+            seed,
+            nb_players,
+            attack_table: None,
+            pieces: None,
+            delays: Delays::default(),
+            inputs: Vec::new(),
+            end: 0,
+        }
+    }
+
+    /// The game of player `nb` at the first tick.
+    pub fn game(&self, nb: usize) -> Tetris {
+        let seed = self.seed.wrapping_add(nb as u64);
+        let mut tetris = match self.pieces {
+            Some(ref pieces) => Tetris::with_pieces(seed, Arc::new(pieces.clone())),
+            None => Tetris::with_seed(seed),
+        };
+        tetris.delays = self.delays;
+        tetris
+    }
+
+    /// Inputs must be recorded in the order of their ticks.
+    pub fn record(&mut self, tick: u64, player: usize, action: Action) {
+        self.inputs.push((tick, player, action));
+        self.end = self.end.max(tick);
+    }
+
+    /// The actions played at `tick`, as `(player, action)`.
+    pub fn inputs_at(&self, tick: u64) -> impl Iterator<Item = (usize, Action)> + '_ {
+        let start = self.inputs.partition_point(|input| input.0 < tick);
+        self.inputs[start..]
+            .iter()
+            .take_while(move |input| input.0 == tick)
+            .map(|&(_, player, action)| (player, action))
+    }
+
+    pub fn parse(content: &str) -> Option<Replay> {
+        let mut replay = Replay::new(0, 1);
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let mut words = line.split_whitespace();
+            match words.next()? {
+                "seed" => replay.seed = words.next()?.parse().ok()?,
+                "players" => replay.nb_players = words.next()?.parse().ok()?,
+                "attack" => {
+                    replay.attack_table = Some(
+                        words
+                            .map(|nb| nb.parse::<u32>().ok())
+                            .collect::<Option<Vec<u32>>>()?,
+                    )
+                }
+                "pieces" => {
+                    let (_, pieces) = line.trim().split_once(' ')?;
+                    replay.pieces = Some(PieceSet::parse(pieces).ok()?);
+                }
+                "delays" => {
+                    replay.delays = Delays {
+                        entry: words.next()?.parse().ok()?,
+                        line_clear: words.next()?.parse().ok()?,
+                    }
+                }
+                "input" => {
+                    let tick = words.next()?.parse::<u64>().ok()?;
+                    let player = words.next()?.parse::<usize>().ok()?;
+                    let action = Action::from_char(words.next()?.chars().next()?)?;
+                    if player >= replay.nb_players || replay.end > tick {
+                        return None;
+                    }
+                    replay.record(tick, player, action);
+                }
+                "end" => replay.end = words.next()?.parse().ok()?,
+                _ => return None,
+            }
+        }
+        if replay.nb_players == 0 {
+            return None;
+        }
+        Some(replay)
+    }
+
+    pub fn load(file_name: &str) -> Option<Replay> {
+        let mut f = File::open(file_name).ok()?;
+        let mut content = String::new();
+        f.read_to_string(&mut content).ok()?;
+        Replay::parse(&content)
+    }
+
+    pub fn save(&self, file_name: &str) -> io::Result<()> {
+        let mut f = File::create(file_name)?;
+        f.write_all(self.to_string().as_bytes())
+    }
+}
+
+impl fmt::Display for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "seed {}", self.seed)?;
+        writeln!(f, "players {}", self.nb_players)?;
+        if let Some(ref attack_table) = self.attack_table {
+            let lines = attack_table
+                .iter()
+                .map(|lines| lines.to_string())
+                .collect::<Vec<_>>();
+            writeln!(f, "attack {}", lines.join(" "))?;
+        }
+        if let Some(ref pieces) = self.pieces {
+            let pieces = serde_json::to_string(pieces).map_err(|_| fmt::Error)?;
+            writeln!(f, "pieces {}", pieces)?;
+        }
+        if self.delays != Delays::default() {
+            writeln!(f, "delays {} {}", self.delays.entry, self.delays.line_clear)?;
+        }
+        for &(tick, player, action) in &self.inputs {
+            writeln!(f, "input {} {} {}", tick, player, action.to_char())?;
+        }
+        writeln!(f, "end {}", self.end)
+    }
+}
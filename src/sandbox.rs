@@ -0,0 +1,556 @@
+//! Practice on a board set up by hand: cases painted and erased, the current
+//! and next pieces picked, placements undone and redone, and setups saved
+//! as text grids to drill openers. The pages of a fumen are practiced one
+//! placement after the other.
+//!
+//! A setup file names the pieces as their piece set does, then has one line
+//! per row of the map: `.` for an empty case, `G` for garbage and the name
+//! of a piece for one of its blocks. Pieces with longer names are written as
+//! garbage. The `current`, `next` and `hold` lines are optional.
+//!
+//! ```text
+//! current T
+//! next I O L
+//! hold S
+//! ..........
+//! ...
+//! GGGGGG.GGG
+//! ```
+
+use crate::fumen::Page;
+use crate::game::{Tetris, GARBAGE, MAP_HEIGHT, MAP_WIDTH};
+use crate::pieces::PieceSet;
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+pub const SETUP_FILE: &str = "setup.txt";
+/// Placements that can be undone, the oldest are forgotten first.
+pub const MAX_UNDO: usize = 100;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Setup {
+    pub game_map: Vec<Vec<u8>>,
+    pub current: Option<u8>,
+    /// The first next pieces, the others are random.
+    pub next: Vec<u8>,
+    pub hold: Option<u8>,
+}
+
+fn case_name(pieces: &PieceSet, kind: u8) -> char {
+    let name = match kind {
+        0 => return '.',
+        GARBAGE => None,
+        kind => pieces
+            .pieces
+            .get(kind as usize - 1)
+            .map(|piece| &piece.name),
+    };
+    let mut chars = name.map(|name| name.chars()).into_iter().flatten();
+    match (chars.next(), chars.next()) {
+        (Some(name), None) => name,
+        _ => 'G',
+    }
+}
+
+fn case_kind(pieces: &PieceSet, name: char) -> Option<u8> {
+    match name {
+        '.' => Some(0),
+        name => pieces
+            .kind(&name.to_string())
+            .or(if name == 'G' { Some(GARBAGE) } else { None }),
+    }
+}
+
+impl Setup {
+    /// An empty map, without any piece picked.
+    pub fn new() -> Setup {
+        Setup {
+            game_map: vec![vec![0; MAP_WIDTH]; MAP_HEIGHT],
+            current: None,
+            next: Vec::new(),
+            hold: None,
+        }
+    }
+
+    /// A game of `pieces` with this setup, drawing the pieces after the
+    /// picked ones from `seed`. The picked pieces past the next ones are
+    /// queued.
+    pub fn game(&self, seed: u64, pieces: Arc<PieceSet>) -> Result<Tetris, String> {
+        let mut tetris = Tetris::with_pieces(seed, pieces);
+        tetris.game_map = self.game_map.clone();
+        tetris.hold_piece = self.hold;
+        for (nb, kind) in self.next.iter().enumerate() {
+            match tetris.next_pieces.get_mut(nb) {
+                Some(next) => *next = *kind,
+                None => tetris.queued.push_back(*kind),
+            }
+        }
+        if let Some(kind) = self.current {
+            if !tetris.replace_current_piece(kind) {
+                return Err("the current piece doesn't fit".to_owned());
+            }
+        }
+        Ok(tetris)
+    }
+
+    /// The board and piece of `pages[page]`, the pieces of the pages after
+    /// it coming next: the first ones in the preview, the others queued by
+    /// `Setup::game`.
+    pub fn from_page(pages: &[Page], page: usize) -> Setup {
+        let kind = |page: &Page| page.piece.map(|piece| piece.0);
+        Setup {
+            game_map: pages[page].game_map.clone(),
+            current: kind(&pages[page]),
+            next: pages[page + 1..].iter().filter_map(kind).collect(),
+            hold: None,
+        }
+    }
+
+    pub fn parse(content: &str, pieces: &PieceSet) -> Result<Setup, String> {
+        let mut setup = Setup::new();
+        let mut rows = Vec::new();
+        let piece_kind = |name: &str| {
+            pieces
+                .kind(name)
+                .ok_or_else(|| format!("unknown piece {:?}", name))
+        };
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("current") => {
+                    let name = words.next().ok_or("no current piece")?;
+                    setup.current = Some(piece_kind(name)?);
+                }
+                Some("next") => setup.next = words.map(piece_kind).collect::<Result<_, _>>()?,
+                Some("hold") => {
+                    let name = words.next().ok_or("no hold piece")?;
+                    setup.hold = Some(piece_kind(name)?);
+                }
+                _ => rows.push(
+                    line.trim()
+                        .chars()
+                        .map(|name| {
+                            case_kind(pieces, name)
+                                .ok_or_else(|| format!("unexpected {:?} in {:?}", name, line))
+                        })
+                        .collect::<Result<Vec<u8>, String>>()?,
+                ),
+            }
+        }
+        if rows.len() != MAP_HEIGHT || rows.iter().any(|row| row.len() != MAP_WIDTH) {
+            return Err(format!(
+                "the map has to be {} rows of {} cases",
+                MAP_HEIGHT, MAP_WIDTH
+            ));
+        }
+        setup.game_map = rows;
+        Ok(setup)
+    }
+
+    pub fn to_string(&self, pieces: &PieceSet) -> String {
+        let name = |kind: &u8| pieces.pieces[*kind as usize - 1].name.clone();
+        let mut content = String::new();
+        if let Some(ref kind) = self.current {
+            content += &format!("current {}\n", name(kind));
+        }
+        if !self.next.is_empty() {
+            let next = self.next.iter().map(name).collect::<Vec<_>>();
+            content += &format!("next {}\n", next.join(" "));
+        }
+        if let Some(ref kind) = self.hold {
+            content += &format!("hold {}\n", name(kind));
+        }
+        for line in &self.game_map {
+            content.extend(line.iter().map(|kind| case_name(pieces, *kind)));
+            content.push('\n');
+        }
+        content
+    }
+
+    pub fn load(file_name: &str, pieces: &PieceSet) -> Result<Setup, String> {
+        let mut f = File::open(file_name).map_err(|e| format!("{}: {}", file_name, e))?;
+        let mut content = String::new();
+        f.read_to_string(&mut content)
+            .map_err(|e| format!("{}: {}", file_name, e))?;
+        Setup::parse(&content, pieces).map_err(|e| format!("{}: {}", file_name, e))
+    }
+
+    pub fn save(&self, file_name: &str, pieces: &PieceSet) -> Result<(), String> {
+        let mut f = File::create(file_name).map_err(|e| format!("{}: {}", file_name, e))?;
+        f.write_all(self.to_string(pieces).as_bytes())
+            .map_err(|e| format!("{}: {}", file_name, e))
+    }
+}
+
+impl Default for Setup {
+    fn default() -> Setup {
+        Setup::new()
+    }
+}
+
+fn spawned(tetris: &Tetris) -> u32 {
+    tetris.statistics.spawned.iter().sum()
+}
+
+/// The pages of a fumen played in turn, each piece to be placed where its
+/// page shows it.
+pub struct Practice {
+    pub pages: Vec<Page>,
+    /// The page the game started from.
+    pub first: usize,
+    /// Pieces locked when the game started.
+    locked: u32,
+    /// Whether each piece locked since was placed right.
+    results: Vec<bool>,
+}
+
+impl Practice {
+    /// The page to play, past the last one once they're all played.
+    pub fn page(&self) -> usize {
+        self.first + self.results.len()
+    }
+
+    /// Whether the last piece was placed right, if any was.
+    pub fn last_result(&self) -> Option<bool> {
+        self.results.last().cloned()
+    }
+}
+
+/// What the player did to a practice game besides playing it.
+pub struct Sandbox {
+    /// How many pieces will have spawned once the picked ones have, which
+    /// follows the queue through placements undone and redone.
+    picked_until: u32,
+    pub practice: Option<Practice>,
+}
+
+impl Sandbox {
+    /// Also makes the placements of `tetris` undoable.
+    pub fn new(tetris: &mut Tetris, setup: &Setup) -> Sandbox {
+        tetris.enable_history(MAX_UNDO);
+        Sandbox {
+            picked_until: spawned(tetris) + setup.next.len() as u32,
+            practice: None,
+        }
+    }
+
+    /// A game practicing `pages` from `page` on, its pieces after the
+    /// pages' drawn from `seed`.
+    pub fn practice(
+        pages: Vec<Page>,
+        page: usize,
+        seed: u64,
+        pieces: Arc<PieceSet>,
+    ) -> Result<(Sandbox, Tetris), String> {
+        if page >= pages.len() {
+            return Err(format!("there's no page {}", page + 1));
+        }
+        let setup = Setup::from_page(&pages, page);
+        let mut tetris = setup.game(seed, pieces)?;
+        let mut sandbox = Sandbox::new(&mut tetris, &setup);
+        sandbox.practice = Some(Practice {
+            pages,
+            first: page,
+            locked: tetris.statistics.locked,
+            results: Vec::new(),
+        });
+        Ok((sandbox, tetris))
+    }
+
+    /// Judges the piece locked by the last tick of `tetris`, if any, against
+    /// its page. Placements undone are forgotten.
+    pub fn after_tick(&mut self, tetris: &Tetris) {
+        let practice = match self.practice {
+            Some(ref mut practice) => practice,
+            None => return,
+        };
+        let locked = tetris.statistics.locked.saturating_sub(practice.locked) as usize;
+        practice.results.truncate(locked);
+        if locked > practice.results.len() {
+            let right = practice
+                .pages
+                .get(practice.page())
+                .is_some_and(|page| page.locked(&tetris.pieces) == tetris.game_map);
+            practice.results.push(right);
+        }
+    }
+
+    /// The setup of `tetris`, with only the picked next pieces.
+    pub fn setup(&self, tetris: &Tetris) -> Setup {
+        Setup {
+            game_map: tetris.game_map.clone(),
+            current: tetris.current_piece.as_ref().map(|piece| piece.kind()),
+            next: tetris
+                .next_pieces
+                .iter()
+                .chain(&tetris.queued)
+                .take(self.picked(tetris))
+                .cloned()
+                .collect(),
+            hold: tetris.hold_piece,
+        }
+    }
+
+    /// Sets the case `(x, y)` to `kind`, 0 to erase it, unless the current
+    /// piece is there.
+    pub fn paint(&mut self, tetris: &mut Tetris, (x, y): (usize, usize), kind: u8) -> bool {
+        if y >= tetris.game_map.len() || x >= tetris.game_map[y].len() {
+            return false;
+        }
+        let previous = tetris.game_map[y][x];
+        tetris.game_map[y][x] = kind;
+        if let Some(ref piece) = tetris.current_piece {
+            if !piece.test_current_position(&tetris.game_map) {
+                tetris.game_map[y][x] = previous;
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Empties the map, but for the current piece.
+    pub fn clear(&mut self, tetris: &mut Tetris) {
+        tetris.game_map = vec![vec![0; MAP_WIDTH]; MAP_HEIGHT];
+    }
+
+    /// Replaces the current piece with one of `kind`, at its spawn position.
+    pub fn set_current(&mut self, tetris: &mut Tetris, kind: u8) -> bool {
+        tetris.replace_current_piece(kind)
+    }
+
+    /// Adds a piece of `kind` after the picked ones.
+    pub fn pick_next(&mut self, tetris: &mut Tetris, kind: u8) {
+        let picked = self.picked(tetris);
+        match tetris.next_pieces.get_mut(picked) {
+            Some(next) => *next = kind,
+            None => {
+                let queued = picked - tetris.next_pieces.len();
+                tetris.queued.truncate(queued);
+                tetris.queued.push_back(kind);
+            }
+        }
+        self.picked_until = spawned(tetris) + picked as u32 + 1;
+    }
+
+    /// Forgets the last picked piece, which the next pick replaces. A
+    /// queued one is taken out of the queue.
+    pub fn unpick(&mut self, tetris: &mut Tetris) {
+        self.picked_until = self.picked_until.saturating_sub(1).max(spawned(tetris));
+        let queued = self.picked(tetris).saturating_sub(tetris.next_pieces.len());
+        tetris.queued.truncate(queued);
+    }
+
+    /// The next pieces picked by hand, at the front of the queue.
+    pub fn picked(&self, tetris: &Tetris) -> usize {
+        self.picked_until.saturating_sub(spawned(tetris)) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{Action, NB_NEXT_PIECES};
+    use crate::test_utils::{empty_map, played};
+
+    fn setup_text() -> String {
+        let mut content = "current T\nnext I O\nhold S\n".to_owned();
+        for _ in 0..MAP_HEIGHT - 2 {
+            content += "..........\n";
+        }
+        content += "ZZ........\nGGGGGG.GGG\n";
+        content
+    }
+
+    #[test]
+    fn setups_are_read_and_written_back() {
+        let pieces = PieceSet::builtin();
+        let setup = Setup::parse(&setup_text(), &pieces).unwrap();
+        assert_eq!(setup.current, pieces.kind("T"));
+        assert_eq!(
+            setup.next,
+            [pieces.kind("I").unwrap(), pieces.kind("O").unwrap()]
+        );
+        assert_eq!(setup.hold, pieces.kind("S"));
+        assert_eq!(setup.game_map[MAP_HEIGHT - 1][0], GARBAGE);
+        assert_eq!(setup.game_map[MAP_HEIGHT - 2][0], pieces.kind("Z").unwrap());
+        assert_eq!(setup.to_string(&pieces), setup_text());
+    }
+
+    #[test]
+    fn setups_have_a_whole_map() {
+        let pieces = PieceSet::builtin();
+        assert!(Setup::parse("current T\n..........\n", &pieces).is_err());
+        let text = setup_text().replace("ZZ........", "ZZ.......");
+        assert!(Setup::parse(&text, &pieces).is_err());
+        let text = setup_text().replace("current T", "current X");
+        assert!(Setup::parse(&text, &pieces).is_err());
+    }
+
+    #[test]
+    fn games_start_from_their_setup() {
+        let pieces = Arc::new(PieceSet::builtin());
+        let setup = Setup::parse(&setup_text(), &pieces).unwrap();
+        let mut tetris = setup.game(1, pieces.clone()).unwrap();
+        let sandbox = Sandbox::new(&mut tetris, &setup);
+        assert_eq!(sandbox.setup(&tetris), setup);
+        assert_eq!(
+            tetris.current_piece.as_ref().unwrap().kind(),
+            setup.current.unwrap()
+        );
+
+        // The picked pieces come next, then random ones.
+        tetris.apply_action(Action::HardDrop);
+        assert!(tetris.spawn());
+        assert_eq!(tetris.current_piece.as_ref().unwrap().kind(), setup.next[0]);
+        assert_eq!(sandbox.picked(&tetris), 1);
+        assert_eq!(sandbox.setup(&tetris).next, &setup.next[1..]);
+    }
+
+    #[test]
+    fn placements_are_undone_with_their_edits() {
+        let pieces = Arc::new(PieceSet::builtin());
+        let setup = Setup::parse(&setup_text(), &pieces).unwrap();
+        let mut tetris = setup.game(1, pieces).unwrap();
+        let mut sandbox = Sandbox::new(&mut tetris, &setup);
+        sandbox.paint(&mut tetris, (9, 0), GARBAGE);
+        let painted = played(&tetris);
+        tetris.apply_action(Action::HardDrop);
+        assert!(tetris.spawn());
+        let placed = played(&tetris);
+        assert_eq!(sandbox.picked(&tetris), 1);
+
+        assert!(tetris.undo());
+        assert_eq!(played(&tetris), painted);
+        assert_eq!(sandbox.picked(&tetris), 2);
+        assert!(!tetris.undo());
+        assert!(tetris.redo());
+        assert_eq!(played(&tetris), placed);
+        assert_eq!(sandbox.picked(&tetris), 1);
+    }
+
+    #[test]
+    fn pieces_picked_past_the_next_ones_are_queued() {
+        let pieces = Arc::new(PieceSet::builtin());
+        let mut setup = Setup::new();
+        setup.current = Some(1);
+        setup.next = (1..=7).collect();
+        let mut tetris = setup.game(1, pieces).unwrap();
+        let mut sandbox = Sandbox::new(&mut tetris, &setup);
+        assert_eq!(tetris.next_pieces.len(), NB_NEXT_PIECES);
+        assert_eq!(sandbox.setup(&tetris), setup);
+        sandbox.pick_next(&mut tetris, 4);
+        sandbox.unpick(&mut tetris);
+        sandbox.pick_next(&mut tetris, 2);
+        setup.next.push(2);
+        assert_eq!(sandbox.setup(&tetris), setup);
+
+        let picked = played(&tetris);
+        tetris.apply_action(Action::HardDrop);
+        assert!(tetris.spawn());
+        let placed = played(&tetris);
+        assert_eq!(tetris.current_piece.as_ref().unwrap().kind(), 1);
+        assert_eq!(sandbox.setup(&tetris).next, &setup.next[1..]);
+        assert!(tetris.undo());
+        assert_eq!(played(&tetris), picked);
+        assert_eq!(sandbox.setup(&tetris), setup);
+        assert!(tetris.redo());
+        assert_eq!(played(&tetris), placed);
+    }
+
+    #[test]
+    fn practice_judges_each_placement() {
+        let pieces = Arc::new(PieceSet::builtin());
+        let (i, o) = (pieces.kind("I").unwrap(), pieces.kind("O").unwrap());
+        let mut game_map = empty_map();
+        game_map[MAP_HEIGHT - 1][..6].copy_from_slice(&[GARBAGE; 6]);
+        let first = Page {
+            game_map,
+            piece: Some((i, 6, MAP_HEIGHT - 1, 0)),
+            comment: String::new(),
+        };
+        let second = Page {
+            game_map: first.locked(&pieces),
+            piece: Some((o, 0, MAP_HEIGHT - 2, 0)),
+            comment: String::new(),
+        };
+        let (mut sandbox, mut tetris) =
+            Sandbox::practice(vec![first, second], 0, 1, pieces).unwrap();
+        assert_eq!(tetris.current_piece.as_ref().unwrap().kind(), i);
+        assert_eq!(tetris.next_pieces[0], o);
+
+        tetris.apply_action(Action::Right);
+        tetris.apply_action(Action::Right);
+        tetris.apply_action(Action::HardDrop);
+        sandbox.after_tick(&tetris);
+        let practice = sandbox.practice.as_ref().unwrap();
+        assert_eq!((practice.page(), practice.last_result()), (1, Some(true)));
+
+        assert!(tetris.undo());
+        sandbox.after_tick(&tetris);
+        tetris.apply_action(Action::HardDrop);
+        sandbox.after_tick(&tetris);
+        let practice = sandbox.practice.as_ref().unwrap();
+        assert_eq!((practice.page(), practice.last_result()), (1, Some(false)));
+    }
+
+    #[test]
+    fn long_practices_are_undone() {
+        let pieces = Arc::new(PieceSet::builtin());
+        let o = pieces.kind("O").unwrap();
+        let mut spawned = pieces.piece(o);
+        let game_map = empty_map();
+        while spawned.change_position(&game_map, spawned.x - 1, spawned.y) {}
+        // Two rows of four Os, the last two columns left empty.
+        let mut pages: Vec<Page> = Vec::new();
+        for nb in 0..8 {
+            let game_map = pages
+                .last()
+                .map_or(game_map.clone(), |page| page.locked(&pieces));
+            let mut piece = pieces.piece(o);
+            piece.x = spawned.x + 2 * (nb % 4);
+            while piece.change_position(&game_map, piece.x, piece.y + 1) {}
+            pages.push(Page {
+                game_map,
+                piece: Some((o, piece.x, piece.y, 0)),
+                comment: String::new(),
+            });
+        }
+        let (mut sandbox, mut tetris) = Sandbox::practice(pages, 0, 1, pieces).unwrap();
+        assert_eq!(tetris.next_pieces.len(), NB_NEXT_PIECES);
+        assert_eq!(tetris.queued.len(), 2);
+
+        for _ in 0..MAP_WIDTH {
+            tetris.apply_action(Action::Left);
+        }
+        tetris.apply_action(Action::HardDrop);
+        sandbox.after_tick(&tetris);
+        assert!(tetris.spawn());
+        let practice = sandbox.practice.as_ref().unwrap();
+        assert_eq!((practice.page(), practice.last_result()), (1, Some(true)));
+
+        assert!(tetris.undo());
+        sandbox.after_tick(&tetris);
+        assert_eq!(sandbox.practice.as_ref().unwrap().page(), 0);
+        assert!(tetris.redo());
+        sandbox.after_tick(&tetris);
+        let practice = sandbox.practice.as_ref().unwrap();
+        assert_eq!((practice.page(), practice.last_result()), (1, Some(true)));
+    }
+
+    #[test]
+    fn paint_spares_the_current_piece() {
+        let pieces = Arc::new(PieceSet::builtin());
+        let setup = Setup::parse(&setup_text(), &pieces).unwrap();
+        let mut tetris = setup.game(1, pieces).unwrap();
+        let mut sandbox = Sandbox::new(&mut tetris, &setup);
+        let piece = tetris.current_piece.clone().unwrap();
+        let x = piece.x as usize;
+        assert!(!sandbox.paint(&mut tetris, (x, piece.y), GARBAGE));
+        assert_eq!(tetris.game_map[piece.y][x], 0);
+        assert!(sandbox.paint(&mut tetris, (0, 5), GARBAGE));
+        assert!(sandbox.paint(&mut tetris, (0, MAP_HEIGHT - 1), 0));
+        assert_eq!(tetris.game_map[MAP_HEIGHT - 1][0], 0);
+    }
+}
@@ -0,0 +1,114 @@
+//! Suspended games. A save file holds the `GameState` of the game as JSON,
+//! after a line with its checksum:
+//!
+//! ```text
+//! checksum 5d1c3b0e6f2a9487
+//! {"game_map":[[0,0,0,0,0,0,0,0,0,0],...],"score":120,...}
+//! ```
+//!
+//! The checksum catches saves corrupted on disk or casually edited, which
+//! aren't resumed. Its key is in the source, so it doesn't stop anyone
+//! willing to recompute it.
+
+use crate::game::{GameState, Tetris};
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+
+pub const SAVE_FILE: &str = "save.txt";
+
+/// FNV-1a hash of `content`, keyed with `tetris-save` so that a hash of the
+/// JSON alone doesn't match.
+fn checksum(content: &str) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for byte in b"tetris-save".iter().chain(content.as_bytes()) {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+pub fn to_string(tetris: &Tetris) -> Result<String, String> {
+    let state = serde_json::to_string(&tetris.state()).map_err(|e| e.to_string())?;
+    Ok(format!("checksum {:016x}\n{}\n", checksum(&state), state))
+}
+
+pub fn parse(content: &str) -> Result<Tetris, String> {
+    let (first_line, state) = content
+        .split_once('\n')
+        .ok_or_else(|| "truncated save".to_owned())?;
+    let state = state.trim_end();
+    let expected = first_line
+        .strip_prefix("checksum ")
+        .and_then(|checksum| u64::from_str_radix(checksum.trim(), 16).ok())
+        .ok_or_else(|| "no checksum".to_owned())?;
+    if checksum(state) != expected {
+        return Err("the checksum doesn't match, the save was modified".to_owned());
+    }
+    let state = serde_json::from_str::<GameState>(state).map_err(|e| e.to_string())?;
+    Tetris::from_state(state)
+}
+
+pub fn save(tetris: &Tetris, file_name: &str) -> Result<(), String> {
+    let content = to_string(tetris)?;
+    let mut f = File::create(file_name).map_err(|e| format!("{}: {}", file_name, e))?;
+    f.write_all(content.as_bytes())
+        .map_err(|e| format!("{}: {}", file_name, e))
+}
+
+pub fn load(file_name: &str) -> Result<Tetris, String> {
+    let mut f = File::open(file_name).map_err(|e| format!("{}: {}", file_name, e))?;
+    let mut content = String::new();
+    f.read_to_string(&mut content)
+        .map_err(|e| format!("{}: {}", file_name, e))?;
+    parse(&content).map_err(|e| format!("{}: {}", file_name, e))
+}
+
+/// Removes the save, so that a game is only resumed once.
+pub fn remove(file_name: &str) -> Result<(), String> {
+    fs::remove_file(file_name).map_err(|e| format!("{}: {}", file_name, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Action;
+
+    fn played_game() -> Tetris {
+        let mut tetris = Tetris::with_seed(11);
+        for action in [Action::Left, Action::HardDrop, Action::Hold, Action::Rotate] {
+            tetris.apply_action(action);
+            tetris.spawn();
+        }
+        tetris
+    }
+
+    #[test]
+    fn saves_restore_the_same_state() {
+        let tetris = played_game();
+        let restored = parse(&to_string(&tetris).unwrap()).unwrap();
+        assert_eq!(restored.state(), tetris.state());
+    }
+
+    #[test]
+    fn modified_saves_are_rejected() {
+        let tetris = played_game();
+        let content = to_string(&tetris).unwrap();
+        let score = format!("\"score\":{}", tetris.score);
+        assert!(content.contains(&score));
+        let modified = content.replace(&score, "\"score\":999999");
+        match parse(&modified) {
+            Err(e) => assert!(e.contains("checksum"), "{}", e),
+            Ok(_) => panic!("The modified save was resumed"),
+        }
+    }
+
+    #[test]
+    fn truncated_saves_are_rejected() {
+        let content = to_string(&played_game()).unwrap();
+        assert!(parse(&content[..content.len() / 2]).is_err());
+        let first_line = content.lines().next().unwrap();
+        assert!(parse(first_line).is_err());
+        assert!(parse("").is_err());
+    }
+}
@@ -0,0 +1,49 @@
+//! Frames saved as PNG images, from the window or from a software renderer
+//! drawing on a surface. The latter needs no window at all, so it also works
+//! with the dummy video driver of a headless machine.
+
+use sdl2::image::SaveSurface;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::{Canvas, RenderTarget, TextureCreator};
+use sdl2::surface::{Surface, SurfaceContext};
+
+use crate::layout::Layout;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A canvas drawing on a surface in memory instead of a window.
+pub struct Offscreen {
+    pub canvas: Canvas<Surface<'static>>,
+    pub texture_creator: TextureCreator<SurfaceContext<'static>>,
+    pub layout: Layout,
+}
+
+impl Offscreen {
+    pub fn new(nb_players: usize, (width, height): (u32, u32)) -> Result<Offscreen, String> {
+        let canvas = Surface::new(width, height, PixelFormatEnum::ARGB8888)?.into_canvas()?;
+        let texture_creator = canvas.texture_creator();
+        Ok(Offscreen {
+            canvas,
+            texture_creator,
+            layout: Layout::new(nb_players, (width, height)),
+        })
+    }
+}
+
+/// Saves what was drawn on `canvas` as a PNG image.
+pub fn save_png<T: RenderTarget>(canvas: &Canvas<T>, file_name: &str) -> Result<(), String> {
+    let (width, height) = canvas.output_size()?;
+    let format = PixelFormatEnum::ABGR8888;
+    let mut pixels = canvas.read_pixels(None, format)?;
+    let surface = Surface::from_data(&mut pixels, width, height, width * 4, format)?;
+    surface.save(file_name)
+}
+
+/// A file name for a screenshot taken now, e.g. `screenshot-1700000000123.png`.
+pub fn file_name() -> String {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis())
+        .unwrap_or(0);
+    format!("screenshot-{}.png", millis)
+}
@@ -0,0 +1,313 @@
+//! The games played on this machine, advanced one tick at a time the same
+//! way whether the inputs come from the keyboard, the bot or a replay, in
+//! the window as in the terminal.
+
+use crate::bot::Bot;
+use crate::effects::{EffectSettings, Effects, LockEvent};
+use crate::game::{Action, Tetrimino, Tetris};
+use crate::replay::Replay;
+use crate::tbp::{ExternalBot, TbpError};
+use crate::versus::{AttackTable, Versus};
+
+use std::collections::VecDeque;
+
+/// Ticks between two actions of the bot, so that its moves can be followed.
+pub const BOT_FRAMES_PER_ACTION: u32 = 4;
+
+/// The keys of a player, of whatever type the frontend reads.
+pub struct Keymap<K> {
+    pub left: K,
+    pub right: K,
+    pub soft_drop: K,
+    pub rotate: K,
+    pub hard_drop: K,
+    pub hold: K,
+}
+
+impl<K: PartialEq> Keymap<K> {
+    pub fn action(&self, key: K) -> Option<Action> {
+        if key == self.left {
+            Some(Action::Left)
+        } else if key == self.right {
+            Some(Action::Right)
+        } else if key == self.soft_drop {
+            Some(Action::SoftDrop)
+        } else if key == self.rotate {
+            Some(Action::Rotate)
+        } else if key == self.hard_drop {
+            Some(Action::HardDrop)
+        } else if key == self.hold {
+            Some(Action::Hold)
+        } else {
+            None
+        }
+    }
+}
+
+pub struct Player {
+    pub tetris: Tetris,
+    /// Whether the last move of the current piece was a rotation.
+    rotated: bool,
+}
+
+impl Player {
+    pub fn new(tetris: Tetris) -> Player {
+        Player {
+            tetris,
+            rotated: false,
+        }
+    }
+
+    /// Applies `action`, or the gravity without one. Returns the number of
+    /// cleared lines and what happened, if it locked the piece.
+    fn play(&mut self, action: Option<Action>) -> Option<(u32, LockEvent)> {
+        let game_map = self.tetris.game_map.clone();
+        let piece = self.tetris.current_piece.clone();
+        let cleared = match action {
+            Some(action) => self.tetris.apply_action(action),
+            None => self.tetris.gravity_tick(),
+        };
+        let position = |piece: &Tetrimino| (piece.x, piece.y, piece.current_state);
+        match (cleared, piece) {
+            (Some(cleared), Some(piece)) => {
+                let hard_drop = action == Some(Action::HardDrop);
+                let event = LockEvent::new(&game_map, &piece, hard_drop, self.rotated);
+                self.rotated = false;
+                Some((cleared, event))
+            }
+            (_, piece) => {
+                let now = self.tetris.current_piece.as_ref().map(position);
+                if now != piece.as_ref().map(position) {
+                    self.rotated = action == Some(Action::Rotate);
+                }
+                None
+            }
+        }
+    }
+}
+
+/// What happened during a tick.
+#[derive(Default)]
+pub struct TickOutcome {
+    /// `(player, cleared lines)` for every locked piece.
+    pub locks: Vec<(usize, u32)>,
+    /// `(player, action)` for the actions that moved, rotated or held a
+    /// piece without locking it.
+    pub moves: Vec<(usize, Action)>,
+    /// Whether a player went up a level.
+    pub level_up: bool,
+    pub loser: Option<usize>,
+}
+
+pub struct Session {
+    pub players: Vec<Player>,
+    pub versus: Option<Versus>,
+    pub effects: Vec<Effects>,
+    pub tick: u64,
+}
+
+impl Session {
+    pub fn new(
+        players: Vec<Player>,
+        versus: Option<Versus>,
+        effect_settings: EffectSettings,
+    ) -> Session {
+        Session {
+            effects: players
+                .iter()
+                .map(|_| Effects::new(effect_settings.clone()))
+                .collect(),
+            players,
+            versus,
+            tick: 0,
+        }
+    }
+
+    /// The session `replay` was recorded from, at its first tick.
+    pub fn from_replay(replay: &Replay, effect_settings: EffectSettings) -> Session {
+        let players = (0..replay.nb_players)
+            .map(|nb| Player::new(replay.game(nb)))
+            .collect::<Vec<_>>();
+        let versus = replay.attack_table.as_ref().map(|lines| {
+            let attack_table = AttackTable::from_lines(lines.clone()).unwrap_or_default();
+            Versus::new(players.len(), attack_table)
+        });
+        Session::new(players, versus, effect_settings)
+    }
+
+    pub fn games(&self) -> Vec<&Tetris> {
+        self.players.iter().map(|player| &player.tetris).collect()
+    }
+
+    /// Runs one tick: the gravity first, then the `(player, action)` inputs.
+    pub fn step(&mut self, actions: &[(usize, Action)]) -> TickOutcome {
+        let mut outcome = TickOutcome::default();
+        let levels = self
+            .players
+            .iter()
+            .map(|player| player.tetris.current_level)
+            .collect::<Vec<_>>();
+
+        let mut locks = Vec::new();
+        for (nb, player) in self.players.iter_mut().enumerate() {
+            if let Some((cleared, event)) = player.play(None) {
+                locks.push((nb, cleared, event));
+            }
+        }
+        for &(nb, action) in actions {
+            let tetris = &self.players[nb].tetris;
+            let before = (
+                tetris
+                    .current_piece
+                    .as_ref()
+                    .map(|piece| (piece.x, piece.current_state)),
+                tetris.hold_piece,
+            );
+            match self.players[nb].play(Some(action)) {
+                Some((cleared, event)) => locks.push((nb, cleared, event)),
+                None => {
+                    let tetris = &self.players[nb].tetris;
+                    let now = tetris
+                        .current_piece
+                        .as_ref()
+                        .map(|piece| (piece.x, piece.current_state));
+                    let moved = match action {
+                        Action::Left | Action::Right | Action::Rotate => now != before.0,
+                        Action::Hold => tetris.hold_piece != before.1,
+                        Action::SoftDrop | Action::HardDrop => false,
+                    };
+                    if moved {
+                        outcome.moves.push((nb, action));
+                    }
+                }
+            }
+        }
+        outcome.level_up = self
+            .players
+            .iter()
+            .zip(&levels)
+            .any(|(player, level)| player.tetris.current_level > *level);
+
+        for (nb, cleared, event) in locks {
+            if let Some(ref mut versus) = self.versus {
+                let lock = versus.on_lock(&mut self.players[nb].tetris, nb, cleared);
+                self.effects[nb].on_garbage(lock.received);
+                if !lock.alive {
+                    outcome.loser = Some(nb);
+                }
+            }
+            self.effects[nb].on_lock(&event);
+            outcome.locks.push((nb, cleared));
+        }
+
+        for (nb, player) in self.players.iter_mut().enumerate() {
+            if !player.tetris.spawn() {
+                outcome.loser = Some(nb);
+            }
+            self.effects[nb].tick(&player.tetris);
+        }
+        self.tick += 1;
+        outcome
+    }
+}
+
+pub enum Brain {
+    Builtin(Bot),
+    External(ExternalBot),
+}
+
+impl Brain {
+    /// Actions placing the current piece.
+    fn plan(&mut self, tetris: &Tetris) -> Result<Vec<Action>, TbpError> {
+        match *self {
+            Brain::Builtin(ref bot) => Ok(bot
+                .best_placement(tetris)
+                .map(|placement| placement.actions)
+                .unwrap_or_default()),
+            Brain::External(ref mut bot) => bot.next_move(tetris),
+        }
+    }
+}
+
+/// Paces the bot's actions so that its moves can be followed on screen.
+pub struct BotPlayer {
+    pub brain: Brain,
+    pub player: usize,
+    actions: VecDeque<Action>,
+    /// Where the last action should have left the piece, if it can be
+    /// told. Anywhere else, the gravity moved it and the plan is redone.
+    expected: Option<(isize, usize, u8)>,
+    frames: u32,
+}
+
+impl BotPlayer {
+    pub fn new(brain: Brain, player: usize) -> BotPlayer {
+        BotPlayer {
+            brain,
+            player,
+            actions: VecDeque::new(),
+            expected: None,
+            frames: 0,
+        }
+    }
+
+    fn next_action(&mut self, tetris: &Tetris) -> Result<Option<Action>, TbpError> {
+        self.frames += 1;
+        if self.frames < BOT_FRAMES_PER_ACTION {
+            return Ok(None);
+        }
+        self.frames = 0;
+        let piece = match tetris.current_piece {
+            Some(ref piece) => piece,
+            None => return Ok(None),
+        };
+        let position = (piece.x, piece.y, piece.current_state);
+        if self.expected.is_some_and(|expected| expected != position) {
+            self.actions.clear();
+        }
+        if self.actions.is_empty() {
+            self.actions = self.brain.plan(tetris)?.into();
+        }
+        let action = self.actions.pop_front();
+        // Locks and holds bring another piece, planned for anyway.
+        self.expected = match action {
+            Some(Action::HardDrop) | Some(Action::Hold) | None => None,
+            Some(action) => {
+                let mut moved = piece.clone();
+                let (game_map, x, y) = (&tetris.game_map, piece.x, piece.y);
+                match action {
+                    Action::Left => moved.change_position(game_map, x - 1, y),
+                    Action::Right => moved.change_position(game_map, x + 1, y),
+                    Action::SoftDrop => moved.change_position(game_map, x, y + 1),
+                    _ => {
+                        moved.rotate(game_map);
+                        true
+                    }
+                };
+                Some((moved.x, moved.y, moved.current_state))
+            }
+        };
+        Ok(action)
+    }
+
+    /// Adds the bot's action of this tick to `actions`, in place of the
+    /// keys pressed for its player.
+    pub fn play(
+        &mut self,
+        session: &Session,
+        actions: &mut Vec<(usize, Action)>,
+    ) -> Result<(), TbpError> {
+        let player = self.player;
+        actions.retain(|(nb, _)| *nb != player);
+        let action = self.next_action(&session.players[player].tetris)?;
+        actions.extend(action.map(|action| (player, action)));
+        Ok(())
+    }
+
+    /// Forgets the planned actions once the bot's piece locked.
+    pub fn on_tick(&mut self, outcome: &TickOutcome) {
+        if outcome.locks.iter().any(|(nb, _)| *nb == self.player) {
+            self.actions.clear();
+        }
+    }
+}
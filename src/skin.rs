@@ -0,0 +1,164 @@
+//! Block textures loaded from images instead of the flat colours.
+//!
+//! A skin is a directory of `SKINS_DIRECTORY` holding a `skin.txt`
+//! description, with one `name value...` entry per line:
+//!
+//! ```text
+//! # Tiles are taken from a single image...
+//! atlas blocks.png
+//! tile_size 32
+//! I 0 0
+//! J 1 0
+//! # ...or from one image per piece.
+//! T t.png
+//! ```
+//!
+//! Pieces are named like in the bot protocol, with `G` for garbage. A tile is
+//! given by its column and row in the atlas, or by an image file. Pieces the
+//! skin doesn't mention keep their flat colour.
+
+use sdl2::image::LoadTexture;
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, RenderTarget, Texture, TextureCreator};
+
+use tetris::game::GARBAGE;
+use tetris::tbp::piece_kind;
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+pub const SKINS_DIRECTORY: &str = "assets/skins";
+pub const SKIN_FILE: &str = "skin.txt";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Tile {
+    /// Column and row in the atlas.
+    Atlas(u32, u32),
+    Image(String),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SkinDescription {
+    pub atlas: Option<String>,
+    pub tile_size: Option<u32>,
+    /// Tiles by piece kind, see `PieceSet::piece`.
+    pub tiles: Vec<(u8, Tile)>,
+}
+
+fn kind(name: &str) -> Option<u8> {
+    let mut chars = name.chars();
+    match (chars.next()?, chars.next()) {
+        ('G', None) => Some(GARBAGE),
+        (name, None) => piece_kind(name),
+        _ => None,
+    }
+}
+
+impl SkinDescription {
+    pub fn parse(content: &str) -> Result<SkinDescription, String> {
+        let mut skin = SkinDescription::default();
+        for (nb, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = || format!("line {}: invalid entry {:?}", nb + 1, line);
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            match words[..] {
+                ["atlas", file_name] => skin.atlas = Some(file_name.to_owned()),
+                ["tile_size", size] => {
+                    skin.tile_size = Some(
+                        size.parse()
+                            .ok()
+                            .filter(|size| *size > 0)
+                            .ok_or_else(error)?,
+                    )
+                }
+                [name, file_name] => skin.tiles.push((
+                    kind(name).ok_or_else(error)?,
+                    Tile::Image(file_name.to_owned()),
+                )),
+                [name, column, row] => {
+                    let column = column.parse().map_err(|_| error())?;
+                    let row = row.parse().map_err(|_| error())?;
+                    skin.tiles
+                        .push((kind(name).ok_or_else(error)?, Tile::Atlas(column, row)));
+                }
+                _ => return Err(error()),
+            }
+        }
+        let uses_atlas = skin
+            .tiles
+            .iter()
+            .any(|(_, tile)| matches!(tile, Tile::Atlas(..)));
+        if uses_atlas && (skin.atlas.is_none() || skin.tile_size.is_none()) {
+            return Err("atlas tiles need both `atlas` and `tile_size`".to_owned());
+        }
+        Ok(skin)
+    }
+
+    pub fn load(directory: &Path) -> Result<SkinDescription, String> {
+        let path = directory.join(SKIN_FILE);
+        let mut content = String::new();
+        File::open(&path)
+            .and_then(|mut f| f.read_to_string(&mut content))
+            .map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+        SkinDescription::parse(&content).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+pub fn skin_directory(name: &str) -> PathBuf {
+    Path::new(SKINS_DIRECTORY).join(name)
+}
+
+/// Loads the tiles of the skin in `directory`, each rendered into its own
+/// `size` x `size` texture so that they're drawn like the flat colours.
+pub fn load_tiles<'a, T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    texture_creator: &'a TextureCreator<T::Context>,
+    directory: &Path,
+    size: u32,
+) -> Result<Vec<(u8, Texture<'a>)>, String> {
+    let skin = SkinDescription::load(directory)?;
+    let atlas = match skin.atlas {
+        Some(ref file_name) => Some(texture_creator.load_texture(directory.join(file_name))?),
+        None => None,
+    };
+
+    let mut tiles = Vec::new();
+    for (kind, tile) in skin.tiles {
+        let (image, source) = match tile {
+            Tile::Atlas(column, row) => {
+                let tile_size = skin.tile_size.unwrap_or(size);
+                let source = Rect::new(
+                    (column * tile_size) as i32,
+                    (row * tile_size) as i32,
+                    tile_size,
+                    tile_size,
+                );
+                (None, Some(source))
+            }
+            Tile::Image(file_name) => (
+                Some(texture_creator.load_texture(directory.join(file_name))?),
+                None,
+            ),
+        };
+        let image = match (image.as_ref(), atlas.as_ref()) {
+            (Some(image), _) | (None, Some(image)) => image,
+            (None, None) => continue,
+        };
+        let mut texture = texture_creator
+            .create_texture_target(None, size, size)
+            .map_err(|e| e.to_string())?;
+        let mut copied = Ok(());
+        canvas
+            .with_texture_canvas(&mut texture, |texture| {
+                copied = texture.copy(image, source, None);
+            })
+            .map_err(|e| e.to_string())?;
+        copied?;
+        tiles.push((kind, texture));
+    }
+    Ok(tiles)
+}
@@ -0,0 +1,581 @@
+//! Searches every way to place a queue of pieces, with the held one, that
+//! ends in a perfect clear, or that builds the shape of an opener.
+//!
+//! Openers are read from `assets/openers.txt`, then from `OPENERS_FILE` if
+//! there's one: a `name` line, then the rows of the shape, `#` for the cases
+//! to fill and `.` for the others, the last one on the floor.
+//!
+//! ```text
+//! name tsd-left
+//! #..#######
+//! #...######
+//! ##.#######
+//! ```
+
+use crate::bitboard::{BitBoard, PieceMasks, Position};
+use crate::fumen::Page;
+use crate::game::{MAP_HEIGHT, MAP_WIDTH};
+use crate::pieces::PieceSet;
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Read;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+pub const OPENERS_FILE: &str = "openers.txt";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Opener {
+    pub name: String,
+    /// The cases to fill, row after row like `Tetris::game_map`.
+    pub cases: Vec<Vec<bool>>,
+}
+
+impl Opener {
+    pub fn parse(content: &str) -> Result<Vec<Opener>, String> {
+        let mut openers: Vec<Opener> = Vec::new();
+        for line in content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+        {
+            if let Some(name) = line.strip_prefix("name ") {
+                openers.push(Opener {
+                    name: name.trim().to_owned(),
+                    cases: Vec::new(),
+                });
+                continue;
+            }
+            let opener = openers.last_mut().ok_or("a shape without a name")?;
+            if line.len() != MAP_WIDTH || !line.chars().all(|case| case == '#' || case == '.') {
+                return Err(format!(
+                    "{}: {:?} isn't a row of {} cases",
+                    opener.name, line, MAP_WIDTH
+                ));
+            }
+            opener
+                .cases
+                .push(line.chars().map(|case| case == '#').collect());
+        }
+        for opener in &mut openers {
+            if opener.cases.len() > MAP_HEIGHT {
+                return Err(format!("{} is higher than the map", opener.name));
+            }
+            while opener.cases.len() < MAP_HEIGHT {
+                opener.cases.insert(0, vec![false; MAP_WIDTH]);
+            }
+        }
+        Ok(openers)
+    }
+
+    /// The built-in openers, then those of `OPENERS_FILE`.
+    pub fn load_all() -> Result<Vec<Opener>, String> {
+        let mut openers = Opener::parse(include_str!("../assets/openers.txt"))?;
+        if let Ok(mut f) = File::open(OPENERS_FILE) {
+            let mut content = String::new();
+            f.read_to_string(&mut content)
+                .map_err(|e| format!("{}: {}", OPENERS_FILE, e))?;
+            openers
+                .extend(Opener::parse(&content).map_err(|e| format!("{}: {}", OPENERS_FILE, e))?);
+        }
+        Ok(openers)
+    }
+
+    /// The last opener called `name`, so that `OPENERS_FILE` can replace
+    /// the built-in ones.
+    pub fn named(name: &str) -> Result<Opener, String> {
+        Opener::load_all()?
+            .into_iter()
+            .rev()
+            .find(|opener| opener.name == name)
+            .ok_or_else(|| format!("no opener is called {:?}", name))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Goal {
+    /// Clearing the whole map, with at most this many lines.
+    PerfectClear(usize),
+    /// Filling the cases of an opener without clearing any line.
+    Opener(Opener),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Problem {
+    pub game_map: Vec<Vec<u8>>,
+    /// The current piece, then the next ones.
+    pub queue: Vec<u8>,
+    pub hold: Option<u8>,
+    pub goal: Goal,
+}
+
+/// A piece locked by a solution.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Step {
+    pub kind: u8,
+    pub position: Position,
+    /// Whether the piece is played by holding the current one.
+    pub hold: bool,
+}
+
+/// The board the next piece is played on, with the queue and the hold left.
+#[derive(Clone)]
+struct Node {
+    board: BitBoard,
+    index: usize,
+    hold: Option<u8>,
+    /// Lines left to clear for a perfect clear.
+    lines: usize,
+}
+
+/// Empties the group of `empty` connected to `(x, y)` and returns its size.
+fn take_group(empty: &mut [u64], (x, y): (u32, usize)) -> u32 {
+    let mut size = 0;
+    let mut stack = vec![(x, y)];
+    empty[y] &= !(1 << x);
+    while let Some((x, y)) = stack.pop() {
+        size += 1;
+        let neighbours = [
+            (x + 1, y),
+            (x.wrapping_sub(1), y),
+            (x, y + 1),
+            (x, y.wrapping_sub(1)),
+        ];
+        for (x, y) in neighbours {
+            if x < 64 && y < empty.len() && empty[y] & 1 << x != 0 {
+                empty[y] &= !(1 << x);
+                stack.push((x, y));
+            }
+        }
+    }
+    size
+}
+
+/// Whether each group of columns of `empty` has a multiple of `size` cases.
+/// The cases of a column all end up next to each other once the lines
+/// between them are cleared, and two columns are together when a row has
+/// both empty.
+fn columns_fit(empty: &[u64], size: u32) -> bool {
+    let (links, columns) = empty.iter().fold((0, 0), |(links, columns), row| {
+        (links | row & row >> 1, columns | row)
+    });
+    let mut group = 0;
+    for x in 0..64 - columns.leading_zeros() {
+        group += empty.iter().filter(|row| *row >> x & 1 != 0).count() as u32;
+        if links >> x & 1 == 0 {
+            if !group.is_multiple_of(size) {
+                return false;
+            }
+            group = 0;
+        }
+    }
+    true
+}
+
+/// What a thread searches with.
+struct Search<'a> {
+    problem: &'a Problem,
+    /// The rows of the map above the board searched, which stay empty.
+    top: usize,
+    masks: Vec<PieceMasks>,
+    spawns: Vec<Position>,
+    /// Blocks of each kind.
+    sizes: Vec<u32>,
+    /// The number of blocks of every piece, when it's the same.
+    size: Option<u32>,
+    /// The rows of the cases to fill, for each number of lines left to
+    /// clear.
+    targets: Vec<Vec<u64>>,
+    /// The nodes already searched without finding any solution.
+    dead_ends: HashSet<(Vec<u64>, usize, Option<u8>, usize)>,
+}
+
+impl<'a> Search<'a> {
+    fn new(problem: &'a Problem, pieces: &PieceSet) -> Search<'a> {
+        let pieces = (1..=pieces.len() as u8)
+            .map(|kind| pieces.piece(kind))
+            .collect::<Vec<_>>();
+        let sizes = pieces
+            .iter()
+            .map(|piece| {
+                piece.states[0]
+                    .iter()
+                    .flatten()
+                    .filter(|case| **case != 0)
+                    .count() as u32
+            })
+            .collect::<Vec<_>>();
+        let full = u64::MAX >> (64 - MAP_WIDTH);
+        let targets = match problem.goal {
+            Goal::PerfectClear(lines) => (0..=lines)
+                .map(|lines| {
+                    (0..MAP_HEIGHT)
+                        .map(|y| if y + lines >= MAP_HEIGHT { full } else { 0 })
+                        .collect()
+                })
+                .collect(),
+            Goal::Opener(ref opener) => vec![opener
+                .cases
+                .iter()
+                .map(|line| {
+                    line.iter()
+                        .enumerate()
+                        .filter(|(_, case)| **case)
+                        .fold(0, |row, (x, _)| row | 1 << x)
+                })
+                .collect()],
+        };
+        // Only the rows of the goal, and enough above them to move the
+        // pieces, are searched: the others are empty and all alike.
+        let goal_height = MAP_HEIGHT
+            - targets
+                .iter()
+                .filter_map(|rows: &Vec<u64>| rows.iter().position(|row| *row != 0))
+                .min()
+                .unwrap_or(MAP_HEIGHT);
+        let margin = pieces
+            .iter()
+            .flat_map(|piece| piece.states.iter().map(|state| state.len()))
+            .max()
+            .unwrap_or(0);
+        let top = MAP_HEIGHT.saturating_sub(goal_height + margin);
+        Search {
+            problem,
+            top,
+            masks: pieces.iter().map(PieceMasks::new).collect(),
+            spawns: pieces
+                .iter()
+                .map(|piece| {
+                    let y = if top == 0 { piece.y } else { 0 };
+                    (piece.current_state, piece.x, y)
+                })
+                .collect(),
+            size: Some(sizes[0]).filter(|size| sizes.iter().all(|other| other == size)),
+            sizes,
+            targets: targets
+                .into_iter()
+                .map(|rows| rows[top..].to_vec())
+                .collect(),
+            dead_ends: HashSet::new(),
+        }
+    }
+
+    /// Whether the cases of `target` left empty can still be filled with
+    /// `blocks` blocks, and nothing else is filled. Groups of empty cases
+    /// have to be filled with whole pieces.
+    fn feasible(&self, rows: &[u64], target: &[u64], blocks: u32) -> bool {
+        let mut empty = Vec::with_capacity(rows.len());
+        for (row, target) in rows.iter().zip(target) {
+            if row & !target != 0 {
+                return false;
+            }
+            empty.push(target & !row);
+        }
+        if empty.iter().map(|row| row.count_ones()).sum::<u32>() > blocks {
+            return false;
+        }
+        let size = match self.size {
+            Some(size) => size,
+            None => return true,
+        };
+        if let Goal::PerfectClear(_) = self.problem.goal {
+            return columns_fit(&empty, size);
+        }
+        while let Some(y) = empty.iter().position(|row| *row != 0) {
+            let x = empty[y].trailing_zeros();
+            if !take_group(&mut empty, (x, y)).is_multiple_of(size) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// The nodes after playing each piece `node` can play, at every place
+    /// it can lock.
+    fn children(&self, node: &Node) -> Vec<(Step, Node)> {
+        let queue = &self.problem.queue;
+        let current = match queue.get(node.index) {
+            Some(current) => *current,
+            None => return Vec::new(),
+        };
+        // The piece played, whether by holding, and the queue and hold left.
+        let mut moves = vec![(current, false, node.index + 1, node.hold)];
+        match node.hold {
+            Some(held) if held != current => {
+                moves.push((held, true, node.index + 1, Some(current)));
+            }
+            Some(_) => {}
+            None => {
+                if let Some(next) = queue.get(node.index + 1) {
+                    moves.push((*next, true, node.index + 2, Some(current)));
+                }
+            }
+        }
+
+        let mut children = Vec::new();
+        for (kind, hold, index, next_hold) in moves {
+            let masks = &self.masks[kind as usize - 1];
+            let spawn = self.spawns[kind as usize - 1];
+            if !node.board.fits(masks, spawn) {
+                continue;
+            }
+            for position in node.board.lock_positions(masks, spawn) {
+                // Perfect clears don't go above their lines.
+                if let Goal::PerfectClear(_) = self.problem.goal {
+                    if masks.top(position) + node.lines < node.board.height() {
+                        continue;
+                    }
+                }
+                let mut board = node.board.clone();
+                let cleared = board.lock(masks, position) as usize;
+                let lines = match self.problem.goal {
+                    Goal::PerfectClear(_) if cleared <= node.lines => node.lines - cleared,
+                    Goal::Opener(_) if cleared == 0 => 0,
+                    _ => continue,
+                };
+                children.push((
+                    Step {
+                        kind,
+                        position,
+                        hold,
+                    },
+                    Node {
+                        board,
+                        index,
+                        hold: next_hold,
+                        lines,
+                    },
+                ));
+            }
+        }
+        children
+    }
+
+    /// Adds the solutions from `node` to `solutions`, each after `steps`.
+    /// Returns whether there was any.
+    fn search(
+        &mut self,
+        node: &Node,
+        steps: &mut Vec<Step>,
+        solutions: &mut Vec<Vec<Step>>,
+    ) -> bool {
+        let target = &self.targets[node.lines];
+        if node.board.rows() == &target[..] {
+            solutions.push(steps.clone());
+            return true;
+        }
+        let blocks = self.problem.queue[node.index.min(self.problem.queue.len())..]
+            .iter()
+            .chain(node.hold.iter())
+            .map(|kind| self.sizes[*kind as usize - 1])
+            .sum();
+        if !self.feasible(node.board.rows(), target, blocks) {
+            return false;
+        }
+        let key = (
+            node.board.rows().to_vec(),
+            node.index,
+            node.hold,
+            node.lines,
+        );
+        if self.dead_ends.contains(&key) {
+            return false;
+        }
+
+        let mut found = false;
+        for (step, child) in self.children(node) {
+            steps.push(step);
+            found |= self.search(&child, steps, solutions);
+            steps.pop();
+        }
+        if !found {
+            self.dead_ends.insert(key);
+        }
+        found
+    }
+}
+
+/// Every solution of `problem`, searched on `threads` threads. They come in
+/// the same order whatever the number of threads.
+pub fn solve(problem: &Problem, pieces: &PieceSet, threads: usize) -> Vec<Vec<Step>> {
+    let search = Search::new(problem, pieces);
+    let (above, map) = problem.game_map.split_at(search.top);
+    if above.iter().flatten().any(|case| *case != 0) {
+        return Vec::new();
+    }
+    let board = BitBoard::from_map(map);
+    let lines = match problem.goal {
+        Goal::PerfectClear(lines) => (1..=lines.min(MAP_HEIGHT)).collect(),
+        Goal::Opener(_) => vec![0],
+    };
+    // Each first piece is searched apart, for the threads to share them.
+    let branches = lines
+        .into_iter()
+        .flat_map(|lines| {
+            let node = Node {
+                board: board.clone(),
+                index: 0,
+                hold: problem.hold,
+                lines,
+            };
+            search.children(&node)
+        })
+        .collect::<Vec<_>>();
+
+    let next_branch = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1).min(branches.len()) {
+            scope.spawn(|| {
+                let mut search = Search::new(problem, pieces);
+                loop {
+                    let branch = next_branch.fetch_add(1, Ordering::SeqCst);
+                    let (step, node) = match branches.get(branch) {
+                        Some(branch) => branch,
+                        None => break,
+                    };
+                    let mut solutions = Vec::new();
+                    search.search(node, &mut vec![*step], &mut solutions);
+                    results
+                        .lock()
+                        .expect("A solver thread panicked")
+                        .push((branch, solutions));
+                }
+            });
+        }
+    });
+
+    let mut results = results.into_inner().expect("A solver thread panicked");
+    results.sort_by_key(|(branch, _)| *branch);
+    results
+        .into_iter()
+        .flat_map(|(_, solutions)| solutions)
+        .map(|mut solution| {
+            for step in &mut solution {
+                step.position.2 += search.top;
+            }
+            solution
+        })
+        .collect()
+}
+
+/// A page per step of `solution`, from the board of `problem`, then the
+/// board reached.
+pub fn pages(problem: &Problem, solution: &[Step], pieces: &PieceSet) -> Vec<Page> {
+    let mut pages = Vec::new();
+    let mut game_map = problem.game_map.clone();
+    for step in solution {
+        let (state, x, y) = step.position;
+        let page = Page {
+            game_map,
+            piece: Some((step.kind, x, y, state)),
+            comment: String::new(),
+        };
+        game_map = page.locked(pieces);
+        pages.push(page);
+    }
+    pages.push(Page {
+        game_map,
+        piece: None,
+        comment: String::new(),
+    });
+    pages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(pieces: &PieceSet, names: &str) -> Vec<u8> {
+        names
+            .chars()
+            .map(|name| pieces.kind(&name.to_string()).unwrap())
+            .collect()
+    }
+
+    /// The map with `rows` on the floor, `#` for grey cases.
+    fn map(rows: &[&str]) -> Vec<Vec<u8>> {
+        let mut game_map = vec![vec![0; MAP_WIDTH]; MAP_HEIGHT - rows.len()];
+        game_map.extend(rows.iter().map(|row| {
+            row.chars()
+                .map(|case| if case == '#' { 8 } else { 0 })
+                .collect::<Vec<_>>()
+        }));
+        game_map
+    }
+
+    #[test]
+    fn openers_are_padded_to_the_map() {
+        let openers = Opener::parse("name gap\n#########.\n\nname top\n..........").unwrap();
+        assert_eq!(openers.len(), 2);
+        assert_eq!(openers[0].name, "gap");
+        assert_eq!(openers[0].cases.len(), MAP_HEIGHT);
+        assert!(openers[0].cases[MAP_HEIGHT - 1][0]);
+        assert!(!openers[0].cases[MAP_HEIGHT - 1][9]);
+        assert!(openers[0].cases[..MAP_HEIGHT - 1]
+            .iter()
+            .flatten()
+            .all(|case| !case));
+
+        assert!(Opener::parse("#########.").is_err());
+        assert!(Opener::parse("name short\n####").is_err());
+        assert!(Opener::named("tsd-left").is_ok());
+    }
+
+    #[test]
+    fn perfect_clears_empty_the_map() {
+        let pieces = PieceSet::builtin();
+        let problem = Problem {
+            game_map: map(&["######....", "######...."]),
+            queue: kinds(&pieces, "OO"),
+            hold: None,
+            goal: Goal::PerfectClear(2),
+        };
+        let solutions = solve(&problem, &pieces, 1);
+        assert!(!solutions.is_empty());
+        for solution in &solutions {
+            let pages = pages(&problem, solution, &pieces);
+            assert_eq!(pages.len(), 3);
+            assert!(pages[2].game_map.iter().flatten().all(|case| *case == 0));
+        }
+
+        // Only the I fills the row, by holding the O.
+        let problem = Problem {
+            game_map: map(&["######...."]),
+            queue: kinds(&pieces, "OI"),
+            goal: Goal::PerfectClear(1),
+            ..problem
+        };
+        let solutions = solve(&problem, &pieces, 1);
+        assert_eq!(solutions.len(), 1);
+        assert_eq!(solutions[0].len(), 1);
+        assert!(solutions[0][0].hold);
+        assert_eq!(solutions[0][0].kind, pieces.kind("I").unwrap());
+        assert_eq!(solutions[0][0].position.2 + 1, MAP_HEIGHT);
+    }
+
+    #[test]
+    fn openers_are_built_the_same_on_any_number_of_threads() {
+        let pieces = PieceSet::builtin();
+        let opener = Opener::named("tsd-left").unwrap();
+        let problem = Problem {
+            game_map: map(&[]),
+            queue: kinds(&pieces, "IOLJSZT"),
+            hold: None,
+            goal: Goal::Opener(opener.clone()),
+        };
+        let solutions = solve(&problem, &pieces, 1);
+        assert!(!solutions.is_empty());
+        assert_eq!(solve(&problem, &pieces, 3), solutions);
+        for solution in &solutions {
+            let built = pages(&problem, solution, &pieces).pop().unwrap().game_map;
+            let cases = built
+                .iter()
+                .map(|line| line.iter().map(|case| *case != 0).collect::<Vec<_>>())
+                .collect::<Vec<_>>();
+            assert_eq!(cases, opener.cases);
+        }
+    }
+}
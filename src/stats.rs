@@ -0,0 +1,401 @@
+//! What happened during a game, beyond its score: pieces, inputs, finesse,
+//! speed, combos and the kinds of line clears.
+
+use crate::game::{Action, Tetrimino, Tetris, TICKS_PER_SECOND};
+use crate::pieces::PieceSet;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::{HashSet, VecDeque};
+use std::fs::OpenOptions;
+use std::io::Write;
+
+/// Every finished game, one JSON record per line.
+pub const RECORD_FILE: &str = "games.jsonl";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Statistics {
+    /// Pieces spawned, by kind minus one.
+    pub spawned: Vec<u32>,
+    pub locked: u32,
+    /// Every action applied, holds and soft drops included.
+    pub inputs: u32,
+    /// Pieces placed with more moves and rotations than needed. Only the
+    /// pieces that could have been placed by moving them at the top of the
+    /// map, then hard dropping them, are judged.
+    pub finesse_faults: u32,
+    /// Moves and rotations spent beyond the optimal ones.
+    pub extra_inputs: u32,
+    pub ticks: u64,
+    pub lines: u32,
+    /// Garbage lines the clears were worth, counted by `Versus`.
+    pub attack: u32,
+    pub max_combo: u32,
+    /// T-spins, with or without lines.
+    pub t_spins: u32,
+    /// Clears by number of lines, from singles to tetrises.
+    pub clears: [u32; 4],
+    /// T-spins by number of lines, from none to triples.
+    pub t_spin_clears: [u32; 4],
+    /// Pieces locked in a row that cleared lines.
+    #[serde(skip)]
+    streak: u32,
+    /// Moves and rotations of the current piece.
+    #[serde(skip)]
+    piece_inputs: u32,
+}
+
+/// The cases a piece covers once dropped as far as it goes.
+fn landing(game_map: &[Vec<u8>], piece: &Tetrimino) -> HashSet<(isize, usize)> {
+    let mut piece = piece.clone();
+    while piece.change_position(game_map, piece.x, piece.y + 1) {}
+    piece.states[piece.current_state as usize]
+        .iter()
+        .enumerate()
+        .flat_map(|(shift_y, line)| {
+            line.iter()
+                .enumerate()
+                .filter(|(_, case)| **case != 0)
+                .map(move |(shift_x, _)| (piece.x + shift_x as isize, piece.y + shift_y))
+        })
+        .collect()
+}
+
+/// The fewest moves and rotations bringing `spawned` above where `locked`
+/// is, so that a hard drop puts it there. `None` if it can't be done, for
+/// instance for a tuck or a spin.
+pub fn optimal_inputs(
+    game_map: &[Vec<u8>],
+    spawned: &Tetrimino,
+    locked: &Tetrimino,
+) -> Option<u32> {
+    let target = landing(game_map, locked);
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::new();
+    seen.insert((spawned.x, spawned.current_state));
+    queue.push_back((spawned.clone(), 0));
+    while let Some((piece, nb_inputs)) = queue.pop_front() {
+        if landing(game_map, &piece) == target {
+            return Some(nb_inputs);
+        }
+        for action in [Action::Left, Action::Right, Action::Rotate] {
+            let mut next = piece.clone();
+            match action {
+                Action::Left => next.change_position(game_map, next.x - 1, next.y),
+                Action::Right => next.change_position(game_map, next.x + 1, next.y),
+                _ => {
+                    next.rotate(game_map);
+                    true
+                }
+            };
+            if seen.insert((next.x, next.current_state)) {
+                queue.push_back((next, nb_inputs + 1));
+            }
+        }
+    }
+    None
+}
+
+impl Statistics {
+    pub fn new(nb_kinds: usize) -> Statistics {
+        Statistics {
+            spawned: vec![0; nb_kinds],
+            ..Statistics::default()
+        }
+    }
+
+    /// The current streak of clearing pieces and the moves of the current
+    /// piece, which the saved statistics leave out but a game in progress
+    /// needs.
+    pub fn progress(&self) -> (u32, u32) {
+        (self.streak, self.piece_inputs)
+    }
+
+    pub fn with_progress(self, (streak, piece_inputs): (u32, u32)) -> Statistics {
+        Statistics {
+            streak,
+            piece_inputs,
+            ..self
+        }
+    }
+
+    pub fn on_spawn(&mut self, kind: u8) {
+        self.spawned[kind as usize - 1] += 1;
+        self.piece_inputs = 0;
+    }
+
+    pub fn on_action(&mut self, action: Action) {
+        self.inputs += 1;
+        match action {
+            Action::Left | Action::Right | Action::Rotate => self.piece_inputs += 1,
+            // The piece coming out of the hold starts again from the top.
+            Action::Hold => self.piece_inputs = 0,
+            Action::SoftDrop | Action::HardDrop => {}
+        }
+    }
+
+    /// `optimal_inputs` is what the piece needed, if it can be judged.
+    pub fn on_lock(&mut self, cleared: u32, t_spin: bool, optimal_inputs: Option<u32>) {
+        self.locked += 1;
+        self.lines += cleared;
+        if let Some(optimal) = optimal_inputs {
+            if self.piece_inputs > optimal {
+                self.finesse_faults += 1;
+                self.extra_inputs += self.piece_inputs - optimal;
+            }
+        }
+        self.piece_inputs = 0;
+        if t_spin {
+            self.t_spins += 1;
+            self.t_spin_clears[(cleared as usize).min(3)] += 1;
+        } else if cleared > 0 {
+            self.clears[(cleared as usize).min(4) - 1] += 1;
+        }
+        if cleared > 0 {
+            self.streak += 1;
+            // The first clear of a streak isn't a combo yet.
+            self.max_combo = self.max_combo.max(self.streak - 1);
+        } else {
+            self.streak = 0;
+        }
+    }
+
+    fn minutes(&self) -> f64 {
+        self.ticks as f64 / TICKS_PER_SECOND as f64 / 60.
+    }
+
+    /// Per minute, 0 if no time went by, as when the bot plays headless.
+    fn per_minute(&self, count: u32) -> f64 {
+        if self.ticks == 0 {
+            0.
+        } else {
+            count as f64 / self.minutes()
+        }
+    }
+
+    pub fn pieces_per_second(&self) -> f64 {
+        self.per_minute(self.locked) / 60.
+    }
+
+    pub fn lines_per_minute(&self) -> f64 {
+        self.per_minute(self.lines)
+    }
+
+    pub fn attack_per_minute(&self) -> f64 {
+        self.per_minute(self.attack)
+    }
+
+    pub fn inputs_per_piece(&self) -> f64 {
+        if self.locked == 0 {
+            0.
+        } else {
+            self.inputs as f64 / self.locked as f64
+        }
+    }
+
+    /// A few lines describing the game, for the game over screen.
+    pub fn summary(&self, pieces: &PieceSet) -> Vec<String> {
+        let spawned = pieces
+            .pieces
+            .iter()
+            .map(|piece| &piece.name)
+            .zip(&self.spawned)
+            .map(|(name, nb)| format!("{} {}", name, nb))
+            .collect::<Vec<_>>();
+        vec![
+            format!("Pieces:          {} ({})", self.locked, spawned.join(", ")),
+            format!(
+                "Speed:           {:.2} PPS, {:.1} LPM, {:.1} APM",
+                self.pieces_per_second(),
+                self.lines_per_minute(),
+                self.attack_per_minute()
+            ),
+            format!(
+                "Inputs:          {:.2} per piece, {} finesse faults ({} extra inputs)",
+                self.inputs_per_piece(),
+                self.finesse_faults,
+                self.extra_inputs
+            ),
+            format!(
+                "Clears:          {} singles, {} doubles, {} triples, {} tetrises",
+                self.clears[0], self.clears[1], self.clears[2], self.clears[3]
+            ),
+            format!(
+                "T-spins:         {} ({} without lines, {} singles, {} doubles, {} triples)",
+                self.t_spins,
+                self.t_spin_clears[0],
+                self.t_spin_clears[1],
+                self.t_spin_clears[2],
+                self.t_spin_clears[3]
+            ),
+            format!("Max combo:       {}", self.max_combo),
+        ]
+    }
+}
+
+/// The score and statistics of `tetris` in lines short enough for the game
+/// over screens, next to the board. The clears are drawn as a histogram.
+pub fn game_over_lines(tetris: &Tetris) -> Vec<String> {
+    let statistics = &tetris.statistics;
+    let mut lines = vec![
+        format!("Score {}", tetris.score),
+        format!("Lines {}  Level {}", tetris.nb_lines, tetris.current_level),
+        String::new(),
+        format!("Pieces {}", statistics.locked),
+    ];
+    let spawned = tetris
+        .pieces
+        .pieces
+        .iter()
+        .zip(&statistics.spawned)
+        .map(|(piece, nb)| format!("{} {}", piece.name, nb))
+        .collect::<Vec<_>>();
+    lines.extend(
+        spawned
+            .chunks(4)
+            .map(|names| format!("  {}", names.join("  "))),
+    );
+    lines.extend([
+        format!(
+            "{:.2} PPS  {:.1} APM",
+            statistics.pieces_per_second(),
+            statistics.attack_per_minute()
+        ),
+        format!(
+            "Finesse faults {} (+{})",
+            statistics.finesse_faults, statistics.extra_inputs
+        ),
+        String::new(),
+    ]);
+    let most = statistics.clears.iter().max().copied().unwrap_or(0).max(1);
+    for (name, nb) in ["Singles", "Doubles", "Triples", "Tetrises"]
+        .iter()
+        .zip(statistics.clears)
+    {
+        // At most ten marks, and one for any clear at all.
+        let bar = "#".repeat((nb * 10).div_ceil(most) as usize);
+        lines.push(format!("{:<9}{:>4} {}", name, nb, bar));
+    }
+    let t_spins = statistics.t_spin_clears;
+    lines.extend([
+        format!("T-spins  {:>4}", statistics.t_spins),
+        format!("  {} none, {} single", t_spins[0], t_spins[1]),
+        format!("  {} double, {} triple", t_spins[2], t_spins[3]),
+        format!("Max combo {}", statistics.max_combo),
+    ]);
+    lines
+}
+
+/// A finished game, as saved in `RECORD_FILE`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GameRecord {
+    pub score: u32,
+    pub lines: u32,
+    pub level: u32,
+    pub statistics: Statistics,
+}
+
+impl GameRecord {
+    pub fn new(tetris: &Tetris) -> GameRecord {
+        GameRecord {
+            score: tetris.score,
+            lines: tetris.nb_lines,
+            level: tetris.current_level,
+            statistics: tetris.statistics.clone(),
+        }
+    }
+
+    /// Appends the record to `file_name`.
+    pub fn save(&self, file_name: &str) -> Result<(), String> {
+        let line = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file_name)
+            .map_err(|e| format!("Couldn't open {}: {}", file_name, e))?;
+        writeln!(file, "{}", line).map_err(|e| format!("Couldn't write {}: {}", file_name, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{GARBAGE, MAP_HEIGHT, MAP_WIDTH};
+
+    #[test]
+    fn combos_count_the_clears_in_a_row() {
+        let mut statistics = Statistics::new(7);
+        for cleared in [1, 2, 1, 0, 1, 1] {
+            statistics.on_lock(cleared, false, None);
+        }
+        assert_eq!(statistics.max_combo, 2);
+        assert_eq!(statistics.progress().0, 2);
+        assert_eq!(statistics.lines, 6);
+        assert_eq!(statistics.locked, 6);
+    }
+
+    #[test]
+    fn t_spins_are_counted_apart_from_clears() {
+        let mut statistics = Statistics::new(7);
+        statistics.on_lock(2, true, None);
+        statistics.on_lock(0, true, None);
+        statistics.on_lock(4, false, None);
+        statistics.on_lock(1, false, None);
+        statistics.on_lock(0, false, None);
+        assert_eq!(statistics.t_spins, 2);
+        assert_eq!(statistics.t_spin_clears, [1, 0, 1, 0]);
+        assert_eq!(statistics.clears, [1, 0, 0, 1]);
+    }
+
+    #[test]
+    fn game_over_lines_draw_the_clears_as_a_histogram() {
+        let mut tetris = Tetris::with_seed(1);
+        for cleared in [1, 1, 1, 1, 2, 2, 4] {
+            tetris.statistics.on_lock(cleared, false, None);
+        }
+        let lines = game_over_lines(&tetris);
+        assert!(lines.contains(&"Singles     4 ##########".to_owned()));
+        assert!(lines.contains(&"Doubles     2 #####".to_owned()));
+        assert!(lines.contains(&"Triples     0 ".to_owned()));
+        assert!(lines.contains(&"Tetrises    1 ###".to_owned()));
+        assert_eq!(lines[3], "Pieces 7");
+        assert!(lines[4].starts_with("  I "));
+    }
+
+    #[test]
+    fn moves_back_and_forth_are_finesse_faults() {
+        let mut tetris = Tetris::with_seed(1);
+        let o = tetris.pieces.kind("O").unwrap();
+        assert!(tetris.replace_current_piece(o));
+        tetris.apply_action(Action::Left);
+        tetris.apply_action(Action::HardDrop);
+        assert_eq!(tetris.statistics.finesse_faults, 0);
+
+        assert!(tetris.replace_current_piece(o));
+        for action in [Action::Left, Action::Right, Action::Left, Action::HardDrop] {
+            tetris.apply_action(action);
+        }
+        assert_eq!(tetris.statistics.finesse_faults, 1);
+        assert_eq!(tetris.statistics.extra_inputs, 2);
+    }
+
+    #[test]
+    fn tucks_arent_judged() {
+        let pieces = PieceSet::builtin();
+        let spawned = pieces.piece(pieces.kind("I").unwrap());
+        let row = spawned.states[0]
+            .iter()
+            .position(|line| line.iter().any(|case| *case != 0))
+            .unwrap();
+        let mut locked = spawned.clone();
+        locked.x = 0;
+        locked.y = MAP_HEIGHT - 1 - row;
+        let mut game_map = vec![vec![0; MAP_WIDTH]; MAP_HEIGHT];
+        assert_eq!(optimal_inputs(&game_map, &spawned, &locked), Some(4));
+
+        // A roof over the bottom left corner.
+        game_map[MAP_HEIGHT - 2][..5].fill(GARBAGE);
+        assert!(locked.test_current_position(&game_map));
+        assert_eq!(optimal_inputs(&game_map, &spawned, &locked), None);
+    }
+}
@@ -0,0 +1,581 @@
+//! Lets an external program play, by exchanging one JSON message per line
+//! over its standard input and output.
+//!
+//! The message flow follows the Tetris Bot Protocol: the bot introduces
+//! itself with `info`, answers `rules` with `ready`, and receives the game
+//! with `start`, its queue being the current piece then the preview. Then
+//! for every piece it gets a `new_piece` for each piece revealed at the end
+//! of the preview and a `suggest`, answers with a `suggestion`, and is told
+//! what was actually played with `play`.
+//!
+//! Coordinates are the game's own rather than TBP's: `board` lists the rows
+//! from top to bottom, and a location is the top-left corner of the piece's
+//! 4x4 box with the index of its rotation state, just like `Tetrimino`.
+//! Only the built-in pieces can be played, since the protocol only knows the
+//! tetrominoes.
+
+use crate::bot::{self, Placement};
+use crate::game::{Action, Tetris, GARBAGE, MAP_HEIGHT, MAP_WIDTH};
+use crate::pieces::PieceSet;
+
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+const PIECE_NAMES: [char; 7] = ['I', 'J', 'L', 'O', 'S', 'Z', 'T'];
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Name of a piece kind of the built-in set, see `PieceSet::piece`. Other
+/// blocks are garbage for the bot.
+pub fn piece_name(kind: u8) -> char {
+    match kind {
+        GARBAGE => 'G',
+        kind => *PIECE_NAMES.get(kind as usize - 1).unwrap_or(&'G'),
+    }
+}
+
+pub fn piece_kind(name: char) -> Option<u8> {
+    PIECE_NAMES
+        .iter()
+        .position(|piece| *piece == name)
+        .map(|kind| kind as u8 + 1)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PieceLocation {
+    #[serde(rename = "type")]
+    pub piece: char,
+    pub orientation: u8,
+    pub x: isize,
+    pub y: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Move {
+    pub location: PieceLocation,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FrontendMessage {
+    Rules {
+        width: usize,
+        height: usize,
+    },
+    Start {
+        hold: Option<char>,
+        queue: Vec<char>,
+        board: Vec<Vec<Option<char>>>,
+    },
+    NewPiece {
+        piece: char,
+    },
+    Suggest,
+    Play {
+        #[serde(rename = "move")]
+        mv: Move,
+    },
+    Stop,
+    Quit,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BotMessage {
+    Info {
+        name: String,
+        version: String,
+        author: String,
+        #[serde(default)]
+        features: Vec<String>,
+    },
+    Ready,
+    Error {
+        reason: String,
+    },
+    Suggestion {
+        moves: Vec<Move>,
+    },
+}
+
+#[derive(Debug)]
+pub enum TbpError {
+    Io(io::Error),
+    Timeout,
+    /// The bot exited or closed its output.
+    Disconnected,
+    BadMessage(String),
+    /// The bot reported an error.
+    Bot(String),
+    /// None of the suggested moves can be played.
+    NoValidMove,
+    /// The game isn't played with the built-in pieces.
+    Pieces,
+}
+
+impl fmt::Display for TbpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TbpError::Io(ref e) => write!(f, "couldn't talk to the bot: {}", e),
+            TbpError::Timeout => write!(f, "the bot took too long to answer"),
+            TbpError::Disconnected => write!(f, "the bot exited"),
+            TbpError::BadMessage(ref line) => write!(f, "unexpected message: {}", line),
+            TbpError::Bot(ref reason) => write!(f, "the bot failed: {}", reason),
+            TbpError::NoValidMove => write!(f, "the bot only suggested invalid moves"),
+            TbpError::Pieces => write!(f, "the protocol only knows the built-in pieces"),
+        }
+    }
+}
+
+impl From<io::Error> for TbpError {
+    fn from(e: io::Error) -> TbpError {
+        TbpError::Io(e)
+    }
+}
+
+/// Where a placement puts its piece, as a TBP location.
+pub fn placement_location(tetris: &Tetris, placement: &Placement) -> Option<PieceLocation> {
+    let kind = if placement.hold {
+        tetris.hold_piece?
+    } else {
+        tetris.current_piece.as_ref()?.kind()
+    };
+    Some(PieceLocation {
+        piece: piece_name(kind),
+        orientation: placement.current_state,
+        x: placement.x,
+        y: placement.y,
+    })
+}
+
+pub fn board(game_map: &[Vec<u8>]) -> Vec<Vec<Option<char>>> {
+    game_map
+        .iter()
+        .map(|line| {
+            line.iter()
+                .map(|case| {
+                    if *case == 0 {
+                        None
+                    } else {
+                        Some(piece_name(*case))
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The game as the bot sees it once its move is played.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Expected {
+    game_map: Vec<Vec<u8>>,
+    /// The pieces left in its queue.
+    queue: Vec<u8>,
+    hold: Option<u8>,
+}
+
+/// The current piece then the preview, as a TBP queue.
+fn queue(tetris: &Tetris) -> Vec<u8> {
+    tetris
+        .current_piece
+        .iter()
+        .map(|piece| piece.kind())
+        .chain(tetris.next_pieces.iter().copied())
+        .collect()
+}
+
+impl Expected {
+    /// What the bot expects once `placement` locks its piece in `game_map`.
+    fn after(tetris: &Tetris, placement: &Placement, game_map: Vec<Vec<u8>>) -> Expected {
+        let queue = queue(tetris);
+        // Holding with nothing held plays the next piece.
+        let played = match (placement.hold, tetris.hold_piece) {
+            (true, None) => 2,
+            _ => 1,
+        };
+        Expected {
+            game_map,
+            queue: queue.get(played..).unwrap_or_default().to_vec(),
+            hold: match placement.hold {
+                true => queue.first().copied(),
+                false => tetris.hold_piece,
+            },
+        }
+    }
+}
+
+/// The messages telling the bot about `tetris`: the pieces revealed since
+/// `expected` if the game is where the bot left it, otherwise the whole game
+/// again.
+fn catch_up(expected: Option<&Expected>, tetris: &Tetris) -> Vec<FrontendMessage> {
+    let queue = queue(tetris);
+    match expected {
+        Some(expected)
+            if expected.game_map == tetris.game_map
+                && expected.hold == tetris.hold_piece
+                && queue.starts_with(&expected.queue) =>
+        {
+            queue[expected.queue.len()..]
+                .iter()
+                .map(|kind| FrontendMessage::NewPiece {
+                    piece: piece_name(*kind),
+                })
+                .collect()
+        }
+        _ => {
+            let mut messages = Vec::new();
+            if expected.is_some() {
+                messages.push(FrontendMessage::Stop);
+            }
+            messages.push(FrontendMessage::Start {
+                hold: tetris.hold_piece.map(piece_name),
+                queue: queue.into_iter().map(piece_name).collect(),
+                board: board(&tetris.game_map),
+            });
+            messages
+        }
+    }
+}
+
+/// A bot running in a child process.
+pub struct ExternalBot {
+    child: Child,
+    stdin: ChildStdin,
+    incoming: Receiver<String>,
+    pub name: String,
+    /// The game the bot expects after its last move. Anything else, such
+    /// as incoming garbage, means it must be started again.
+    expected: Option<Expected>,
+    builtin: PieceSet,
+}
+
+impl ExternalBot {
+    /// Starts `command`, split on whitespace, and waits until it's ready.
+    pub fn launch(command: &str) -> Result<ExternalBot, TbpError> {
+        let mut words = command.split_whitespace();
+        let program = words
+            .next()
+            .ok_or_else(|| TbpError::Bot("empty command".to_owned()))?;
+        let mut child = Command::new(program)
+            .args(words)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("The bot's stdin is piped");
+        let stdout = child.stdout.take().expect("The bot's stdout is piped");
+
+        let (sender, incoming) = channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut bot = ExternalBot {
+            child,
+            stdin,
+            incoming,
+            name: String::new(),
+            expected: None,
+            builtin: PieceSet::builtin(),
+        };
+        match bot.recv()? {
+            BotMessage::Info { name, version, .. } => bot.name = format!("{} {}", name, version),
+            message => return Err(unexpected(&message)),
+        }
+        bot.send(&FrontendMessage::Rules {
+            width: MAP_WIDTH,
+            height: MAP_HEIGHT,
+        })?;
+        match bot.recv()? {
+            BotMessage::Ready => Ok(bot),
+            message => Err(unexpected(&message)),
+        }
+    }
+
+    fn send(&mut self, message: &FrontendMessage) -> Result<(), TbpError> {
+        let line = serde_json::to_string(message).expect("Messages are always serializable");
+        writeln!(self.stdin, "{}", line)?;
+        self.stdin.flush()?;
+        Ok(())
+    }
+
+    fn recv(&mut self) -> Result<BotMessage, TbpError> {
+        let line = match self.incoming.recv_timeout(TIMEOUT) {
+            Ok(line) => line,
+            Err(RecvTimeoutError::Timeout) => return Err(TbpError::Timeout),
+            Err(RecvTimeoutError::Disconnected) => return Err(TbpError::Disconnected),
+        };
+        match serde_json::from_str(&line) {
+            Ok(BotMessage::Error { reason }) => Err(TbpError::Bot(reason)),
+            Ok(message) => Ok(message),
+            Err(_) => Err(TbpError::BadMessage(line)),
+        }
+    }
+
+    /// Asks the bot where to put the current piece and returns the actions
+    /// that bring it there. Only moves reachable by a player are accepted.
+    pub fn next_move(&mut self, tetris: &Tetris) -> Result<Vec<Action>, TbpError> {
+        if !self.knows(&tetris.pieces) {
+            return Err(TbpError::Pieces);
+        }
+        if tetris.current_piece.is_none() {
+            return Ok(Vec::new());
+        }
+        for message in catch_up(self.expected.as_ref(), tetris) {
+            self.send(&message)?;
+        }
+
+        self.send(&FrontendMessage::Suggest)?;
+        let moves = match self.recv()? {
+            BotMessage::Suggestion { moves } => moves,
+            message => return Err(unexpected(&message)),
+        };
+        let placements = bot::placements(tetris);
+        let (mv, placement) = moves
+            .into_iter()
+            .find_map(|mv| {
+                placements
+                    .iter()
+                    .find(|placement| {
+                        placement_location(tetris, placement).as_ref() == Some(&mv.location)
+                    })
+                    .map(|placement| (mv, placement.clone()))
+            })
+            .ok_or(TbpError::NoValidMove)?;
+
+        let mut piece = tetris
+            .pieces
+            .piece(piece_kind(mv.location.piece).unwrap_or(1));
+        piece.current_state = placement.current_state;
+        piece.x = placement.x;
+        piece.y = placement.y;
+        let game_map = bot::lock_piece(&tetris.game_map, &piece).0;
+        self.expected = Some(Expected::after(tetris, &placement, game_map));
+        self.send(&FrontendMessage::Play { mv })?;
+        Ok(placement.actions)
+    }
+
+    /// Whether the bot can play with `pieces`, which only the built-in ones
+    /// are.
+    pub fn knows(&self, pieces: &PieceSet) -> bool {
+        *pieces == self.builtin
+    }
+
+    /// Same as `Bot::play`, with the external bot choosing the moves.
+    pub fn play(&mut self, tetris: &mut Tetris, max_pieces: u32) -> Result<u32, TbpError> {
+        let mut nb_pieces = 0;
+        tetris.skip_delays();
+        while nb_pieces < max_pieces && tetris.spawn() {
+            for action in self.next_move(tetris)? {
+                tetris.apply_action(action);
+            }
+            tetris.skip_delays();
+            nb_pieces += 1;
+        }
+        Ok(nb_pieces)
+    }
+}
+
+impl Drop for ExternalBot {
+    fn drop(&mut self) {
+        let _ = self.send(&FrontendMessage::Quit);
+        for _ in 0..10 {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn unexpected(message: &BotMessage) -> TbpError {
+    TbpError::BadMessage(serde_json::to_string(message).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::Value;
+
+    /// Checks that `line` reads as `T` and is written back the same.
+    fn round_trip<T: Serialize + for<'de> Deserialize<'de>>(line: &str) -> T {
+        let message: T = serde_json::from_str(line).unwrap();
+        let written = serde_json::to_string(&message).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(&written).unwrap(),
+            serde_json::from_str::<Value>(line).unwrap(),
+        );
+        message
+    }
+
+    #[test]
+    fn frontend_messages_follow_the_protocol() {
+        for line in [
+            r#"{"type": "rules", "width": 10, "height": 16}"#,
+            r#"{"type": "start", "hold": "S", "queue": ["T"], "board": [[null, "G"], ["I", null]]}"#,
+            r#"{"type": "start", "hold": null, "queue": [], "board": []}"#,
+            r#"{"type": "new_piece", "piece": "O"}"#,
+            r#"{"type": "suggest"}"#,
+            r#"{"type": "stop"}"#,
+            r#"{"type": "quit"}"#,
+        ] {
+            round_trip::<FrontendMessage>(line);
+        }
+        let line = r#"{"type": "play", "move": {"location": {"type": "T", "orientation": 2, "x": 3, "y": 14}}}"#;
+        match round_trip::<FrontendMessage>(line) {
+            FrontendMessage::Play { mv } => assert_eq!(
+                mv.location,
+                PieceLocation {
+                    piece: 'T',
+                    orientation: 2,
+                    x: 3,
+                    y: 14,
+                }
+            ),
+            message => panic!("{:?}", message),
+        }
+    }
+
+    #[test]
+    fn bot_messages_follow_the_protocol() {
+        for line in [
+            r#"{"type": "info", "name": "bot", "version": "1.0", "author": "me", "features": []}"#,
+            r#"{"type": "ready"}"#,
+            r#"{"type": "error", "reason": "unsupported rules"}"#,
+            r#"{"type": "suggestion", "moves": [{"location": {"type": "I", "orientation": 1, "x": -1, "y": 12}}]}"#,
+        ] {
+            round_trip::<BotMessage>(line);
+        }
+        // Features are optional.
+        let line = r#"{"type": "info", "name": "bot", "version": "1.0", "author": "me"}"#;
+        match serde_json::from_str(line).unwrap() {
+            BotMessage::Info { features, .. } => assert!(features.is_empty()),
+            message => panic!("{:?}", message),
+        }
+        assert!(serde_json::from_str::<BotMessage>(r#"{"type": "nope"}"#).is_err());
+    }
+
+    #[test]
+    fn the_queue_is_told_then_the_revealed_pieces() {
+        let mut tetris = Tetris::with_seed(3);
+        assert!(tetris.spawn());
+        let names = |kinds: &[u8]| {
+            kinds
+                .iter()
+                .map(|kind| piece_name(*kind))
+                .collect::<Vec<_>>()
+        };
+        let expected_queue = names(&queue(&tetris));
+        assert_eq!(expected_queue.len(), tetris.next_pieces.len() + 1);
+        match catch_up(None, &tetris).as_slice() {
+            [FrontendMessage::Start {
+                queue, hold: None, ..
+            }] => {
+                assert_eq!(*queue, expected_queue)
+            }
+            messages => panic!("{:?}", messages),
+        }
+
+        // Once a piece is played, only the one revealed at the end of the
+        // preview is new.
+        let placement = bot::placements(&tetris)
+            .into_iter()
+            .find(|placement| !placement.hold)
+            .unwrap();
+        let mut played = Tetris::from_state(tetris.state()).unwrap();
+        for action in &placement.actions {
+            played.apply_action(*action);
+        }
+        played.skip_delays();
+        assert!(played.spawn());
+        let expected = Expected::after(&tetris, &placement, played.game_map.clone());
+        let revealed = piece_name(*played.next_pieces.back().unwrap());
+        match catch_up(Some(&expected), &played).as_slice() {
+            [FrontendMessage::NewPiece { piece }] => assert_eq!(*piece, revealed),
+            messages => panic!("{:?}", messages),
+        }
+
+        // Garbage the bot doesn't know about starts the game again.
+        played.add_garbage(1, 0);
+        match catch_up(Some(&expected), &played).as_slice() {
+            [FrontendMessage::Stop, FrontendMessage::Start { queue, .. }] => {
+                assert_eq!(*queue, names(&super::queue(&played)))
+            }
+            messages => panic!("{:?}", messages),
+        }
+    }
+
+    #[test]
+    fn boards_are_rows_from_the_top() {
+        let mut tetris = Tetris::with_seed(1);
+        let t = tetris.pieces.kind("T").unwrap();
+        tetris.game_map[MAP_HEIGHT - 1] = vec![GARBAGE; MAP_WIDTH];
+        tetris.game_map[MAP_HEIGHT - 1][4] = 0;
+        tetris.game_map[MAP_HEIGHT - 2][0] = t;
+        let board = board(&tetris.game_map);
+        assert_eq!(board.len(), MAP_HEIGHT);
+        assert!(board[0].iter().all(|case| case.is_none()));
+        assert_eq!(board[MAP_HEIGHT - 2][..2], [Some('T'), None]);
+        assert_eq!(board[MAP_HEIGHT - 1][3..6], [Some('G'), None, Some('G')]);
+    }
+
+    #[test]
+    fn locations_are_where_the_placements_lock() {
+        let mut tetris = Tetris::with_seed(1);
+        let (i, t) = (
+            tetris.pieces.kind("I").unwrap(),
+            tetris.pieces.kind("T").unwrap(),
+        );
+        tetris.game_map[MAP_HEIGHT - 1] = vec![GARBAGE; MAP_WIDTH];
+        tetris.game_map[MAP_HEIGHT - 1][7] = 0;
+        tetris.current_piece = Some(tetris.pieces.piece(t));
+        tetris.hold_piece = Some(i);
+        let placements = bot::placements(&tetris);
+        assert!(placements.iter().any(|placement| placement.hold));
+        // Dropped from its spawn, the T's box is at the column it spawns at,
+        // its top row two rows above the garbage.
+        let dropped = placements
+            .iter()
+            .find(|placement| !placement.hold && placement.actions == [Action::HardDrop])
+            .unwrap();
+        assert_eq!(
+            placement_location(&tetris, dropped),
+            Some(PieceLocation {
+                piece: 'T',
+                orientation: 0,
+                x: 4,
+                y: MAP_HEIGHT - 3,
+            })
+        );
+        for placement in placements {
+            let location = placement_location(&tetris, &placement).unwrap();
+            assert_eq!(location.piece, if placement.hold { 'I' } else { 'T' });
+            // The piece locked at the location is the piece the actions
+            // lock.
+            let mut piece = tetris.pieces.piece(piece_kind(location.piece).unwrap());
+            piece.current_state = location.orientation;
+            piece.x = location.x;
+            piece.y = location.y;
+            let mut played = Tetris::from_state(tetris.state()).unwrap();
+            for action in &placement.actions {
+                played.apply_action(*action);
+            }
+            assert_eq!(bot::lock_piece(&tetris.game_map, &piece).0, played.game_map);
+        }
+    }
+}
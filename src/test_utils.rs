@@ -0,0 +1,15 @@
+//! Fixtures shared by the tests of the modules.
+
+use crate::game::{GameState, Tetris, MAP_HEIGHT, MAP_WIDTH};
+
+pub fn empty_map() -> Vec<Vec<u8>> {
+    vec![vec![0; MAP_WIDTH]; MAP_HEIGHT]
+}
+
+/// The state of a game, but for the inputs played, which undoing doesn't
+/// take back.
+pub fn played(tetris: &Tetris) -> GameState {
+    let mut state = tetris.state();
+    state.statistics.inputs = 0;
+    state
+}
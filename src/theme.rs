@@ -0,0 +1,209 @@
+use crate::game::GARBAGE;
+use crate::pieces::PieceSet;
+
+use std::fs::File;
+use std::io::Read;
+
+pub const THEME_FILE: &str = "theme.txt";
+
+pub type Rgb = (u8, u8, u8);
+
+/// A bright colour for the piece `nb`, with hues a golden angle apart so
+/// that neighbours differ.
+fn spread_colour(nb: usize) -> Rgb {
+    let hue = (nb as f64 * 137.508) % 360. / 60.;
+    let (saturation, value) = (0.65, 0.95);
+    let chroma = value * saturation;
+    let second = chroma * (1. - (hue % 2. - 1.).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, second, 0.),
+        1 => (second, chroma, 0.),
+        2 => (0., chroma, second),
+        3 => (0., second, chroma),
+        4 => (second, 0., chroma),
+        _ => (chroma, 0., second),
+    };
+    let component = |c: f64| ((c + value - chroma) * 255.).round() as u8;
+    (component(r), component(g), component(b))
+}
+
+/// Every colour the game is drawn with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Theme {
+    pub name: String,
+    pub background: Rgb,
+    pub border: Rgb,
+    pub grid: Rgb,
+    /// Piece colours by kind, see `PieceSet::piece`.
+    pub pieces: Vec<Rgb>,
+    pub garbage: Rgb,
+    pub ghost: Rgb,
+    pub text: Rgb,
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme::classic()
+    }
+}
+
+impl Theme {
+    pub fn classic() -> Theme {
+        Theme {
+            name: "classic".to_owned(),
+            background: (255, 0, 0),
+            border: (255, 255, 255),
+            grid: (0, 0, 0),
+            pieces: vec![
+                (255, 69, 69),
+                (255, 220, 69),
+                (237, 150, 37),
+                (171, 99, 237),
+                (77, 149, 239),
+                (39, 218, 225),
+                (45, 216, 47),
+            ],
+            garbage: (128, 128, 128),
+            ghost: (70, 70, 70),
+            text: (255, 255, 255),
+        }
+    }
+
+    pub fn dark() -> Theme {
+        Theme {
+            name: "dark".to_owned(),
+            background: (24, 24, 32),
+            border: (90, 90, 110),
+            grid: (0, 0, 0),
+            ghost: (55, 55, 65),
+            text: (220, 220, 220),
+            ..Theme::classic()
+        }
+    }
+
+    /// Saturated pieces on black, for low vision.
+    pub fn high_contrast() -> Theme {
+        Theme {
+            name: "high-contrast".to_owned(),
+            background: (0, 0, 0),
+            border: (255, 255, 255),
+            grid: (0, 0, 0),
+            pieces: vec![
+                (0, 255, 255),
+                (90, 130, 255),
+                (255, 150, 0),
+                (255, 255, 0),
+                (0, 255, 0),
+                (255, 0, 0),
+                (255, 0, 255),
+            ],
+            garbage: (200, 200, 200),
+            ghost: (110, 110, 110),
+            text: (255, 255, 255),
+        }
+    }
+
+    /// The Okabe-Ito palette, which stays distinguishable with the common
+    /// forms of colour blindness.
+    pub fn colour_blind() -> Theme {
+        Theme {
+            name: "colour-blind".to_owned(),
+            background: (30, 30, 30),
+            border: (200, 200, 200),
+            grid: (0, 0, 0),
+            pieces: vec![
+                (86, 180, 233),
+                (0, 114, 178),
+                (230, 159, 0),
+                (240, 228, 66),
+                (0, 158, 115),
+                (213, 94, 0),
+                (204, 121, 167),
+            ],
+            garbage: (153, 153, 153),
+            ghost: (70, 70, 70),
+            text: (255, 255, 255),
+        }
+    }
+
+    pub fn builtins() -> Vec<Theme> {
+        vec![
+            Theme::classic(),
+            Theme::dark(),
+            Theme::high_contrast(),
+            Theme::colour_blind(),
+        ]
+    }
+
+    /// The theme with the colours given by the pieces of `pieces`. Pieces
+    /// after the ones of the theme get colours of their own.
+    pub fn with_piece_colours(mut self, pieces: &PieceSet) -> Theme {
+        for (nb, piece) in pieces.pieces.iter().enumerate() {
+            if nb >= self.pieces.len() {
+                self.pieces.push(spread_colour(nb));
+            }
+            if let Some(colour) = piece.colour {
+                self.pieces[nb] = colour;
+            }
+        }
+        self
+    }
+
+    /// Colour of a case of the game map, `None` for an empty one.
+    pub fn block(&self, kind: u8) -> Option<Rgb> {
+        match kind {
+            0 => None,
+            GARBAGE => Some(self.garbage),
+            kind => Some(
+                self.pieces
+                    .get(kind as usize - 1)
+                    .cloned()
+                    .unwrap_or(self.garbage),
+            ),
+        }
+    }
+
+    /// Parses one `name r g b` entry per line, e.g. `background 0 0 0`, on
+    /// top of the classic theme. Pieces are named `I J L O S Z T`, and the
+    /// theme itself with a `name` entry.
+    pub fn parse(content: &str) -> Option<Theme> {
+        let mut theme = Theme {
+            name: "custom".to_owned(),
+            ..Theme::classic()
+        };
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let mut words = line.split_whitespace();
+            let name = words.next()?;
+            if name == "name" {
+                theme.name = words.collect::<Vec<_>>().join(" ");
+                continue;
+            }
+            let mut component = || words.next()?.parse::<u8>().ok();
+            let color = (component()?, component()?, component()?);
+            let entry = match name {
+                "background" => &mut theme.background,
+                "border" => &mut theme.border,
+                "grid" => &mut theme.grid,
+                "garbage" => &mut theme.garbage,
+                "ghost" => &mut theme.ghost,
+                "text" => &mut theme.text,
+                piece => {
+                    let kind = crate::tbp::piece_kind(piece.chars().next()?)?;
+                    if piece.len() != 1 {
+                        return None;
+                    }
+                    theme.pieces.get_mut(kind as usize - 1)?
+                }
+            };
+            *entry = color;
+        }
+        Some(theme)
+    }
+
+    pub fn load(file_name: &str) -> Option<Theme> {
+        let mut f = File::open(file_name).ok()?;
+        let mut content = String::new();
+        f.read_to_string(&mut content).ok()?;
+        Theme::parse(&content)
+    }
+}
@@ -0,0 +1,210 @@
+use crate::game::Tetris;
+
+use std::fs::File;
+use std::io::Read;
+
+pub const ATTACK_TABLE_FILE: &str = "attack.txt";
+const DEFAULT_ATTACK_TABLE: [u32; 5] = [0, 0, 1, 2, 4];
+
+/// Number of garbage lines sent to the opponent, indexed by the number of
+/// lines cleared at once.
+pub struct AttackTable {
+    lines: Vec<u32>,
+}
+
+impl Default for AttackTable {
+    fn default() -> AttackTable {
+        AttackTable {
+            lines: DEFAULT_ATTACK_TABLE.to_vec(),
+        }
+    }
+}
+
+impl AttackTable {
+    /// Parses a space-separated list of attack values, such as `0 0 1 2 4`.
+    pub fn parse(content: &str) -> Option<AttackTable> {
+        let lines = content
+            .split_whitespace()
+            .map(|nb| nb.parse::<u32>().ok())
+            .collect::<Option<Vec<u32>>>()?;
+        AttackTable::from_lines(lines)
+    }
+
+    pub fn from_lines(lines: Vec<u32>) -> Option<AttackTable> {
+        if lines.is_empty() {
+            None
+        } else {
+            Some(AttackTable { lines })
+        }
+    }
+
+    pub fn lines(&self) -> &[u32] {
+        &self.lines
+    }
+
+    pub fn load(file_name: &str) -> Option<AttackTable> {
+        let mut f = File::open(file_name).ok()?;
+        let mut content = String::new();
+        f.read_to_string(&mut content).ok()?;
+        AttackTable::parse(&content)
+    }
+
+    /// Clears bigger than the table send as much as its last entry.
+    pub fn attack(&self, cleared: u32) -> u32 {
+        match self.lines.get(cleared as usize) {
+            Some(lines) => *lines,
+            None => *self.lines.last().unwrap_or(&0),
+        }
+    }
+}
+
+/// Garbage received by a player that hasn't entered their map yet.
+#[derive(Default)]
+pub struct GarbageMeter {
+    pending: Vec<u32>,
+}
+
+impl GarbageMeter {
+    pub fn receive(&mut self, nb_lines: u32) {
+        if nb_lines > 0 {
+            self.pending.push(nb_lines);
+        }
+    }
+
+    /// Cancels pending garbage with an attack, oldest first. Returns what is
+    /// left of the attack once the meter is empty.
+    pub fn counter(&mut self, mut attack: u32) -> u32 {
+        while attack > 0 && !self.pending.is_empty() {
+            if self.pending[0] > attack {
+                self.pending[0] -= attack;
+                attack = 0;
+            } else {
+                attack -= self.pending.remove(0);
+            }
+        }
+        attack
+    }
+
+    pub fn total(&self) -> u32 {
+        self.pending.iter().sum()
+    }
+
+    pub fn take(&mut self) -> Vec<u32> {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+pub struct LockOutcome {
+    /// Garbage lines sent to the opponents.
+    pub sent: u32,
+    /// Garbage lines that entered the player's map.
+    pub received: u32,
+    /// `false` if the incoming garbage pushed the player out of its map.
+    pub alive: bool,
+}
+
+/// Garbage exchange between several games.
+pub struct Versus {
+    pub attack_table: AttackTable,
+    pub meters: Vec<GarbageMeter>,
+}
+
+impl Versus {
+    pub fn new(nb_players: usize, attack_table: AttackTable) -> Versus {
+        Versus {
+            attack_table,
+            meters: (0..nb_players).map(|_| GarbageMeter::default()).collect(),
+        }
+    }
+
+    /// Must be called every time `player` locks a piece. Its attack first
+    /// cancels its own pending garbage, the rest goes to every opponent. If
+    /// nothing was cleared, the pending garbage enters its map instead.
+    pub fn on_lock(&mut self, tetris: &mut Tetris, player: usize, cleared: u32) -> LockOutcome {
+        let mut outcome = LockOutcome {
+            sent: 0,
+            received: 0,
+            alive: true,
+        };
+        if cleared > 0 {
+            let attack = self.attack_table.attack(cleared);
+            tetris.statistics.attack += attack;
+            outcome.sent = self.meters[player].counter(attack);
+            for (opponent, meter) in self.meters.iter_mut().enumerate() {
+                if opponent != player {
+                    meter.receive(outcome.sent);
+                }
+            }
+        } else {
+            for nb_lines in self.meters[player].take() {
+                let hole = tetris.garbage_hole();
+                outcome.received += nb_lines;
+                outcome.alive &= tetris.add_garbage(nb_lines, hole);
+            }
+        }
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{GARBAGE, MAP_HEIGHT};
+
+    #[test]
+    fn counters_cancel_the_oldest_garbage_first() {
+        let mut meter = GarbageMeter::default();
+        meter.receive(2);
+        meter.receive(3);
+        // Partly cancels the oldest attack.
+        assert_eq!(meter.counter(1), 0);
+        assert_eq!(meter.pending, vec![1, 3]);
+        // Exactly cancels it.
+        assert_eq!(meter.counter(1), 0);
+        assert_eq!(meter.pending, vec![3]);
+        // Cancels everything, and the rest is sent.
+        assert_eq!(meter.counter(5), 2);
+        assert_eq!(meter.total(), 0);
+    }
+
+    #[test]
+    fn attacks_follow_the_table() {
+        let table = AttackTable::default();
+        let attacks = (0..=4)
+            .map(|cleared| table.attack(cleared))
+            .collect::<Vec<_>>();
+        assert_eq!(attacks, vec![0, 0, 1, 2, 4]);
+        // Clears bigger than the table, with polyominoes.
+        assert_eq!(table.attack(5), 4);
+
+        let table = AttackTable::parse("0 1 2").unwrap();
+        assert_eq!(table.attack(1), 1);
+        assert_eq!(table.attack(4), 2);
+        assert!(AttackTable::parse("").is_none());
+        assert!(AttackTable::parse("0 one").is_none());
+    }
+
+    #[test]
+    fn clears_send_garbage_to_the_opponent() {
+        let mut versus = Versus::new(2, AttackTable::default());
+        let mut players = [Tetris::with_seed(1), Tetris::with_seed(2)];
+
+        let outcome = versus.on_lock(&mut players[0], 0, 4);
+        assert_eq!(outcome.sent, 4);
+        assert_eq!(players[0].statistics.attack, 4);
+        assert_eq!(versus.meters[1].total(), 4);
+
+        // Countered in part by a double, the rest enters on the next lock.
+        assert_eq!(versus.on_lock(&mut players[1], 1, 2).sent, 0);
+        assert_eq!(versus.meters[0].total(), 0);
+        let outcome = versus.on_lock(&mut players[1], 1, 0);
+        assert_eq!((outcome.received, outcome.alive), (3, true));
+        assert_eq!(versus.meters[1].total(), 0);
+        for line in &players[1].game_map[MAP_HEIGHT - 3..] {
+            assert_eq!(
+                line.iter().filter(|case| **case == GARBAGE).count(),
+                line.len() - 1
+            );
+        }
+    }
+}